@@ -1,8 +1,12 @@
 //! Loggin facilities
 
+#[cfg(feature = "syslog")]
+use std::net::SocketAddr;
 use std::path::Path;
 
 use clap::ArgMatches;
+#[cfg(feature = "syslog")]
+use clap::{value_t, Error as ClapError, ErrorKind as ClapErrorKind};
 use log::LevelFilter;
 use log4rs::{
     append::console::{ConsoleAppender, Target},
@@ -10,6 +14,9 @@ use log4rs::{
     encode::pattern::PatternEncoder,
 };
 
+#[cfg(feature = "syslog")]
+mod syslog;
+
 pub fn init_with_file<P>(path: P)
 where
     P: AsRef<Path>,
@@ -31,40 +38,93 @@ pub fn init_with_config(bin_name: &str, matches: &ArgMatches) {
     }
     pattern += "{m}{n}";
 
-    let logging_builder = Config::builder().appender(
-        Appender::builder().build(
-            "console",
-            Box::new(
-                ConsoleAppender::builder()
-                    .encoder(Box::new(PatternEncoder::new(&pattern)))
-                    .target(Target::Stderr)
-                    .build(),
+    let mut logging_builder = Config::builder();
+    let mut appenders = Vec::new();
+
+    let (log_to_stderr, log_to_syslog) = match matches.values_of("LOG_TARGET") {
+        Some(targets) => {
+            let targets = targets.collect::<Vec<_>>();
+            (targets.contains(&"stderr"), targets.contains(&"syslog"))
+        }
+        None => (true, false),
+    };
+
+    if log_to_stderr {
+        logging_builder = logging_builder.appender(
+            Appender::builder().build(
+                "console",
+                Box::new(
+                    ConsoleAppender::builder()
+                        .encoder(Box::new(PatternEncoder::new(&pattern)))
+                        .target(Target::Stderr)
+                        .build(),
+                ),
             ),
-        ),
-    );
+        );
+        appenders.push("console");
+    }
+
+    #[cfg(feature = "syslog")]
+    if log_to_syslog {
+        use self::syslog::SyslogAppender;
+
+        // Syslog daemon adds timestamp and level by itself, and messages shouldn't contain colors and newlines
+        let mut pattern = String::new();
+        if debug_level >= 1 {
+            pattern += "[{P}:{I}] [{M}] ";
+        }
+        pattern += "{m}";
+        let encoder = Box::new(PatternEncoder::new(&pattern));
+
+        // SYSLOG_ADDR is checked by validator, and is required on platforms without /dev/log
+        let appender = match matches.value_of("SYSLOG_ADDR") {
+            Some(..) => match value_t!(matches, "SYSLOG_ADDR", SocketAddr) {
+                Ok(addr) => SyslogAppender::remote(addr, bin_name, encoder),
+                Err(err) => err.exit(),
+            },
+            #[cfg(unix)]
+            None => SyslogAppender::local(bin_name, encoder),
+            #[cfg(not(unix))]
+            None => ClapError::argument_not_found_auto("syslog-addr").exit(),
+        };
+
+        let appender = match appender {
+            Ok(a) => a,
+            Err(err) => {
+                ClapError::with_description(&format!("failed to open syslog, {}", err), ClapErrorKind::Io).exit()
+            }
+        };
+
+        logging_builder = logging_builder.appender(Appender::builder().build("syslog", Box::new(appender)));
+        appenders.push("syslog");
+    }
+    #[cfg(not(feature = "syslog"))]
+    let _ = log_to_syslog;
+
+    let root = |level: LevelFilter| Root::builder().appenders(appenders.iter().cloned()).build(level);
 
     let config = match debug_level {
         0 => logging_builder
             .logger(Logger::builder().build(bin_name, LevelFilter::Info))
             .logger(Logger::builder().build("shadowsocks", LevelFilter::Info))
             .logger(Logger::builder().build("shadowsocks_service", LevelFilter::Info))
-            .build(Root::builder().appender("console").build(LevelFilter::Off)),
+            .build(root(LevelFilter::Off)),
         1 => logging_builder
             .logger(Logger::builder().build(bin_name, LevelFilter::Debug))
             .logger(Logger::builder().build("shadowsocks", LevelFilter::Debug))
             .logger(Logger::builder().build("shadowsocks_service", LevelFilter::Debug))
-            .build(Root::builder().appender("console").build(LevelFilter::Off)),
+            .build(root(LevelFilter::Off)),
         2 => logging_builder
             .logger(Logger::builder().build(bin_name, LevelFilter::Trace))
             .logger(Logger::builder().build("shadowsocks", LevelFilter::Trace))
             .logger(Logger::builder().build("shadowsocks_service", LevelFilter::Trace))
-            .build(Root::builder().appender("console").build(LevelFilter::Off)),
+            .build(root(LevelFilter::Off)),
         3 => logging_builder
             .logger(Logger::builder().build(bin_name, LevelFilter::Trace))
             .logger(Logger::builder().build("shadowsocks", LevelFilter::Trace))
             .logger(Logger::builder().build("shadowsocks_service", LevelFilter::Trace))
-            .build(Root::builder().appender("console").build(LevelFilter::Debug)),
-        _ => logging_builder.build(Root::builder().appender("console").build(LevelFilter::Trace)),
+            .build(root(LevelFilter::Debug)),
+        _ => logging_builder.build(root(LevelFilter::Trace)),
     }
     .expect("logging");
 
@@ -0,0 +1,119 @@
+//! Syslog appender for log4rs
+//!
+//! Messages are sent in [RFC 3164](https://tools.ietf.org/html/rfc3164) format without timestamp and hostname,
+//! which will be filled by the syslog daemon when receiving.
+
+use std::{
+    fmt,
+    io::{self, Write},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    process,
+};
+#[cfg(unix)]
+use std::{os::unix::net::UnixDatagram, path::Path};
+
+use log::{Level, Record};
+use log4rs::{
+    append::Append,
+    encode::{writer::simple::SimpleWriter, Encode},
+};
+
+/// Path of the local syslog daemon's socket
+#[cfg(unix)]
+const SYSLOG_LOCAL_PATH: &str = "/dev/log";
+
+/// Facility `daemon`
+const SYSLOG_FACILITY_DAEMON: u8 = 3;
+
+enum SyslogSocket {
+    #[cfg(unix)]
+    Unix(UnixDatagram),
+    Udp(UdpSocket),
+}
+
+impl SyslogSocket {
+    fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            #[cfg(unix)]
+            SyslogSocket::Unix(ref s) => s.send(buf),
+            SyslogSocket::Udp(ref s) => s.send(buf),
+        }
+    }
+}
+
+/// Appender that sends logs to syslog daemon
+pub struct SyslogAppender {
+    socket: SyslogSocket,
+    tag: String,
+    encoder: Box<dyn Encode>,
+}
+
+impl SyslogAppender {
+    /// Send logs to the local syslog daemon via `/dev/log`
+    #[cfg(unix)]
+    pub fn local(tag: &str, encoder: Box<dyn Encode>) -> io::Result<SyslogAppender> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(Path::new(SYSLOG_LOCAL_PATH))?;
+
+        Ok(SyslogAppender {
+            socket: SyslogSocket::Unix(socket),
+            tag: tag.to_owned(),
+            encoder,
+        })
+    }
+
+    /// Send logs to the remote syslog server `addr` via UDP
+    pub fn remote(addr: SocketAddr, tag: &str, encoder: Box<dyn Encode>) -> io::Result<SyslogAppender> {
+        let bind_addr = match addr {
+            SocketAddr::V4(..) => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+            SocketAddr::V6(..) => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0),
+        };
+
+        let socket = UdpSocket::bind(bind_addr)?;
+        socket.connect(addr)?;
+
+        Ok(SyslogAppender {
+            socket: SyslogSocket::Udp(socket),
+            tag: tag.to_owned(),
+            encoder,
+        })
+    }
+}
+
+impl fmt::Debug for SyslogAppender {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SyslogAppender")
+            .field("tag", &self.tag)
+            .field("encoder", &self.encoder)
+            .finish()
+    }
+}
+
+impl Append for SyslogAppender {
+    fn append(&self, record: &Record) -> anyhow::Result<()> {
+        let mut writer = SimpleWriter(Vec::new());
+        write!(
+            writer,
+            "<{}>{}[{}]: ",
+            SYSLOG_FACILITY_DAEMON * 8 + severity(record.level()),
+            self.tag,
+            process::id()
+        )?;
+        self.encoder.encode(&mut writer, record)?;
+
+        self.socket.send(&writer.0)?;
+        Ok(())
+    }
+
+    fn flush(&self) {}
+}
+
+/// Syslog severity of log `level`
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
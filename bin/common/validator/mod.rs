@@ -26,11 +26,14 @@ validate_type!(
 );
 validate_type!(validate_socket_addr, SocketAddr, "should be ip:port");
 validate_type!(validate_address, Address, "should be either ip:port or domain:port");
-validate_type!(
-    validate_manager_addr,
-    ManagerAddr,
-    "should be either ip:port, domain:port or /path/to/unix.sock"
-);
+
+pub fn validate_manager_addr(v: String) -> Result<(), String> {
+    match v.parse::<ManagerAddr>() {
+        Ok(..) => Ok(()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
 #[cfg(feature = "local-dns")]
 validate_type!(
     validate_name_server_addr,
@@ -40,6 +43,14 @@ validate_type!(
 validate_type!(validate_u64, u64, "should be unsigned integer");
 validate_type!(validate_u32, u32, "should be unsigned integer");
 validate_type!(validate_usize, usize, "should be unsigned integer");
+validate_type!(validate_i32, i32, "should be integer");
+
+pub fn validate_dscp(v: String) -> Result<(), String> {
+    match v.parse::<u8>() {
+        Ok(dscp) if dscp <= 63 => Ok(()),
+        _ => Err("should be an unsigned integer in range 0 - 63".to_owned()),
+    }
+}
 
 pub fn validate_server_url(v: String) -> Result<(), String> {
     match ServerConfig::from_url(&v) {
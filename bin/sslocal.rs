@@ -46,6 +46,7 @@ fn main() {
         (@arg CONFIG: -c --config +takes_value required_unless_all(&["LOCAL_ADDR", "SERVER_CONFIG"]) "Shadowsocks configuration file (https://shadowsocks.org/en/config/quick-guide.html)")
 
         (@arg LOCAL_ADDR: -b --("local-addr") +takes_value {validator::validate_server_addr} "Local address, listen only to this address if specified")
+        (@arg LOCAL_ADDR_FALLBACK: --("local-addr-fallback") +takes_value +multiple number_of_values(1) {validator::validate_server_addr} "Alternative local address if local-addr couldn't be bound, could be specified multiple times and tried in order")
 
         (@arg SERVER_ADDR: -s --("server-addr") +takes_value {validator::validate_server_addr} requires[PASSWORD ENCRYPT_METHOD] "Server address")
         (@arg PASSWORD: -k --password +takes_value requires[SERVER_ADDR] "Server's password")
@@ -63,16 +64,20 @@ fn main() {
         (@arg PROTOCOL: --protocol +takes_value default_value("socks") possible_values(ProtocolType::available_protocols()) +next_line_help "Protocol that for communicating with clients")
 
         (@arg NO_DELAY: --("no-delay") !takes_value "Set TCP_NODELAY option for socket")
+        (@arg STRICT: --strict !takes_value "Refuse to start with insecure configurations, such as stream ciphers and short passwords")
         (@arg NOFILE: -n --nofile +takes_value "Set RLIMIT_NOFILE with both soft and hard limit (only for *nix systems)")
         (@arg ACL: --acl +takes_value "Path to ACL (Access Control List)")
 
+        (@arg TCP_TIMEOUT: --("tcp-timeout") +takes_value {validator::validate_u64} "Timeout seconds for TCP relay of servers without their own timeout")
         (@arg UDP_TIMEOUT: --("udp-timeout") +takes_value {validator::validate_u64} "Timeout seconds for UDP relay")
         (@arg UDP_MAX_ASSOCIATIONS: --("udp-max-associations") +takes_value {validator::validate_u64} "Maximum associations to be kept simultaneously for UDP relay")
+        (@arg MAX_CONNECTION_LIFETIME: --("max-connection-lifetime") +takes_value {validator::validate_u64} "Close TCP connections after they have been relayed for this many seconds, regardless of activity")
 
         (@arg UDP_BIND_ADDR: --("udp-bind-addr") +takes_value {validator::validate_server_addr} "UDP relay's bind address, default is the same as local-addr")
 
         (@arg INBOUND_SEND_BUFFER_SIZE: --("inbound-send-buffer-size") +takes_value {validator::validate_u32} "Set inbound sockets' SO_SNDBUF option")
         (@arg INBOUND_RECV_BUFFER_SIZE: --("inbound-recv-buffer-size") +takes_value {validator::validate_u32} "Set inbound sockets' SO_RCVBUF option")
+        (@arg LISTEN_BACKLOG: --("listen-backlog") +takes_value {validator::validate_i32} "Set TCP listeners' backlog, capped by the system (net.core.somaxconn on Linux)")
         (@arg OUTBOUND_SEND_BUFFER_SIZE: --("outbound-send-buffer-size") +takes_value {validator::validate_u32} "Set outbound sockets' SO_SNDBUF option")
         (@arg OUTBOUND_RECV_BUFFER_SIZE: --("outbound-recv-buffer-size") +takes_value {validator::validate_u32} "Set outbound sockets' SO_RCVBUF option")
     );
@@ -93,6 +98,26 @@ fn main() {
         );
     }
 
+    #[cfg(feature = "syslog")]
+    {
+        app = clap_app!(@app (app)
+            (@arg LOG_TARGET: --("log-target") +takes_value +multiple number_of_values(1) possible_values(&["stderr", "syslog"]) "Log output targets, could be specified multiple times, stderr by default")
+        );
+
+        #[cfg(unix)]
+        {
+            app = clap_app!(@app (app)
+                (@arg SYSLOG_ADDR: --("syslog-addr") +takes_value {validator::validate_socket_addr} "Send logs to the remote syslog server via UDP, instead of local /dev/log")
+            );
+        }
+        #[cfg(not(unix))]
+        {
+            app = clap_app!(@app (app)
+                (@arg SYSLOG_ADDR: --("syslog-addr") +takes_value required_if("LOG_TARGET", "syslog") {validator::validate_socket_addr} "Send logs to the remote syslog server via UDP, required for logging to syslog")
+            );
+        }
+    }
+
     #[cfg(feature = "local-tunnel")]
     {
         app = clap_app!(@app (app)
@@ -163,6 +188,13 @@ fn main() {
         );
     }
 
+    #[cfg(unix)]
+    {
+        app = clap_app!(@app (app)
+            (@arg OUTBOUND_DSCP: --dscp +takes_value {validator::validate_dscp} "Set DSCP (0 - 63) with IP_TOS / IPV6_TCLASS option for outbound socket")
+        );
+    }
+
     #[cfg(feature = "multi-threaded")]
     {
         app = clap_app!(@app (app)
@@ -285,6 +317,12 @@ fn main() {
         config.local_addr = Some(local_addr);
     }
 
+    if let Some(fallbacks) = matches.values_of("LOCAL_ADDR_FALLBACK") {
+        config.local_addr_fallback = fallbacks
+            .map(|addr| addr.parse::<ServerAddr>().expect("local bind addr fallback"))
+            .collect();
+    }
+
     // override the config's mode if UDP_ONLY is set
     if matches.is_present("UDP_ONLY") {
         config.mode = Mode::UdpOnly;
@@ -298,6 +336,15 @@ fn main() {
         config.no_delay = true;
     }
 
+    if matches.is_present("STRICT") {
+        config.strict = true;
+    }
+
+    #[cfg(unix)]
+    if let Some(dscp) = matches.value_of("OUTBOUND_DSCP") {
+        config.outbound_dscp = Some(dscp.parse::<u8>().expect("an unsigned integer for `dscp`"));
+    }
+
     #[cfg(any(target_os = "linux", target_os = "android"))]
     if let Some(mark) = matches.value_of("OUTBOUND_FWMARK") {
         config.outbound_fwmark = Some(mark.parse::<u32>().expect("an unsigned integer for `outbound-fwmark`"));
@@ -343,6 +390,15 @@ fn main() {
         }
     }
 
+    if let Some(tcp_timeout) = matches.value_of("TCP_TIMEOUT") {
+        let tcp_timeout = Duration::from_secs(tcp_timeout.parse::<u64>().expect("tcp-timeout"));
+        for svr in &mut config.server {
+            if svr.timeout().is_none() {
+                svr.set_timeout(tcp_timeout);
+            }
+        }
+    }
+
     if let Some(udp_timeout) = matches.value_of("UDP_TIMEOUT") {
         config.udp_timeout = Some(Duration::from_secs(udp_timeout.parse::<u64>().expect("udp-timeout")));
     }
@@ -351,6 +407,12 @@ fn main() {
         config.udp_max_associations = Some(udp_max_assoc.parse::<usize>().expect("udp-max-associations"));
     }
 
+    if let Some(lifetime) = matches.value_of("MAX_CONNECTION_LIFETIME") {
+        config.max_connection_lifetime = Some(Duration::from_secs(
+            lifetime.parse::<u64>().expect("max-connection-lifetime"),
+        ));
+    }
+
     if let Some(udp_bind_addr) = matches.value_of("UDP_BIND_ADDR") {
         config.udp_bind_addr = Some(udp_bind_addr.parse::<ServerAddr>().expect("udp-bind-addr"));
     }
@@ -361,6 +423,9 @@ fn main() {
     if let Some(bs) = matches.value_of("INBOUND_RECV_BUFFER_SIZE") {
         config.inbound_recv_buffer_size = Some(bs.parse::<u32>().expect("inbound-recv-buffer-size"));
     }
+    if let Some(backlog) = matches.value_of("LISTEN_BACKLOG") {
+        config.listen_backlog = Some(backlog.parse::<i32>().expect("listen-backlog"));
+    }
     if let Some(bs) = matches.value_of("OUTBOUND_SEND_BUFFER_SIZE") {
         config.outbound_send_buffer_size = Some(bs.parse::<u32>().expect("outbound-send-buffer-size"));
     }
@@ -390,6 +455,12 @@ fn main() {
         return;
     }
 
+    if let Err(err) = config.dedup_servers() {
+        eprintln!("config servers check failed, {}", err);
+        println!("{}", matches.usage());
+        return;
+    }
+
     if let Err(err) = config.check_integrity() {
         eprintln!("config integrity check failed, {}", err);
         println!("{}", matches.usage());
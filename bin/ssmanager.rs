@@ -54,16 +54,19 @@ fn main() {
         (@arg SERVER_HOST: -s --("server-host") +takes_value "Host name or IP address of your remote server")
 
         (@arg NO_DELAY: --("no-delay") !takes_value "Set TCP_NODELAY option for socket")
+        (@arg STRICT: --strict !takes_value "Refuse to start with insecure configurations, such as stream ciphers and short passwords")
 
-        (@arg MANAGER_ADDRESS: --("manager-address") +takes_value {validator::validate_manager_addr} "ShadowSocks Manager (ssmgr) address, could be ip:port, domain:port or /path/to/unix.sock")
+        (@arg MANAGER_ADDRESS: --("manager-address") +takes_value {validator::validate_manager_addr} "ShadowSocks Manager (ssmgr) address, could be ip:port, domain:port, /path/to/unix.sock or @name (abstract unix socket, Linux only)")
         (@arg ENCRYPT_METHOD: -m --("encrypt-method") +takes_value possible_values(available_ciphers()) +next_line_help "Default encryption method")
         (@arg TIMEOUT: --timeout +takes_value {validator::validate_u64} "Default timeout seconds for TCP relay")
+        (@arg MAX_CONNECTION_LIFETIME: --("max-connection-lifetime") +takes_value {validator::validate_u64} "Close TCP connections after they have been relayed for this many seconds, regardless of activity")
 
         (@arg NOFILE: -n --nofile +takes_value "Set RLIMIT_NOFILE with both soft and hard limit (only for *nix systems)")
         (@arg ACL: --acl +takes_value "Path to ACL (Access Control List)")
 
         (@arg INBOUND_SEND_BUFFER_SIZE: --("inbound-send-buffer-size") +takes_value {validator::validate_u32} "Set inbound sockets' SO_SNDBUF option")
         (@arg INBOUND_RECV_BUFFER_SIZE: --("inbound-recv-buffer-size") +takes_value {validator::validate_u32} "Set inbound sockets' SO_RCVBUF option")
+        (@arg LISTEN_BACKLOG: --("listen-backlog") +takes_value {validator::validate_i32} "Set TCP listeners' backlog, capped by the system (net.core.somaxconn on Linux)")
         (@arg OUTBOUND_SEND_BUFFER_SIZE: --("outbound-send-buffer-size") +takes_value {validator::validate_u32} "Set outbound sockets' SO_SNDBUF option")
         (@arg OUTBOUND_RECV_BUFFER_SIZE: --("outbound-recv-buffer-size") +takes_value {validator::validate_u32} "Set outbound sockets' SO_RCVBUF option")
     );
@@ -77,6 +80,26 @@ fn main() {
         );
     }
 
+    #[cfg(feature = "syslog")]
+    {
+        app = clap_app!(@app (app)
+            (@arg LOG_TARGET: --("log-target") +takes_value +multiple number_of_values(1) possible_values(&["stderr", "syslog"]) "Log output targets, could be specified multiple times, stderr by default")
+        );
+
+        #[cfg(unix)]
+        {
+            app = clap_app!(@app (app)
+                (@arg SYSLOG_ADDR: --("syslog-addr") +takes_value {validator::validate_socket_addr} "Send logs to the remote syslog server via UDP, instead of local /dev/log")
+            );
+        }
+        #[cfg(not(unix))]
+        {
+            app = clap_app!(@app (app)
+                (@arg SYSLOG_ADDR: --("syslog-addr") +takes_value required_if("LOG_TARGET", "syslog") {validator::validate_socket_addr} "Send logs to the remote syslog server via UDP, required for logging to syslog")
+            );
+        }
+    }
+
     #[cfg(unix)]
     {
         app = clap_app!(@app (app)
@@ -85,6 +108,13 @@ fn main() {
         );
     }
 
+    #[cfg(unix)]
+    {
+        app = clap_app!(@app (app)
+            (@arg OUTBOUND_DSCP: --dscp +takes_value {validator::validate_dscp} "Set DSCP (0 - 63) with IP_TOS / IPV6_TCLASS option for outbound socket")
+        );
+    }
+
     #[cfg(any(target_os = "linux", target_os = "android"))]
     {
         app = clap_app!(@app (app)
@@ -161,6 +191,15 @@ fn main() {
         config.no_delay = true;
     }
 
+    if matches.is_present("STRICT") {
+        config.strict = true;
+    }
+
+    #[cfg(unix)]
+    if let Some(dscp) = matches.value_of("OUTBOUND_DSCP") {
+        config.outbound_dscp = Some(dscp.parse::<u8>().expect("an unsigned integer for `dscp`"));
+    }
+
     #[cfg(any(target_os = "linux", target_os = "android"))]
     if let Some(mark) = matches.value_of("OUTBOUND_FWMARK") {
         config.outbound_fwmark = Some(mark.parse::<u32>().expect("an unsigned integer for `outbound-fwmark`"));
@@ -211,12 +250,21 @@ fn main() {
         config.ipv6_first = true;
     }
 
+    if let Some(lifetime) = matches.value_of("MAX_CONNECTION_LIFETIME") {
+        config.max_connection_lifetime = Some(Duration::from_secs(
+            lifetime.parse::<u64>().expect("max-connection-lifetime"),
+        ));
+    }
+
     if let Some(bs) = matches.value_of("INBOUND_SEND_BUFFER_SIZE") {
         config.inbound_send_buffer_size = Some(bs.parse::<u32>().expect("inbound-send-buffer-size"));
     }
     if let Some(bs) = matches.value_of("INBOUND_RECV_BUFFER_SIZE") {
         config.inbound_recv_buffer_size = Some(bs.parse::<u32>().expect("inbound-recv-buffer-size"));
     }
+    if let Some(backlog) = matches.value_of("LISTEN_BACKLOG") {
+        config.listen_backlog = Some(backlog.parse::<i32>().expect("listen-backlog"));
+    }
     if let Some(bs) = matches.value_of("OUTBOUND_SEND_BUFFER_SIZE") {
         config.outbound_send_buffer_size = Some(bs.parse::<u32>().expect("outbound-send-buffer-size"));
     }
@@ -235,6 +283,12 @@ fn main() {
         return;
     }
 
+    if let Err(err) = config.dedup_servers() {
+        eprintln!("config servers check failed, {}", err);
+        println!("{}", matches.usage());
+        return;
+    }
+
     if let Err(err) = config.check_integrity() {
         eprintln!("config integrity check failed, {}", err);
         println!("{}", matches.usage());
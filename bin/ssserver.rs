@@ -58,17 +58,21 @@ fn main() {
         (@arg PLUGIN: --plugin +takes_value requires[SERVER_ADDR] "SIP003 (https://shadowsocks.org/en/spec/Plugin.html) plugin")
         (@arg PLUGIN_OPT: --("plugin-opts") +takes_value requires[PLUGIN] "Set SIP003 plugin options")
 
-        (@arg MANAGER_ADDRESS: --("manager-address") +takes_value "ShadowSocks Manager (ssmgr) address, could be \"IP:Port\", \"Domain:Port\" or \"/path/to/unix.sock\"")
+        (@arg MANAGER_ADDRESS: --("manager-address") +takes_value {validator::validate_manager_addr} "ShadowSocks Manager (ssmgr) address, could be \"IP:Port\", \"Domain:Port\", \"/path/to/unix.sock\" or \"@name\" (abstract unix socket, Linux only)")
 
         (@arg NO_DELAY: --("no-delay") !takes_value "Set TCP_NODELAY option for socket")
+        (@arg STRICT: --strict !takes_value "Refuse to start with insecure configurations, such as stream ciphers and short passwords")
         (@arg NOFILE: -n --nofile +takes_value "Set RLIMIT_NOFILE with both soft and hard limit (only for *nix systems)")
         (@arg ACL: --acl +takes_value "Path to ACL (Access Control List)")
 
+        (@arg TCP_TIMEOUT: --("tcp-timeout") +takes_value {validator::validate_u64} "Timeout seconds for TCP relay of servers without their own timeout")
         (@arg UDP_TIMEOUT: --("udp-timeout") +takes_value {validator::validate_u64} "Timeout seconds for UDP relay")
         (@arg UDP_MAX_ASSOCIATIONS: --("udp-max-associations") +takes_value {validator::validate_u64} "Maximum associations to be kept simultaneously for UDP relay")
+        (@arg MAX_CONNECTION_LIFETIME: --("max-connection-lifetime") +takes_value {validator::validate_u64} "Close TCP connections after they have been relayed for this many seconds, regardless of activity")
 
         (@arg INBOUND_SEND_BUFFER_SIZE: --("inbound-send-buffer-size") +takes_value {validator::validate_u32} "Set inbound sockets' SO_SNDBUF option")
         (@arg INBOUND_RECV_BUFFER_SIZE: --("inbound-recv-buffer-size") +takes_value {validator::validate_u32} "Set inbound sockets' SO_RCVBUF option")
+        (@arg LISTEN_BACKLOG: --("listen-backlog") +takes_value {validator::validate_i32} "Set TCP listeners' backlog, capped by the system (net.core.somaxconn on Linux)")
         (@arg OUTBOUND_SEND_BUFFER_SIZE: --("outbound-send-buffer-size") +takes_value {validator::validate_u32} "Set outbound sockets' SO_SNDBUF option")
         (@arg OUTBOUND_RECV_BUFFER_SIZE: --("outbound-recv-buffer-size") +takes_value {validator::validate_u32} "Set outbound sockets' SO_RCVBUF option")
 
@@ -84,6 +88,26 @@ fn main() {
         );
     }
 
+    #[cfg(feature = "syslog")]
+    {
+        app = clap_app!(@app (app)
+            (@arg LOG_TARGET: --("log-target") +takes_value +multiple number_of_values(1) possible_values(&["stderr", "syslog"]) "Log output targets, could be specified multiple times, stderr by default")
+        );
+
+        #[cfg(unix)]
+        {
+            app = clap_app!(@app (app)
+                (@arg SYSLOG_ADDR: --("syslog-addr") +takes_value {validator::validate_socket_addr} "Send logs to the remote syslog server via UDP, instead of local /dev/log")
+            );
+        }
+        #[cfg(not(unix))]
+        {
+            app = clap_app!(@app (app)
+                (@arg SYSLOG_ADDR: --("syslog-addr") +takes_value required_if("LOG_TARGET", "syslog") {validator::validate_socket_addr} "Send logs to the remote syslog server via UDP, required for logging to syslog")
+            );
+        }
+    }
+
     #[cfg(unix)]
     {
         app = clap_app!(@app (app)
@@ -92,6 +116,13 @@ fn main() {
         );
     }
 
+    #[cfg(unix)]
+    {
+        app = clap_app!(@app (app)
+            (@arg OUTBOUND_DSCP: --dscp +takes_value {validator::validate_dscp} "Set DSCP (0 - 63) with IP_TOS / IPV6_TCLASS option for outbound socket")
+        );
+    }
+
     #[cfg(any(target_os = "linux", target_os = "android"))]
     {
         app = clap_app!(@app (app)
@@ -199,6 +230,15 @@ fn main() {
         config.no_delay = true;
     }
 
+    if matches.is_present("STRICT") {
+        config.strict = true;
+    }
+
+    #[cfg(unix)]
+    if let Some(dscp) = matches.value_of("OUTBOUND_DSCP") {
+        config.outbound_dscp = Some(dscp.parse::<u8>().expect("an unsigned integer for `dscp`"));
+    }
+
     #[cfg(any(target_os = "linux", target_os = "android"))]
     if let Some(mark) = matches.value_of("OUTBOUND_FWMARK") {
         config.outbound_fwmark = Some(mark.parse::<u32>().expect("an unsigned integer for `outbound-fwmark`"));
@@ -231,6 +271,15 @@ fn main() {
         config.ipv6_first = true;
     }
 
+    if let Some(tcp_timeout) = matches.value_of("TCP_TIMEOUT") {
+        let tcp_timeout = Duration::from_secs(tcp_timeout.parse::<u64>().expect("tcp-timeout"));
+        for svr in &mut config.server {
+            if svr.timeout().is_none() {
+                svr.set_timeout(tcp_timeout);
+            }
+        }
+    }
+
     if let Some(udp_timeout) = matches.value_of("UDP_TIMEOUT") {
         config.udp_timeout = Some(Duration::from_secs(udp_timeout.parse::<u64>().expect("udp-timeout")));
     }
@@ -239,12 +288,21 @@ fn main() {
         config.udp_max_associations = Some(udp_max_assoc.parse::<usize>().expect("udp-max-associations"));
     }
 
+    if let Some(lifetime) = matches.value_of("MAX_CONNECTION_LIFETIME") {
+        config.max_connection_lifetime = Some(Duration::from_secs(
+            lifetime.parse::<u64>().expect("max-connection-lifetime"),
+        ));
+    }
+
     if let Some(bs) = matches.value_of("INBOUND_SEND_BUFFER_SIZE") {
         config.inbound_send_buffer_size = Some(bs.parse::<u32>().expect("inbound-send-buffer-size"));
     }
     if let Some(bs) = matches.value_of("INBOUND_RECV_BUFFER_SIZE") {
         config.inbound_recv_buffer_size = Some(bs.parse::<u32>().expect("inbound-recv-buffer-size"));
     }
+    if let Some(backlog) = matches.value_of("LISTEN_BACKLOG") {
+        config.listen_backlog = Some(backlog.parse::<i32>().expect("listen-backlog"));
+    }
     if let Some(bs) = matches.value_of("OUTBOUND_SEND_BUFFER_SIZE") {
         config.outbound_send_buffer_size = Some(bs.parse::<u32>().expect("outbound-send-buffer-size"));
     }
@@ -264,6 +322,12 @@ fn main() {
         return;
     }
 
+    if let Err(err) = config.dedup_servers() {
+        eprintln!("config servers check failed, {}", err);
+        println!("{}", matches.usage());
+        return;
+    }
+
     if let Err(err) = config.check_integrity() {
         eprintln!("config integrity check failed, {}", err);
         println!("{}", matches.usage());
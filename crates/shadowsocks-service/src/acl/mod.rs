@@ -7,11 +7,12 @@ use std::{
     fs::File,
     io::{self, BufRead, BufReader, Error, ErrorKind},
     net::{IpAddr, SocketAddr},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
 use ipnet::{IpNet, Ipv4Net, Ipv6Net};
 use iprange::IpRange;
+use log::info;
 use regex::{RegexSet, RegexSetBuilder};
 
 use shadowsocks::{context::Context, relay::socks5::Address};
@@ -30,6 +31,8 @@ struct Rules {
     ipv4: IpRange<Ipv4Net>,
     ipv6: IpRange<Ipv6Net>,
     rule: RegexSet,
+    // Name of the section, if matches should be logged
+    log_section: Option<String>,
 }
 
 impl fmt::Debug for Rules {
@@ -56,12 +59,22 @@ impl fmt::Debug for Rules {
 
 impl Rules {
     /// Create a new rule
-    fn new(mut ipv4: IpRange<Ipv4Net>, mut ipv6: IpRange<Ipv6Net>, rule: RegexSet) -> Rules {
+    fn new(
+        mut ipv4: IpRange<Ipv4Net>,
+        mut ipv6: IpRange<Ipv6Net>,
+        rule: RegexSet,
+        log_section: Option<String>,
+    ) -> Rules {
         // Optimization, merging networks
         ipv4.simplify();
         ipv6.simplify();
 
-        Rules { ipv4, ipv6, rule }
+        Rules {
+            ipv4,
+            ipv6,
+            rule,
+            log_section,
+        }
     }
 
     /// Check if the specified address matches these rules
@@ -75,15 +88,39 @@ impl Rules {
 
     /// Check if the specified address matches any rules
     fn check_ip_matched(&self, addr: &IpAddr) -> bool {
-        match addr {
+        let matched = match addr {
             IpAddr::V4(v4) => self.ipv4.contains(v4),
             IpAddr::V6(v6) => self.ipv6.contains(v6),
+        };
+
+        if matched {
+            if let Some(ref section) = self.log_section {
+                // Networks are merged, so this may be wider than the rule that is actually written
+                let network = match addr {
+                    IpAddr::V4(v4) => self.ipv4.iter().find(|n| n.contains(v4)).map(IpNet::V4),
+                    IpAddr::V6(v6) => self.ipv6.iter().find(|n| n.contains(v6)).map(IpNet::V6),
+                };
+                if let Some(network) = network {
+                    info!("ACL [{}] rule {} matched {}", section, network, addr);
+                }
+            }
         }
+
+        matched
     }
 
     /// Check if the specified host matches any rules
     fn check_host_matched(&self, host: &str) -> bool {
-        self.rule.is_match(host)
+        match self.log_section {
+            None => self.rule.is_match(host),
+            Some(ref section) => match self.rule.matches(host).iter().next() {
+                Some(idx) => {
+                    info!("ACL [{}] rule {} matched {}", section, self.rule.patterns()[idx], host);
+                    true
+                }
+                None => false,
+            },
+        }
     }
 
     /// Check if there are no rules for IP addresses
@@ -116,6 +153,9 @@ impl Rules {
 /// RULE-3
 /// ```
 ///
+/// Sections could be followed by a `log` flag, like `[outbound_block_list] log`, then every match of its rules
+/// will be logged with the matched rule and target.
+///
 /// Available sections are
 ///
 /// - For local servers (`sslocal`, `ssredir`, ...)
@@ -150,12 +190,15 @@ pub struct AccessControl {
     black_list: Rules,
     white_list: Rules,
     mode: Mode,
+    file_path: PathBuf,
 }
 
 impl AccessControl {
     /// Load ACL rules from a file
     pub fn load_from_file<P: AsRef<Path>>(p: P) -> io::Result<AccessControl> {
-        let fp = File::open(p)?;
+        let file_path = p.as_ref().to_owned();
+
+        let fp = File::open(&file_path)?;
         let r = BufReader::new(fp);
 
         let mut mode = Mode::BlackList;
@@ -170,6 +213,10 @@ impl AccessControl {
         let mut proxy_ipv6 = IpRange::new();
         let mut proxy_rules = Vec::new();
 
+        let mut outbound_block_log = None;
+        let mut bypass_log = None;
+        let mut proxy_log = None;
+
         let mut curr_ipv4 = &mut bypass_ipv4;
         let mut curr_ipv6 = &mut bypass_ipv6;
         let mut curr_rules = &mut bypass_rules;
@@ -185,7 +232,13 @@ impl AccessControl {
                 continue;
             }
 
-            match line.as_str() {
+            // Section flags, `[section] log`
+            let (section, log) = match line.find(']') {
+                Some(pos) if line.starts_with('[') && line[pos + 1..].trim() == "log" => (&line[..pos + 1], true),
+                _ => (line.as_str(), false),
+            };
+
+            match section {
                 "[reject_all]" | "[bypass_all]" => {
                     mode = Mode::WhiteList;
                 }
@@ -193,16 +246,25 @@ impl AccessControl {
                     mode = Mode::BlackList;
                 }
                 "[outbound_block_list]" => {
+                    if log {
+                        outbound_block_log = Some(section[1..section.len() - 1].to_owned());
+                    }
                     curr_ipv4 = &mut outbound_block_ipv4;
                     curr_ipv6 = &mut outbound_block_ipv6;
                     curr_rules = &mut outbound_block_rules;
                 }
                 "[black_list]" | "[bypass_list]" => {
+                    if log {
+                        bypass_log = Some(section[1..section.len() - 1].to_owned());
+                    }
                     curr_ipv4 = &mut bypass_ipv4;
                     curr_ipv6 = &mut bypass_ipv6;
                     curr_rules = &mut bypass_rules;
                 }
                 "[white_list]" | "[proxy_list]" => {
+                    if log {
+                        proxy_log = Some(section[1..section.len() - 1].to_owned());
+                    }
                     curr_ipv4 = &mut proxy_ipv4;
                     curr_ipv6 = &mut proxy_ipv6;
                     curr_rules = &mut proxy_rules;
@@ -279,13 +341,24 @@ impl AccessControl {
         };
 
         Ok(AccessControl {
-            outbound_block: Rules::new(outbound_block_ipv4, outbound_block_ipv6, outbound_block_regex),
-            black_list: Rules::new(bypass_ipv4, bypass_ipv6, bypass_regex),
-            white_list: Rules::new(proxy_ipv4, proxy_ipv6, proxy_regex),
+            outbound_block: Rules::new(
+                outbound_block_ipv4,
+                outbound_block_ipv6,
+                outbound_block_regex,
+                outbound_block_log,
+            ),
+            black_list: Rules::new(bypass_ipv4, bypass_ipv6, bypass_regex, bypass_log),
+            white_list: Rules::new(proxy_ipv4, proxy_ipv6, proxy_regex, proxy_log),
             mode,
+            file_path,
         })
     }
 
+    /// Path of the file that rules were loaded from
+    pub fn file_path(&self) -> &Path {
+        &self.file_path
+    }
+
     /// Check if domain name is in proxy_list.
     /// If so, it should be resolved from remote (for Android's DNS relay)
     ///
@@ -58,12 +58,13 @@ use std::{
 };
 
 use cfg_if::cfg_if;
+use log::{info, Level};
 use serde::{Deserialize, Serialize};
 #[cfg(any(feature = "local-tunnel", feature = "local-dns"))]
 use shadowsocks::relay::socks5::Address;
 use shadowsocks::{
     config::{ManagerAddr, ServerAddr, ServerConfig},
-    crypto::v1::CipherKind,
+    crypto::v1::{CipherCategory, CipherKind},
     plugin::PluginConfig,
 };
 #[cfg(feature = "trust-dns")]
@@ -81,6 +82,14 @@ enum SSDnsConfig {
     TrustDns(ResolverConfig),
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+enum SSLocalAddress {
+    Single(String),
+    // Tried in order, until one of them could be bound
+    Fallback(Vec<String>),
+}
+
 #[derive(Serialize, Deserialize, Debug, Default)]
 struct SSConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -88,7 +97,7 @@ struct SSConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     server_port: Option<u16>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    local_address: Option<String>,
+    local_address: Option<SSLocalAddress>,
     #[serde(skip_serializing_if = "Option::is_none")]
     local_port: Option<u16>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -108,10 +117,18 @@ struct SSConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     timeout: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    tcp_timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     udp_timeout: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     udp_max_associations: Option<usize>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    udp_send_error_policy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    udp_eviction_policy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    udp_source_verify: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     servers: Option<Vec<SSServerExtConfig>>,
     #[cfg(feature = "trust-dns")]
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -124,6 +141,56 @@ struct SSConfig {
     nofile: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     ipv6_first: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unmap_ipv4: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min_avg_chunk_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    netflow_collector: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    autoproxy: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    autoproxy_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    autoproxy_direct_timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    split_first_packet: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duplicate_server_policy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    startup_resolve_policy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    canary: Option<SSCanaryConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    balancer_strategy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    balancer_state_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    server_affinity: Option<SSAffinityConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    outbound_dscp: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    outbound_reuse_addr: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    strict: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    per_ip_ramp: Option<SSRampConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    verify_server_response: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    socks5_server_hint: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    connect_deadline: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_pending_connects: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    captive_portal_check: Option<SSCaptivePortalConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_plugin_processes: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    plugin_log_level: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_connection_lifetime: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -151,6 +218,38 @@ struct SSServerExtConfig {
     remarks: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    weight: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SSCanaryConfig {
+    // SIP002 URL
+    server: String,
+    percentage: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SSAffinityConfig {
+    // Seconds
+    ttl: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SSRampConfig {
+    burst: usize,
+    step_delay_ms: u64,
+    max_delay_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    window_ms: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SSCaptivePortalConfig {
+    url: String,
+    interval: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    threshold: Option<u32>,
 }
 
 /// Listening address
@@ -227,6 +326,222 @@ impl FromStr for Mode {
     }
 }
 
+/// Policy for servers with exactly the same configuration
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DuplicateServerPolicy {
+    /// Keep all of them, for spreading loads
+    Keep,
+    /// Remove the duplicated ones
+    Dedup,
+    /// Reject the configuration
+    Error,
+}
+
+impl Default for DuplicateServerPolicy {
+    fn default() -> DuplicateServerPolicy {
+        DuplicateServerPolicy::Dedup
+    }
+}
+
+impl fmt::Display for DuplicateServerPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DuplicateServerPolicy::Keep => f.write_str("keep"),
+            DuplicateServerPolicy::Dedup => f.write_str("dedup"),
+            DuplicateServerPolicy::Error => f.write_str("error"),
+        }
+    }
+}
+
+impl FromStr for DuplicateServerPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(DuplicateServerPolicy::Keep),
+            "dedup" => Ok(DuplicateServerPolicy::Dedup),
+            "error" => Ok(DuplicateServerPolicy::Error),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Policy for servers whose host names couldn't be resolved when local servers start
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StartupResolvePolicy {
+    /// Refuse to start
+    Fail,
+    /// Start anyway, host names are resolved on every connection, so they are retried until they are resolved
+    Defer,
+    /// Remove them from the servers with a warning, keep the others
+    ///
+    /// Removed servers are not added back if they become resolvable later.
+    Skip,
+}
+
+impl Default for StartupResolvePolicy {
+    fn default() -> StartupResolvePolicy {
+        StartupResolvePolicy::Defer
+    }
+}
+
+impl fmt::Display for StartupResolvePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StartupResolvePolicy::Fail => f.write_str("fail"),
+            StartupResolvePolicy::Defer => f.write_str("defer"),
+            StartupResolvePolicy::Skip => f.write_str("skip"),
+        }
+    }
+}
+
+impl FromStr for StartupResolvePolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(StartupResolvePolicy::Fail),
+            "defer" => Ok(StartupResolvePolicy::Defer),
+            "skip" => Ok(StartupResolvePolicy::Skip),
+            _ => Err(()),
+        }
+    }
+}
+
+/// How the load balancer chooses between the stable servers
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BalancerStrategy {
+    /// The server with the best score of probes
+    Latency,
+    /// Randomly by servers' `weight` (1 if not set), unhealthy servers are skipped
+    Weighted,
+}
+
+impl Default for BalancerStrategy {
+    fn default() -> BalancerStrategy {
+        BalancerStrategy::Latency
+    }
+}
+
+impl fmt::Display for BalancerStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BalancerStrategy::Latency => f.write_str("latency"),
+            BalancerStrategy::Weighted => f.write_str("weighted"),
+        }
+    }
+}
+
+impl FromStr for BalancerStrategy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "latency" => Ok(BalancerStrategy::Latency),
+            "weighted" => Ok(BalancerStrategy::Weighted),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Policy for errors of sending UDP packets to targets
+///
+/// Errors that only concern the packet itself, like `EMSGSIZE`, always drop the packet only.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UdpSendErrorPolicy {
+    /// Drop the packet, the association is kept
+    DropPacket,
+    /// Drop the packet and close the association
+    DropAssociation,
+}
+
+impl Default for UdpSendErrorPolicy {
+    fn default() -> UdpSendErrorPolicy {
+        UdpSendErrorPolicy::DropPacket
+    }
+}
+
+impl fmt::Display for UdpSendErrorPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UdpSendErrorPolicy::DropPacket => f.write_str("drop-packet"),
+            UdpSendErrorPolicy::DropAssociation => f.write_str("drop-association"),
+        }
+    }
+}
+
+impl FromStr for UdpSendErrorPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop-packet" => Ok(UdpSendErrorPolicy::DropPacket),
+            "drop-association" => Ok(UdpSendErrorPolicy::DropAssociation),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Policy for choosing the UDP association to evict when the association table is full
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UdpEvictionPolicy {
+    /// Least recently used
+    Lru,
+    /// Least frequently used
+    Lfu,
+    /// Created earliest
+    Oldest,
+}
+
+impl Default for UdpEvictionPolicy {
+    fn default() -> UdpEvictionPolicy {
+        UdpEvictionPolicy::Lru
+    }
+}
+
+impl fmt::Display for UdpEvictionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UdpEvictionPolicy::Lru => f.write_str("lru"),
+            UdpEvictionPolicy::Lfu => f.write_str("lfu"),
+            UdpEvictionPolicy::Oldest => f.write_str("oldest"),
+        }
+    }
+}
+
+impl FromStr for UdpEvictionPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lru" => Ok(UdpEvictionPolicy::Lru),
+            "lfu" => Ok(UdpEvictionPolicy::Lfu),
+            "oldest" => Ok(UdpEvictionPolicy::Oldest),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Check if two servers are exactly the same
+///
+/// Servers are the same if they have the same address, method, password, plugin (with options and arguments)
+/// and name (`remarks`). `timeout`, `id` and `weight` are not compared.
+fn is_duplicated_server(lhs: &ServerConfig, rhs: &ServerConfig) -> bool {
+    let plugin_eq = match (lhs.plugin(), rhs.plugin()) {
+        (None, None) => true,
+        (Some(lp), Some(rp)) => {
+            lp.plugin == rp.plugin && lp.plugin_opts == rp.plugin_opts && lp.plugin_args == rp.plugin_args
+        }
+        _ => false,
+    };
+
+    lhs.addr() == rhs.addr()
+        && lhs.method() == rhs.method()
+        && lhs.password() == rhs.password()
+        && plugin_eq
+        && lhs.remarks() == rhs.remarks()
+}
+
 cfg_if! {
     if #[cfg(feature = "local-redir")] {
         use strum::IntoEnumIterator;
@@ -543,6 +858,93 @@ impl FromStr for ProtocolType {
     }
 }
 
+/// Canary server for validating a new server in production
+///
+/// Each connection (or UDP association) is randomly sent to the canary server by chance of `percentage`%,
+/// the rest of them are sent to the best one of the stable servers. The canary server is probed and scored
+/// separately, so its failures never affect choosing between the stable servers. While the canary server is unhealthy,
+/// all connections are sent to the stable servers.
+#[derive(Clone, Debug)]
+pub struct CanaryConfig {
+    /// The canary server, which is not one of the stable servers
+    pub server: ServerConfig,
+    /// Percentage of connections sent to the canary server, in (0, 100]
+    pub percentage: f64,
+}
+
+/// Affinity of clients to servers in local servers (sticky sessions)
+///
+/// Connections (and UDP associations) from the same client IP are sent to the same server, until the client hasn't
+/// made any connections for `ttl`. Clients are reassigned if their servers become unhealthy.
+#[derive(Clone, Debug)]
+pub struct AffinityConfig {
+    /// Time for clients to be remembered since their latest connections
+    pub ttl: Duration,
+}
+
+/// Default time constant of the sliding window of `RampConfig`
+const DEFAULT_RAMP_WINDOW: Duration = Duration::from_secs(10);
+
+/// Connections ramp for each client IP on servers
+///
+/// TCP connections accepted from each client IP are counted in a sliding window, which decays exponentially with the
+/// time constant `window`. Each client IP could have `burst` recent connections served immediately, the `n`-th
+/// connection beyond that is delayed by `n * step_delay`, at most `max_delay`. Connections are delayed but never
+/// rejected.
+#[derive(Clone, Debug)]
+pub struct RampConfig {
+    /// Recent connections that are served without delay
+    pub burst: usize,
+    /// Delay added for each recent connection beyond `burst`
+    pub step_delay: Duration,
+    /// Maximum delay of a connection
+    pub max_delay: Duration,
+    /// Time constant of the sliding window, connections are mostly forgotten after 3 windows
+    pub window: Duration,
+}
+
+/// Default consecutive detections of `CaptivePortalConfig`
+const DEFAULT_CAPTIVE_PORTAL_THRESHOLD: u32 = 2;
+
+/// Captive portal check of local servers
+///
+/// `url` is requested directly (not through servers) every `interval`. It must respond `204 No Content` when there is
+/// no captive portal, like `http://connectivitycheck.gstatic.com/generate_204`. A redirect (usually to the portal's
+/// login page) or a `200 OK` with a body means that the network is behind a captive portal.
+#[derive(Clone, Debug)]
+pub struct CaptivePortalConfig {
+    /// Connectivity check URL, only `http://` URLs are supported
+    pub url: String,
+    /// Interval between checks
+    pub interval: Duration,
+    /// Consecutive detections required before proxying is paused
+    pub threshold: u32,
+}
+
+impl CaptivePortalConfig {
+    /// Split `url` into host, port and path, `None` if it isn't a valid `http://` URL
+    pub fn split_url(&self) -> Option<(&str, u16, &str)> {
+        let rest = self.url.strip_prefix("http://")?;
+        let (authority, path) = match rest.find('/') {
+            Some(pos) => (&rest[..pos], &rest[pos..]),
+            None => (rest, "/"),
+        };
+
+        let (host, port) = match authority.rfind(':') {
+            // Not a colon inside IPv6 address, `[::1]`
+            Some(pos) if !authority[pos..].contains(']') => (&authority[..pos], authority[pos + 1..].parse().ok()?),
+            _ => (authority, 80),
+        };
+
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            return None;
+        }
+
+        Some((host, port, path))
+    }
+}
+
 /// Configuration
 #[derive(Clone, Debug)]
 pub struct Config {
@@ -550,6 +952,11 @@ pub struct Config {
     pub server: Vec<ServerConfig>,
     /// Local server's bind address, or ShadowSocks server's outbound address
     pub local_addr: Option<ClientConfig>,
+    /// Local server's alternative bind addresses, with the same port as `local_addr`
+    ///
+    /// If `local_addr` couldn't be bound, for example, the interface is not up yet, they are tried in order, until
+    /// one of them could be bound. The address actually bound is used for all the listeners of the local server.
+    pub local_addr_fallback: Vec<ClientConfig>,
 
     /// Destination address for tunnel
     #[cfg(feature = "local-tunnel")]
@@ -570,6 +977,11 @@ pub struct Config {
     ///
     /// Set to `true` if you want to query IPv6 addresses before IPv4
     pub ipv6_first: bool,
+    /// Connect IPv4-mapped IPv6 targets (`::ffff:a.b.c.d`) with plain IPv4 addresses, default is `true`
+    ///
+    /// Otherwise they are connected with IPv6 sockets, which depends on whether the platform supports dual-stack
+    /// sockets. IPv4-compatible addresses (`::a.b.c.d`) are always treated as IPv6.
+    pub unmap_ipv4: bool,
 
     /// Internal DNS's bind address
     #[cfg(feature = "local-dns")]
@@ -599,15 +1011,26 @@ pub struct Config {
     /// Path to protect callback unix address, only for Android
     #[cfg(target_os = "android")]
     pub outbound_vpn_protect_path: Option<PathBuf>,
+    /// Set DSCP with `IP_TOS` or `IPV6_TCLASS` socket option for outbound sockets, must be in range 0 - 63
+    #[cfg(unix)]
+    pub outbound_dscp: Option<u8>,
 
     /// Set `SO_SNDBUF` for inbound sockets
     pub inbound_send_buffer_size: Option<u32>,
     /// Set `SO_RCVBUF` for inbound sockets
     pub inbound_recv_buffer_size: Option<u32>,
+    /// Set backlog of TCP listeners, capped by the system (`net.core.somaxconn` on Linux)
+    pub listen_backlog: Option<i32>,
     /// Set `SO_SNDBUF` for outbound sockets
     pub outbound_send_buffer_size: Option<u32>,
     /// Set `SO_RCVBUF` for outbound sockets
     pub outbound_recv_buffer_size: Option<u32>,
+    /// Set `SO_REUSEADDR` for outbound TCP sockets
+    ///
+    /// It only matters if outbound sockets bind to `local_address`, ports still in `TIME_WAIT` could be bound again.
+    /// It doesn't widen the range of ephemeral ports. Ignored on Windows, where `SO_REUSEADDR` allows stealing ports
+    /// that are in use.
+    pub outbound_reuse_addr: bool,
 
     /// Manager's configuration
     pub manager: Option<ManagerConfig>,
@@ -620,9 +1043,26 @@ pub struct Config {
     pub local_protocol: ProtocolType,
 
     /// Timeout for UDP Associations, default is 5 minutes
+    ///
+    /// Not affected by the generic `timeout` in configuration file. Timeout for TCP is set to each server,
+    /// which is `tcp_timeout` or the generic `timeout`.
     pub udp_timeout: Option<Duration>,
     /// Maximum number of UDP Associations, default is unconfigured
     pub udp_max_associations: Option<usize>,
+    /// Policy for errors of sending UDP packets to targets (or to servers in local, for proxied packets)
+    pub udp_send_error_policy: UdpSendErrorPolicy,
+    /// Policy for choosing the association to evict when there are already `udp_max_associations` associations
+    pub udp_eviction_policy: UdpEvictionPolicy,
+    /// Verify sources of UDP packets in servers
+    ///
+    /// Packets from a source without an UDP association are dropped, unless the source IP has passed the handshake
+    /// of a TCP connection (which can't be done with a spoofed address) to the server within the UDP association
+    /// timeout. Shadowsocks UDP packets don't carry a handshake or session, so this is the only way of proving that
+    /// a client owns its address, and servers won't be used for reflecting packets to spoofed addresses.
+    ///
+    /// Only clients are verified. The relay is full-cone NAT: an association's outbound sockets accept packets from
+    /// any remote address and relay them back to the client, which isn't restricted by this option.
+    pub udp_source_verify: bool,
     /// UDP relay's bind address, it uses `local_addr` by default
     ///
     /// Resolving Android's issue: [shadowsocks/shadowsocks-android#2571](https://github.com/shadowsocks/shadowsocks-android/issues/2571)
@@ -631,6 +1071,125 @@ pub struct Config {
     /// ACL configuration
     pub acl: Option<AccessControl>,
 
+    /// Minimum average size of AEAD chunks received in one connection
+    ///
+    /// Connections flooding with tiny chunks will be dropped, `0` disables the check. It is 2 bytes for servers
+    /// if not set, and sslocal only checks chunks from servers if it is set.
+    pub min_avg_chunk_size: Option<usize>,
+
+    /// IPFIX collector's address
+    ///
+    /// Servers will export a flow record for each finished TCP tunnel
+    pub netflow_collector: Option<SocketAddr>,
+
+    /// Autoproxy mode for local servers, disabled by default
+    ///
+    /// Domain name targets are connected directly first, and will be proxied if they couldn't be connected.
+    /// Domain names that have to be proxied are learned and will be proxied immediately next time.
+    ///
+    /// Connecting directly resolves domain names with the local DNS resolver, so the DNS queries of targets are
+    /// visible to the local network until they have been learned. Only failures of establishing connections are
+    /// detected, connections reset after being established are not learned.
+    pub autoproxy: bool,
+    /// Path to the file for persisting learned autoproxy domain names
+    pub autoproxy_path: Option<PathBuf>,
+    /// Timeout for connecting to targets directly in autoproxy mode, 3 seconds by default
+    pub autoproxy_direct_timeout: Option<Duration>,
+
+    /// Split the first packet of bypassed TCP tunnels into segments with this size, disabled by default
+    ///
+    /// This is a best-effort evasion of DPI that blocks connections by reassembling the first packet,
+    /// such as SNI-based blocking. It won't take effect if the first packet is sent with SYN by TFO.
+    /// Tunnels through servers are not split, their first packets are encrypted already.
+    ///
+    /// Bypassed tunnels wait at most 500ms for the first packet if it is enabled, which delays protocols that servers
+    /// send their messages first.
+    pub split_first_packet: Option<usize>,
+
+    /// Policy for servers with exactly the same configuration, applied by `dedup_servers`
+    pub duplicate_server_policy: DuplicateServerPolicy,
+
+    /// Policy for servers (including the canary server) whose host names couldn't be resolved when local servers
+    /// start, for example, the network is not up yet at boot
+    pub startup_resolve_policy: StartupResolvePolicy,
+
+    /// Canary server for local servers, which receives a small percentage of connections
+    pub canary: Option<CanaryConfig>,
+    /// How the balancer chooses between the stable servers, servers' `weight` is ignored unless it is `Weighted`
+    pub balancer_strategy: BalancerStrategy,
+    /// Path to the file for persisting the balancer's learned server scores
+    ///
+    /// Scores are loaded at startup, so servers are not chosen blindly after restarting. Loaded scores are only
+    /// the initial state, they are superseded by fresh probes within the statistic window (about 10 minutes).
+    pub balancer_state_file: Option<PathBuf>,
+    /// Sticky servers for each client IP
+    pub server_affinity: Option<AffinityConfig>,
+
+    /// Strict mode, refuses insecure configurations in `check_integrity`
+    ///
+    /// These are refused for all configured servers (including the canary server) and manager's default method:
+    ///
+    /// 1. Deprecated stream ciphers
+    /// 2. Methods without encryption (`none` / `plain`)
+    /// 3. Passwords shorter than 8 characters
+    ///
+    /// There is no built-in TLS transport, so TLS verification of plugins (SIP003) couldn't be checked.
+    pub strict: bool,
+
+    /// Connections ramp for each client IP on servers
+    pub per_ip_ramp: Option<RampConfig>,
+
+    /// Verify that servers respond to the first packet of TCP tunnels in local servers
+    ///
+    /// Servers which reset or close proxied connections before sending the first byte of response (connections reset
+    /// after handshake by middleboxes, for example) are reported as failed to the load balancer, which counts against
+    /// their scores. The failed connection itself is closed, it is not retried with another server.
+    pub verify_server_response: bool,
+
+    /// Allow SOCKS5 clients to choose the server by a `server:<name>` field in the username
+    ///
+    /// The username/password method (RFC1929) is only negotiated if it is enabled and the client doesn't offer the
+    /// no authentication method. The password is not verified, it only carries the hint.
+    pub socks5_server_hint: bool,
+
+    /// Upper bound of time for establishing an outbound connection in local servers
+    ///
+    /// It covers the whole process, including connecting directly in autoproxy mode, connecting the server
+    /// and all the resolved addresses tried underneath. Connections exceeding it fail with `TimedOut`.
+    pub connect_deadline: Option<Duration>,
+
+    /// Maximum number of outbound connections being established concurrently in local servers
+    ///
+    /// Connects exceeding it wait until one of the pending connects finishes (successfully or not). Only the
+    /// establishment is limited, established connections are not counted. Time spent on waiting is included in
+    /// `connect_deadline`.
+    pub max_pending_connects: Option<usize>,
+
+    /// Captive portal check of local servers
+    ///
+    /// TCP connections through servers fail immediately while a captive portal is detected, and are allowed again
+    /// after the portal has been cleared. Connections bypassed (by ACL) are not affected, and servers are not
+    /// reported as failed because of the portal. UDP associations are not paused.
+    pub captive_portal_check: Option<CaptivePortalConfig>,
+
+    /// Maximum number of plugin processes running concurrently
+    ///
+    /// Servers with plugins fail to start if it is exceeded. Local servers share one plugin process between
+    /// servers with the same address and exactly the same plugin configuration.
+    pub max_plugin_processes: Option<usize>,
+    /// Log lines written to stderr by plugin processes with this level, prefixed with plugin's name and server
+    ///
+    /// Plugin processes write to stderr of the current process directly if it is not set.
+    pub plugin_log_level: Option<Level>,
+
+    /// Maximum lifetime of TCP connections, regardless of activity
+    ///
+    /// Connections stop reading after they have been relayed for this long, which is not reset by traffic like
+    /// `timeout`. Data already read are written out before both directions are half-closed, for at most 5 seconds,
+    /// data still in flight from the peers are lost. Clients have to reconnect, so long-lived connections are
+    /// recycled through the balancer periodically.
+    pub max_connection_lifetime: Option<Duration>,
+
     /// TCP Transparent Proxy type
     #[cfg(feature = "local-redir")]
     pub tcp_redir: RedirType,
@@ -708,6 +1267,7 @@ impl Config {
         Config {
             server: Vec::new(),
             local_addr: None,
+            local_addr_fallback: Vec::new(),
 
             #[cfg(feature = "local-tunnel")]
             forward: None,
@@ -715,6 +1275,7 @@ impl Config {
             #[cfg(feature = "trust-dns")]
             dns: None,
             ipv6_first: false,
+            unmap_ipv4: true,
 
             #[cfg(feature = "local-dns")]
             dns_bind_addr: None,
@@ -732,11 +1293,15 @@ impl Config {
             outbound_bind_interface: None,
             #[cfg(target_os = "android")]
             outbound_vpn_protect_path: None,
+            #[cfg(unix)]
+            outbound_dscp: None,
 
             inbound_send_buffer_size: None,
             inbound_recv_buffer_size: None,
+            listen_backlog: None,
             outbound_send_buffer_size: None,
             outbound_recv_buffer_size: None,
+            outbound_reuse_addr: false,
 
             manager: None,
 
@@ -746,10 +1311,36 @@ impl Config {
 
             udp_timeout: None,
             udp_max_associations: None,
+            udp_send_error_policy: UdpSendErrorPolicy::default(),
+            udp_eviction_policy: UdpEvictionPolicy::default(),
+            udp_source_verify: false,
             udp_bind_addr: None,
 
             acl: None,
 
+            min_avg_chunk_size: None,
+            netflow_collector: None,
+            autoproxy: false,
+            autoproxy_path: None,
+            autoproxy_direct_timeout: None,
+            split_first_packet: None,
+            duplicate_server_policy: DuplicateServerPolicy::default(),
+            startup_resolve_policy: StartupResolvePolicy::default(),
+            canary: None,
+            balancer_strategy: BalancerStrategy::default(),
+            balancer_state_file: None,
+            server_affinity: None,
+            strict: false,
+            per_ip_ramp: None,
+            verify_server_response: false,
+            socks5_server_hint: false,
+            connect_deadline: None,
+            max_pending_connects: None,
+            captive_portal_check: None,
+            max_plugin_processes: None,
+            plugin_log_level: None,
+            max_connection_lifetime: None,
+
             #[cfg(feature = "local-redir")]
             tcp_redir: RedirType::tcp_default(),
             #[cfg(feature = "local-redir")]
@@ -785,14 +1376,39 @@ impl Config {
                     config.local_port.unwrap_or(0)
                 };
 
-                let local_addr = match la.parse::<IpAddr>() {
+                let parse_local_addr = |la: String| match la.parse::<IpAddr>() {
                     Ok(ip) => ServerAddr::from(SocketAddr::new(ip, local_port)),
                     Err(..) => {
                         // treated as domain
                         ServerAddr::from((la, local_port))
                     }
                 };
-                nconfig.local_addr = Some(local_addr);
+
+                match la {
+                    SSLocalAddress::Single(la) => {
+                        nconfig.local_addr = Some(parse_local_addr(la));
+                    }
+                    SSLocalAddress::Fallback(las) => {
+                        if !config_type.is_local() {
+                            let err = Error::new(
+                                ErrorKind::Invalid,
+                                "`local_address` could only be a list for local servers",
+                                None,
+                            );
+                            return Err(err);
+                        }
+
+                        let mut las = las.into_iter().map(parse_local_addr);
+                        nconfig.local_addr = match las.next() {
+                            Some(la) => Some(la),
+                            None => {
+                                let err = Error::new(ErrorKind::Malformed, "`local_address` list is empty", None);
+                                return Err(err);
+                            }
+                        };
+                        nconfig.local_addr_fallback = las.collect();
+                    }
+                }
             }
             None => {
                 if config_type.is_local() && config.local_port.is_some() {
@@ -855,7 +1471,7 @@ impl Config {
                     }
                 }
 
-                if let Some(timeout) = config.timeout.map(Duration::from_secs) {
+                if let Some(timeout) = config.tcp_timeout.or(config.timeout).map(Duration::from_secs) {
                     nsvr.set_timeout(timeout);
                 }
 
@@ -918,7 +1534,7 @@ impl Config {
                     }
                 }
 
-                if let Some(timeout) = config.timeout.map(Duration::from_secs) {
+                if let Some(timeout) = svr.timeout.map(Duration::from_secs) {
                     nsvr.set_timeout(timeout);
                 }
 
@@ -930,12 +1546,18 @@ impl Config {
                     nsvr.set_id(id);
                 }
 
+                if let Some(weight) = svr.weight {
+                    nsvr.set_weight(weight);
+                }
+
                 nconfig.server.push(nsvr);
             }
         }
 
         // Set timeout globally
-        if let Some(timeout) = config.timeout {
+        //
+        // `tcp_timeout` takes precedence over `timeout`
+        if let Some(timeout) = config.tcp_timeout.or(config.timeout) {
             let timeout = Duration::from_secs(timeout);
             // Set as a default timeout
             for svr in &mut nconfig.server {
@@ -957,6 +1579,13 @@ impl Config {
                         }
                     }
                 }
+                None if ma.starts_with('@') => match ma.parse::<ManagerAddr>() {
+                    Ok(m) => m,
+                    Err(err) => {
+                        let e = Error::new(ErrorKind::Invalid, "invalid `manager_address`", Some(err.to_string()));
+                        return Err(e);
+                    }
+                },
                 #[cfg(unix)]
                 None => ManagerAddr::from(PathBuf::from(ma)),
                 #[cfg(not(unix))]
@@ -1070,14 +1699,362 @@ impl Config {
         // Maximum associations to be kept simultaneously
         nconfig.udp_max_associations = config.udp_max_associations;
 
-        // RLIMIT_NOFILE
-        nconfig.nofile = config.nofile;
+        // UDP associations eviction
+        if let Some(p) = config.udp_eviction_policy {
+            match p.parse::<UdpEvictionPolicy>() {
+                Ok(p) => nconfig.udp_eviction_policy = p,
+                Err(..) => {
+                    let e = Error::new(
+                        ErrorKind::Malformed,
+                        "malformed `udp_eviction_policy`, must be one of `lru`, `lfu` and `oldest`",
+                        None,
+                    );
+                    return Err(e);
+                }
+            }
+        }
 
-        // Uses IPv6 first
-        if let Some(f) = config.ipv6_first {
+        // UDP source verification
+        if let Some(b) = config.udp_source_verify {
+            if b && !nconfig.mode.enable_tcp() {
+                let err = Error::new(
+                    ErrorKind::Invalid,
+                    "`udp_source_verify` requires TCP relay, sources are verified by TCP connections",
+                    None,
+                );
+                return Err(err);
+            }
+            nconfig.udp_source_verify = b;
+        }
+
+        // UDP send errors
+        if let Some(p) = config.udp_send_error_policy {
+            match p.parse::<UdpSendErrorPolicy>() {
+                Ok(p) => nconfig.udp_send_error_policy = p,
+                Err(..) => {
+                    let e = Error::new(
+                        ErrorKind::Malformed,
+                        "malformed `udp_send_error_policy`, must be one of `drop-packet` and `drop-association`",
+                        None,
+                    );
+                    return Err(e);
+                }
+            }
+        }
+
+        // RLIMIT_NOFILE
+        nconfig.nofile = config.nofile;
+
+        // Uses IPv6 first
+        if let Some(f) = config.ipv6_first {
             nconfig.ipv6_first = f;
         }
 
+        // IPv4-mapped IPv6 targets
+        if let Some(b) = config.unmap_ipv4 {
+            nconfig.unmap_ipv4 = b;
+        }
+
+        // AEAD chunk flooding
+        nconfig.min_avg_chunk_size = config.min_avg_chunk_size;
+
+        // Autoproxy
+        if let Some(b) = config.autoproxy {
+            nconfig.autoproxy = b;
+        }
+        nconfig.autoproxy_path = config.autoproxy_path.map(PathBuf::from);
+        if let Some(t) = config.autoproxy_direct_timeout {
+            if t == 0 {
+                let err = Error::new(
+                    ErrorKind::Invalid,
+                    "invalid `autoproxy_direct_timeout`, must be greater than 0",
+                    None,
+                );
+                return Err(err);
+            }
+            nconfig.autoproxy_direct_timeout = Some(Duration::from_secs(t));
+        }
+
+        // DPI evasion
+        nconfig.split_first_packet = config.split_first_packet;
+
+        // Duplicated servers
+        if let Some(p) = config.duplicate_server_policy {
+            match p.parse::<DuplicateServerPolicy>() {
+                Ok(p) => nconfig.duplicate_server_policy = p,
+                Err(..) => {
+                    let e = Error::new(
+                        ErrorKind::Malformed,
+                        "malformed `duplicate_server_policy`, must be one of `keep`, `dedup` and `error`",
+                        None,
+                    );
+                    return Err(e);
+                }
+            }
+        }
+
+        // Unresolvable servers
+        if let Some(p) = config.startup_resolve_policy {
+            match p.parse::<StartupResolvePolicy>() {
+                Ok(p) => nconfig.startup_resolve_policy = p,
+                Err(..) => {
+                    let e = Error::new(
+                        ErrorKind::Malformed,
+                        "malformed `startup_resolve_policy`, must be one of `fail`, `defer` and `skip`",
+                        None,
+                    );
+                    return Err(e);
+                }
+            }
+        }
+
+        // Canary server
+        if let Some(canary) = config.canary {
+            let server = match ServerConfig::from_url(&canary.server) {
+                Ok(s) => s,
+                Err(err) => {
+                    let err = Error::new(
+                        ErrorKind::Invalid,
+                        "invalid `canary.server`, must be a SIP002 URL",
+                        Some(format!("{}", err)),
+                    );
+                    return Err(err);
+                }
+            };
+
+            if !(canary.percentage > 0.0 && canary.percentage <= 100.0) {
+                let err = Error::new(
+                    ErrorKind::Invalid,
+                    "invalid `canary.percentage`, must be in (0, 100]",
+                    Some(format!("{}", canary.percentage)),
+                );
+                return Err(err);
+            }
+
+            nconfig.canary = Some(CanaryConfig {
+                server,
+                percentage: canary.percentage,
+            });
+        }
+        if let Some(s) = config.balancer_strategy {
+            match s.parse::<BalancerStrategy>() {
+                Ok(s) => nconfig.balancer_strategy = s,
+                Err(..) => {
+                    let e = Error::new(
+                        ErrorKind::Malformed,
+                        "malformed `balancer_strategy`, must be one of `latency` and `weighted`",
+                        None,
+                    );
+                    return Err(e);
+                }
+            }
+        }
+        nconfig.balancer_state_file = config.balancer_state_file.map(PathBuf::from);
+
+        if let Some(affinity) = config.server_affinity {
+            if affinity.ttl == 0 {
+                let err = Error::new(
+                    ErrorKind::Invalid,
+                    "invalid `server_affinity.ttl`, must be greater than 0",
+                    None,
+                );
+                return Err(err);
+            }
+            nconfig.server_affinity = Some(AffinityConfig {
+                ttl: Duration::from_secs(affinity.ttl),
+            });
+        }
+
+        // Strict mode
+        if let Some(b) = config.strict {
+            nconfig.strict = b;
+        }
+
+        // Per-IP connections ramp
+        if let Some(ramp) = config.per_ip_ramp {
+            if ramp.burst == 0 {
+                let err = Error::new(
+                    ErrorKind::Invalid,
+                    "invalid `per_ip_ramp.burst`, must be greater than 0",
+                    None,
+                );
+                return Err(err);
+            }
+
+            if ramp.max_delay_ms < ramp.step_delay_ms {
+                let err = Error::new(
+                    ErrorKind::Invalid,
+                    "invalid `per_ip_ramp.max_delay_ms`, must not be less than `step_delay_ms`",
+                    Some(format!("{} < {}", ramp.max_delay_ms, ramp.step_delay_ms)),
+                );
+                return Err(err);
+            }
+
+            if ramp.window_ms == Some(0) {
+                let err = Error::new(
+                    ErrorKind::Invalid,
+                    "invalid `per_ip_ramp.window_ms`, must be greater than 0",
+                    None,
+                );
+                return Err(err);
+            }
+
+            nconfig.per_ip_ramp = Some(RampConfig {
+                burst: ramp.burst,
+                step_delay: Duration::from_millis(ramp.step_delay_ms),
+                max_delay: Duration::from_millis(ramp.max_delay_ms),
+                window: ramp.window_ms.map(Duration::from_millis).unwrap_or(DEFAULT_RAMP_WINDOW),
+            });
+        }
+
+        // Verify servers' response
+        if let Some(b) = config.verify_server_response {
+            nconfig.verify_server_response = b;
+        }
+
+        // SOCKS5 server hint
+        if let Some(b) = config.socks5_server_hint {
+            nconfig.socks5_server_hint = b;
+        }
+
+        // Connect deadline
+        if let Some(d) = config.connect_deadline {
+            if d == 0 {
+                let err = Error::new(
+                    ErrorKind::Invalid,
+                    "invalid `connect_deadline`, must be greater than 0",
+                    None,
+                );
+                return Err(err);
+            }
+            nconfig.connect_deadline = Some(Duration::from_secs(d));
+        }
+
+        // Pending connects limit
+        if let Some(n) = config.max_pending_connects {
+            if n == 0 {
+                let err = Error::new(
+                    ErrorKind::Invalid,
+                    "invalid `max_pending_connects`, must be greater than 0",
+                    None,
+                );
+                return Err(err);
+            }
+            nconfig.max_pending_connects = Some(n);
+        }
+
+        // Captive portal check
+        if let Some(c) = config.captive_portal_check {
+            if c.interval == 0 {
+                let err = Error::new(
+                    ErrorKind::Invalid,
+                    "invalid `captive_portal_check.interval`, must be greater than 0",
+                    None,
+                );
+                return Err(err);
+            }
+
+            if c.threshold == Some(0) {
+                let err = Error::new(
+                    ErrorKind::Invalid,
+                    "invalid `captive_portal_check.threshold`, must be greater than 0",
+                    None,
+                );
+                return Err(err);
+            }
+
+            let check = CaptivePortalConfig {
+                url: c.url,
+                interval: Duration::from_secs(c.interval),
+                threshold: c.threshold.unwrap_or(DEFAULT_CAPTIVE_PORTAL_THRESHOLD),
+            };
+
+            if check.split_url().is_none() {
+                let err = Error::new(
+                    ErrorKind::Malformed,
+                    "malformed `captive_portal_check.url`, must be an http:// URL",
+                    Some(check.url),
+                );
+                return Err(err);
+            }
+
+            nconfig.captive_portal_check = Some(check);
+        }
+
+        // Plugin processes limit
+        if let Some(n) = config.max_plugin_processes {
+            if n == 0 {
+                let err = Error::new(
+                    ErrorKind::Invalid,
+                    "invalid `max_plugin_processes`, must be greater than 0",
+                    None,
+                );
+                return Err(err);
+            }
+            nconfig.max_plugin_processes = Some(n);
+        }
+
+        // Plugins' stderr
+        if let Some(level) = config.plugin_log_level {
+            match level.parse::<Level>() {
+                Ok(level) => nconfig.plugin_log_level = Some(level),
+                Err(..) => {
+                    let e = Error::new(
+                        ErrorKind::Malformed,
+                        "malformed `plugin_log_level`, must be one of `error`, `warn`, `info`, `debug` and `trace`",
+                        None,
+                    );
+                    return Err(e);
+                }
+            }
+        }
+
+        // Connection lifetime
+        if let Some(d) = config.max_connection_lifetime {
+            if d == 0 {
+                let err = Error::new(
+                    ErrorKind::Invalid,
+                    "invalid `max_connection_lifetime`, must be greater than 0",
+                    None,
+                );
+                return Err(err);
+            }
+            nconfig.max_connection_lifetime = Some(Duration::from_secs(d));
+        }
+
+        // DSCP
+        #[cfg(unix)]
+        if let Some(dscp) = config.outbound_dscp {
+            if dscp > 63 {
+                let err = Error::new(
+                    ErrorKind::Invalid,
+                    "invalid `outbound_dscp`, must be in range 0 - 63",
+                    Some(format!("{}", dscp)),
+                );
+                return Err(err);
+            }
+            nconfig.outbound_dscp = Some(dscp);
+        }
+
+        if let Some(b) = config.outbound_reuse_addr {
+            nconfig.outbound_reuse_addr = b;
+        }
+
+        // IPFIX collector
+        if let Some(collector) = config.netflow_collector {
+            match collector.parse::<SocketAddr>() {
+                Ok(addr) => nconfig.netflow_collector = Some(addr),
+                Err(..) => {
+                    let err = Error::new(
+                        ErrorKind::Invalid,
+                        "invalid `netflow_collector`",
+                        Some(format!("`{}` is not a valid ip:port", collector)),
+                    );
+                    return Err(err);
+                }
+            }
+        }
+
         Ok(nconfig)
     }
 
@@ -1105,6 +2082,45 @@ impl Config {
         false
     }
 
+    /// Apply `duplicate_server_policy` to servers
+    ///
+    /// This should be called after servers from all sources have been merged into `server`.
+    pub fn dedup_servers(&mut self) -> Result<(), Error> {
+        match self.duplicate_server_policy {
+            DuplicateServerPolicy::Keep => Ok(()),
+            DuplicateServerPolicy::Dedup => {
+                let total = self.server.len();
+
+                let mut servers: Vec<ServerConfig> = Vec::with_capacity(total);
+                for svr_cfg in self.server.drain(..) {
+                    if !servers.iter().any(|s| is_duplicated_server(s, &svr_cfg)) {
+                        servers.push(svr_cfg);
+                    }
+                }
+
+                if servers.len() < total {
+                    info!("removed {} duplicated servers", total - servers.len());
+                }
+
+                self.server = servers;
+                Ok(())
+            }
+            DuplicateServerPolicy::Error => {
+                for (idx, svr_cfg) in self.server.iter().enumerate() {
+                    if self.server[..idx].iter().any(|s| is_duplicated_server(s, svr_cfg)) {
+                        let err = Error::new(
+                            ErrorKind::Invalid,
+                            "duplicated servers",
+                            Some(format!("server {} is configured more than once", svr_cfg.addr())),
+                        );
+                        return Err(err);
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
     /// Check if all required fields are already set
     pub fn check_integrity(&self) -> Result<(), Error> {
         if self.config_type.is_local() {
@@ -1126,6 +2142,19 @@ impl Config {
                         );
                         return Err(err);
                     }
+
+                    if self
+                        .local_addr_fallback
+                        .iter()
+                        .any(|fallback| fallback.port() != addr.port())
+                    {
+                        let err = Error::new(
+                            ErrorKind::Invalid,
+                            "fallbacks of `local_address` must have the same port as `local_port`",
+                            None,
+                        );
+                        return Err(err);
+                    }
                 }
             }
 
@@ -1190,6 +2219,16 @@ impl Config {
                     let err = Error::new(ErrorKind::Malformed, "`plugin` shouldn't be an empty string", None);
                     return Err(err);
                 }
+
+                // TCP connections come from the plugin, sources of UDP clients are unknown
+                if self.udp_source_verify {
+                    let err = Error::new(
+                        ErrorKind::Invalid,
+                        "`udp_source_verify` couldn't be used with `plugin`, TCP connections come from the plugin",
+                        None,
+                    );
+                    return Err(err);
+                }
             }
 
             // Server's domain name shouldn't be an empty string
@@ -1246,6 +2285,55 @@ impl Config {
             }
         }
 
+        if self.strict {
+            self.check_strict()?;
+        }
+
+        Ok(())
+    }
+
+    /// Check configurations refused by strict mode
+    fn check_strict(&self) -> Result<(), Error> {
+        const STRICT_MIN_PASSWORD_LEN: usize = 8;
+
+        let strict_error = |desc: &'static str, detail: String| -> Result<(), Error> {
+            Err(Error::new(ErrorKind::Invalid, desc, Some(detail)))
+        };
+
+        let check_method = |method: CipherKind, owner: &dyn Display| -> Result<(), Error> {
+            match method.category() {
+                CipherCategory::Aead => Ok(()),
+                CipherCategory::None => strict_error(
+                    "strict mode refuses methods without encryption",
+                    format!("{} uses method {}", owner, method),
+                ),
+                #[allow(unreachable_patterns)]
+                _ => strict_error(
+                    "strict mode refuses deprecated stream ciphers",
+                    format!("{} uses method {}", owner, method),
+                ),
+            }
+        };
+
+        let canary_server = self.canary.as_ref().map(|c| &c.server);
+        for server in self.server.iter().chain(canary_server) {
+            let owner = format!("server {}", server.addr());
+            check_method(server.method(), &owner)?;
+
+            if server.password().chars().count() < STRICT_MIN_PASSWORD_LEN {
+                return strict_error(
+                    "strict mode refuses short passwords",
+                    format!("{} has a password shorter than {}", owner, STRICT_MIN_PASSWORD_LEN),
+                );
+            }
+        }
+
+        if let Some(ref manager) = self.manager {
+            if let Some(method) = manager.method {
+                check_method(method, &"manager's default method")?;
+            }
+        }
+
         Ok(())
     }
 }
@@ -1257,23 +2345,29 @@ impl fmt::Display for Config {
         let mut jconf = SSConfig::default();
 
         if let Some(ref client) = self.local_addr {
-            match *client {
-                ServerAddr::SocketAddr(ref sa) => {
-                    jconf.local_address = Some(sa.ip().to_string());
-                    jconf.local_port = Some(sa.port());
-                }
-                ServerAddr::DomainName(ref dname, port) => {
-                    jconf.local_address = Some(dname.to_owned());
-                    jconf.local_port = Some(port);
-                }
-            }
+            let host = |addr: &ServerAddr| match *addr {
+                ServerAddr::SocketAddr(ref sa) => sa.ip().to_string(),
+                ServerAddr::DomainName(ref dname, ..) => dname.to_owned(),
+            };
+
+            jconf.local_address = if self.local_addr_fallback.is_empty() {
+                Some(SSLocalAddress::Single(host(client)))
+            } else {
+                let mut las = vec![host(client)];
+                las.extend(self.local_addr_fallback.iter().map(host));
+                Some(SSLocalAddress::Fallback(las))
+            };
+            jconf.local_port = Some(client.port());
         }
 
         // Servers
         match self.server.len() {
             0 => {}
             // For 1 server, uses standard configure format
-            1 if self.server[0].id().is_none() && self.server[0].remarks().is_none() => {
+            1 if self.server[0].id().is_none()
+                && self.server[0].remarks().is_none()
+                && self.server[0].weight().is_none() =>
+            {
                 let svr = &self.server[0];
 
                 jconf.server = Some(match *svr.addr() {
@@ -1326,6 +2420,7 @@ impl fmt::Display for Config {
                         timeout: svr.timeout().map(|t| t.as_secs()),
                         remarks: svr.remarks().map(ToOwned::to_owned),
                         id: svr.id().map(ToOwned::to_owned),
+                        weight: svr.weight(),
                     });
                 }
 
@@ -1339,6 +2434,8 @@ impl fmt::Display for Config {
                 ManagerAddr::DomainName(ref dname, ..) => dname.clone(),
                 #[cfg(unix)]
                 ManagerAddr::UnixSocketAddr(ref path) => path.display().to_string(),
+                #[cfg(target_os = "linux")]
+                ManagerAddr::UnixAbstractAddr(..) => m.addr.to_string(),
             });
 
             jconf.manager_port = match m.addr {
@@ -1346,6 +2443,8 @@ impl fmt::Display for Config {
                 ManagerAddr::DomainName(.., port) => Some(port),
                 #[cfg(unix)]
                 ManagerAddr::UnixSocketAddr(..) => None,
+                #[cfg(target_os = "linux")]
+                ManagerAddr::UnixAbstractAddr(..) => None,
             };
         }
 
@@ -1364,12 +2463,99 @@ impl fmt::Display for Config {
 
         jconf.udp_max_associations = self.udp_max_associations;
 
+        if self.udp_eviction_policy != UdpEvictionPolicy::default() {
+            jconf.udp_eviction_policy = Some(self.udp_eviction_policy.to_string());
+        }
+
+        if self.udp_send_error_policy != UdpSendErrorPolicy::default() {
+            jconf.udp_send_error_policy = Some(self.udp_send_error_policy.to_string());
+        }
+
+        if self.udp_source_verify {
+            jconf.udp_source_verify = Some(self.udp_source_verify);
+        }
+
         jconf.nofile = self.nofile;
 
         if self.ipv6_first {
             jconf.ipv6_first = Some(self.ipv6_first);
         }
 
+        if !self.unmap_ipv4 {
+            jconf.unmap_ipv4 = Some(self.unmap_ipv4);
+        }
+
+        jconf.min_avg_chunk_size = self.min_avg_chunk_size;
+
+        jconf.netflow_collector = self.netflow_collector.map(|addr| addr.to_string());
+
+        if self.autoproxy {
+            jconf.autoproxy = Some(self.autoproxy);
+        }
+        jconf.autoproxy_path = self.autoproxy_path.as_ref().map(|p| p.display().to_string());
+        jconf.autoproxy_direct_timeout = self.autoproxy_direct_timeout.map(|t| t.as_secs());
+
+        jconf.split_first_packet = self.split_first_packet;
+
+        if self.duplicate_server_policy != DuplicateServerPolicy::default() {
+            jconf.duplicate_server_policy = Some(self.duplicate_server_policy.to_string());
+        }
+
+        if self.startup_resolve_policy != StartupResolvePolicy::default() {
+            jconf.startup_resolve_policy = Some(self.startup_resolve_policy.to_string());
+        }
+
+        jconf.canary = self.canary.as_ref().map(|c| SSCanaryConfig {
+            server: c.server.to_url(),
+            percentage: c.percentage,
+        });
+        if self.balancer_strategy != BalancerStrategy::default() {
+            jconf.balancer_strategy = Some(self.balancer_strategy.to_string());
+        }
+        jconf.balancer_state_file = self.balancer_state_file.as_ref().map(|p| p.display().to_string());
+        jconf.server_affinity = self
+            .server_affinity
+            .as_ref()
+            .map(|a| SSAffinityConfig { ttl: a.ttl.as_secs() });
+
+        #[cfg(unix)]
+        {
+            jconf.outbound_dscp = self.outbound_dscp;
+        }
+        if self.outbound_reuse_addr {
+            jconf.outbound_reuse_addr = Some(self.outbound_reuse_addr);
+        }
+
+        if self.strict {
+            jconf.strict = Some(self.strict);
+        }
+
+        jconf.per_ip_ramp = self.per_ip_ramp.as_ref().map(|r| SSRampConfig {
+            burst: r.burst,
+            step_delay_ms: r.step_delay.as_millis() as u64,
+            max_delay_ms: r.max_delay.as_millis() as u64,
+            window_ms: Some(r.window.as_millis() as u64),
+        });
+
+        if self.verify_server_response {
+            jconf.verify_server_response = Some(self.verify_server_response);
+        }
+
+        if self.socks5_server_hint {
+            jconf.socks5_server_hint = Some(self.socks5_server_hint);
+        }
+
+        jconf.connect_deadline = self.connect_deadline.map(|d| d.as_secs());
+        jconf.max_pending_connects = self.max_pending_connects;
+        jconf.captive_portal_check = self.captive_portal_check.as_ref().map(|c| SSCaptivePortalConfig {
+            url: c.url.clone(),
+            interval: c.interval.as_secs(),
+            threshold: Some(c.threshold),
+        });
+        jconf.max_plugin_processes = self.max_plugin_processes;
+        jconf.plugin_log_level = self.plugin_log_level.map(|l| l.as_str().to_lowercase());
+        jconf.max_connection_lifetime = self.max_connection_lifetime.map(|d| d.as_secs());
+
         write!(f, "{}", json5::to_string(&jconf).unwrap())
     }
 }
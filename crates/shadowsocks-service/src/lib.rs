@@ -65,6 +65,7 @@ pub mod local;
 #[cfg(feature = "manager")]
 pub mod manager;
 pub mod net;
+pub mod plugin;
 #[cfg(feature = "server")]
 pub mod server;
 mod sys;
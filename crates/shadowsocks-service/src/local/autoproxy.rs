@@ -0,0 +1,111 @@
+//! Learned list of domain names that have to be proxied
+//!
+//! In autoproxy mode, local servers try to connect to domain name targets directly first. If the direct connection
+//! couldn't be established, the target will be connected through proxy and its domain name will be remembered,
+//! so the following connections to that domain name will go through proxy immediately.
+//!
+//! Learned domain names could be persisted in a file, one domain name per line.
+//!
+//! Autoproxy mode has to be enabled explicitly. Domain names of targets that haven't been learned are resolved by the
+//! local DNS resolver for connecting directly, so these queries could be seen by the local network (DNS leak). Only
+//! failures of establishing connections are detected, targets that are reset after the connection is established
+//! (by SNI-based blocking for example) are not learned.
+
+use std::{
+    collections::HashSet,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, ErrorKind, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use log::{debug, error};
+use spin::Mutex as SpinMutex;
+use tokio::task;
+
+/// Default timeout for connecting to targets directly
+///
+/// Censored targets usually don't response to SYN, so this should be much shorter than the default connect timeout
+pub const DEFAULT_AUTOPROXY_DIRECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Domain names that have been learned to be proxied
+pub struct AutoProxyDomains {
+    domains: SpinMutex<HashSet<String>>,
+    path: Option<PathBuf>,
+}
+
+impl AutoProxyDomains {
+    /// Create an empty list which only lives in memory
+    pub fn new() -> AutoProxyDomains {
+        AutoProxyDomains {
+            domains: SpinMutex::new(HashSet::new()),
+            path: None,
+        }
+    }
+
+    /// Load learned domain names from `path`, and persist the newly learned ones into it
+    ///
+    /// The file will be created when the first domain name is learned if it doesn't exist.
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> io::Result<AutoProxyDomains> {
+        let path = path.as_ref();
+
+        let mut domains = HashSet::new();
+        match File::open(path) {
+            Ok(fp) => {
+                for line in BufReader::new(fp).lines() {
+                    let line = line?;
+                    let domain = line.trim();
+                    if domain.is_empty() || domain.starts_with('#') {
+                        continue;
+                    }
+                    domains.insert(domain.to_ascii_lowercase());
+                }
+            }
+            Err(ref err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+
+        debug!("loaded {} autoproxy domains from {}", domains.len(), path.display());
+
+        Ok(AutoProxyDomains {
+            domains: SpinMutex::new(domains),
+            path: Some(path.to_owned()),
+        })
+    }
+
+    /// Check if `domain` has been learned to be proxied
+    pub fn contains(&self, domain: &str) -> bool {
+        let domain = domain.to_ascii_lowercase();
+        self.domains.lock().contains(&domain)
+    }
+
+    /// Remember that `domain` has to be proxied
+    pub async fn insert(&self, domain: &str) {
+        let domain = domain.to_ascii_lowercase();
+
+        if !self.domains.lock().insert(domain.clone()) {
+            return;
+        }
+
+        debug!("autoproxy learned {} should be proxied", domain);
+
+        if let Some(ref path) = self.path {
+            let path = path.clone();
+            let result = task::spawn_blocking(move || {
+                let mut fp = OpenOptions::new().create(true).append(true).open(&path)?;
+                writeln!(fp, "{}", domain)
+            })
+            .await;
+
+            match result {
+                Ok(Ok(..)) => {}
+                Ok(Err(err)) => {
+                    error!("failed to persist autoproxy domain, error: {}", err);
+                }
+                Err(err) => {
+                    error!("failed to persist autoproxy domain, error: {}", err);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Captive portal detection
+//!
+//! Networks behind a captive portal (hotels, airports, ...) intercept all connections until the user has logged in
+//! on the portal's page. Connections through servers fail in confusing ways, and servers would be reported as
+//! failed one by one. A connectivity check URL, which responds `204 No Content` on open networks, is requested
+//! directly from time to time. A redirect (3xx), or a `200 OK` with a body (the portal's page), means there is a portal.
+//! Other responses, like errors of the check server, are not counted as portals.
+//!
+//! TCP connections through servers are paused until the portal is gone. UDP associations are not paused, packets sent
+//! to servers are simply dropped by the portal.
+
+use std::{io, net::IpAddr, str, sync::Arc, time::Duration};
+
+use log::{debug, info, warn};
+use shadowsocks::{net::TcpStream, relay::socks5::Address};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    time,
+};
+
+use crate::config::CaptivePortalConfig;
+
+use super::context::ServiceContext;
+
+/// Timeout for requesting the connectivity check URL
+pub const CAPTIVE_PORTAL_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Check if the network is behind a captive portal by requesting `config.url`
+pub async fn check_captive_portal(context: &ServiceContext, config: &CaptivePortalConfig) -> io::Result<bool> {
+    let (host, port, path) = config
+        .split_url()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid captive portal check url"))?;
+
+    let addr = match host.parse::<IpAddr>() {
+        Ok(ip) => Address::SocketAddress((ip, port).into()),
+        Err(..) => Address::DomainNameAddress(host.to_owned(), port),
+    };
+
+    let mut stream =
+        TcpStream::connect_remote_with_opts(context.context_ref(), &addr, context.connect_opts_ref()).await?;
+
+    let host_header = match (addr, port) {
+        (Address::SocketAddress(sa), _) if sa.is_ipv6() => format!("[{}]:{}", host, port),
+        (_, 80) => host.to_owned(),
+        _ => format!("{}:{}", host, port),
+    };
+
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: shadowsocks-rust\r\nConnection: close\r\n\r\n",
+        path, host_header
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    // Status line and headers, and possibly the beginning of the body
+    let mut buffer = [0u8; 4096];
+    let mut n = 0;
+    let header_end = loop {
+        if let Some(pos) = buffer[..n].windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        if n == buffer.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "captive portal check response headers too long",
+            ));
+        }
+        let rn = stream.read(&mut buffer[n..]).await?;
+        if rn == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "captive portal check response truncated",
+            ));
+        }
+        n += rn;
+    };
+
+    let head = str::from_utf8(&buffer[..header_end])
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid captive portal check response"))?;
+    let mut lines = head.split("\r\n");
+
+    let status = lines
+        .next()
+        .and_then(|s| s.split_whitespace().nth(1))
+        .and_then(|s| s.parse::<u16>().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid captive portal check response"))?;
+
+    match status {
+        // Redirect to the portal's login page
+        300..=399 => Ok(true),
+        // Portal's page served in place of the check URL
+        200 => {
+            let mut content_length = None;
+            let mut chunked = false;
+            for line in lines {
+                let (name, value) = match line.find(':') {
+                    Some(pos) => (line[..pos].trim(), line[pos + 1..].trim()),
+                    None => continue,
+                };
+                if name.eq_ignore_ascii_case("Content-Length") {
+                    content_length = value.parse::<u64>().ok();
+                } else if name.eq_ignore_ascii_case("Transfer-Encoding") {
+                    chunked = value.to_ascii_lowercase().contains("chunked");
+                }
+            }
+
+            match content_length {
+                Some(len) => Ok(len > 0),
+                None => {
+                    // Chunked body always has the last chunk "0\r\n\r\n", which is empty
+                    let body = &buffer[header_end..n];
+                    let has_body = if !body.is_empty() {
+                        !chunked || !body.starts_with(b"0\r\n")
+                    } else {
+                        let mut byte = [0u8; 1];
+                        let rn = stream.read(&mut byte).await?;
+                        rn > 0 && (!chunked || byte[0] != b'0')
+                    };
+                    Ok(has_body)
+                }
+            }
+        }
+        // 204 No Content, or errors of the check server
+        _ => Ok(false),
+    }
+}
+
+/// Check captive portal every `config.interval`, proxying is paused while it is detected
+///
+/// Proxying is paused after `config.threshold` consecutive detections, and resumed on the first check without a portal.
+/// State is kept if the check fails, for example, the network is down.
+pub async fn captive_portal_task(context: Arc<ServiceContext>, config: CaptivePortalConfig) -> io::Result<()> {
+    let mut detections = 0;
+
+    loop {
+        match time::timeout(CAPTIVE_PORTAL_CHECK_TIMEOUT, check_captive_portal(&context, &config)).await {
+            Ok(Ok(detected)) => {
+                if detected {
+                    detections += 1;
+                    debug!(
+                        "captive portal detected by {}, {}/{} times",
+                        config.url, detections, config.threshold
+                    );
+                } else {
+                    detections = 0;
+                }
+
+                let detected = detections >= config.threshold;
+                let was_detected = context.set_captive_portal_detected(detected);
+                if detected && !was_detected {
+                    warn!(
+                        "captive portal detected by {}, proxying paused until it is cleared",
+                        config.url
+                    );
+                } else if !detected && was_detected {
+                    info!("captive portal cleared, proxying resumed");
+                }
+            }
+            Ok(Err(err)) => {
+                debug!("captive portal check {} failed, error: {}", config.url, err);
+            }
+            Err(..) => {
+                debug!("captive portal check {} timeout", config.url);
+            }
+        }
+
+        time::sleep(config.interval).await;
+    }
+}
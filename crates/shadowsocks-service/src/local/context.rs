@@ -1,9 +1,16 @@
 //! Shadowsocks Local Server Context
 
-use std::sync::Arc;
 #[cfg(feature = "local-dns")]
-use std::{net::IpAddr, time::Duration};
+use std::net::IpAddr;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
+use arc_swap::ArcSwapOption;
 #[cfg(feature = "local-dns")]
 use lru_time_cache::LruCache;
 use shadowsocks::{
@@ -15,8 +22,15 @@ use shadowsocks::{
 };
 #[cfg(feature = "local-dns")]
 use tokio::sync::Mutex;
+use tokio::sync::Semaphore;
+
+use crate::{
+    acl::AccessControl,
+    config::{UdpEvictionPolicy, UdpSendErrorPolicy},
+    net::{udp_send_error::UdpSendErrorStat, FlowStat},
+};
 
-use crate::{acl::AccessControl, net::FlowStat};
+use super::autoproxy::{AutoProxyDomains, DEFAULT_AUTOPROXY_DIRECT_TIMEOUT};
 
 /// Local Service Context
 pub struct ServiceContext {
@@ -24,12 +38,47 @@ pub struct ServiceContext {
     connect_opts: ConnectOpts,
     accept_opts: AcceptOpts,
 
-    // Access Control
-    acl: Option<AccessControl>,
+    // Access Control, could be replaced by `reload_acl`
+    acl: ArcSwapOption<AccessControl>,
+
+    // Domain names learned to be proxied in autoproxy mode
+    autoproxy_domains: Option<AutoProxyDomains>,
+    autoproxy_direct_timeout: Duration,
 
     // Flow statistic report
     flow_stat: Arc<FlowStat>,
 
+    // Split the first packet of bypassed TCP tunnels into segments with this size
+    split_first_packet: Option<usize>,
+
+    // Wait for the first response of servers before trusting the connections
+    verify_server_response: bool,
+
+    // SOCKS5 clients could choose the server by a hint in the username
+    socks5_server_hint: bool,
+
+    // TCP tunnels are closed after they have been relayed for this long
+    max_connection_lifetime: Option<Duration>,
+
+    // Upper bound of establishing an outbound connection, including all attempts
+    connect_deadline: Option<Duration>,
+
+    // Limits outbound connections being established concurrently
+    connect_permits: Option<Arc<Semaphore>>,
+
+    // Proxying is paused while the network is behind a captive portal
+    captive_portal_detected: AtomicBool,
+
+    // Connect IPv4-mapped IPv6 targets with IPv4 addresses
+    unmap_ipv4: bool,
+
+    // Choose the UDP association to evict when the association table is full
+    udp_eviction_policy: UdpEvictionPolicy,
+
+    // UDP send errors
+    udp_send_error_policy: UdpSendErrorPolicy,
+    udp_send_error_stat: UdpSendErrorStat,
+
     // For DNS relay's ACL domain name reverse lookup -- whether the IP shall be forwarded
     #[cfg(feature = "local-dns")]
     reverse_lookup_cache: Mutex<LruCache<IpAddr, bool>>,
@@ -42,8 +91,21 @@ impl ServiceContext {
             context: Context::new_shared(ServerType::Local),
             connect_opts: ConnectOpts::default(),
             accept_opts: AcceptOpts::default(),
-            acl: None,
+            acl: ArcSwapOption::empty(),
+            autoproxy_domains: None,
+            autoproxy_direct_timeout: DEFAULT_AUTOPROXY_DIRECT_TIMEOUT,
             flow_stat: Arc::new(FlowStat::new()),
+            split_first_packet: None,
+            verify_server_response: false,
+            socks5_server_hint: false,
+            max_connection_lifetime: None,
+            connect_deadline: None,
+            connect_permits: None,
+            captive_portal_detected: AtomicBool::new(false),
+            unmap_ipv4: true,
+            udp_eviction_policy: UdpEvictionPolicy::default(),
+            udp_send_error_policy: UdpSendErrorPolicy::default(),
+            udp_send_error_stat: UdpSendErrorStat::new(),
             #[cfg(feature = "local-dns")]
             reverse_lookup_cache: Mutex::new(LruCache::with_expiry_duration(Duration::from_secs(3 * 24 * 60 * 60))),
         }
@@ -81,12 +143,41 @@ impl ServiceContext {
 
     /// Set Access Control List
     pub fn set_acl(&mut self, acl: AccessControl) {
-        self.acl = Some(acl);
+        self.acl = ArcSwapOption::from_pointee(acl);
     }
 
-    /// Get Access Control List reference
-    pub fn acl(&self) -> Option<&AccessControl> {
-        self.acl.as_ref()
+    /// Get the current Access Control List
+    pub fn acl(&self) -> Option<Arc<AccessControl>> {
+        self.acl.load_full()
+    }
+
+    /// Replace Access Control List, connections that have already been established are not affected
+    pub async fn reload_acl(&self, acl: AccessControl) {
+        self.acl.store(Some(Arc::new(acl)));
+
+        // Cached decisions were made by the previous rules
+        #[cfg(feature = "local-dns")]
+        self.reverse_lookup_cache.lock().await.clear();
+    }
+
+    /// Enable autoproxy mode with domain names that have already been learned
+    pub fn set_autoproxy_domains(&mut self, domains: AutoProxyDomains) {
+        self.autoproxy_domains = Some(domains);
+    }
+
+    /// Get learned domain names if autoproxy mode is enabled
+    pub fn autoproxy_domains(&self) -> Option<&AutoProxyDomains> {
+        self.autoproxy_domains.as_ref()
+    }
+
+    /// Set timeout for connecting to targets directly in autoproxy mode
+    pub fn set_autoproxy_direct_timeout(&mut self, timeout: Duration) {
+        self.autoproxy_direct_timeout = timeout;
+    }
+
+    /// Get timeout for connecting to targets directly in autoproxy mode
+    pub fn autoproxy_direct_timeout(&self) -> Duration {
+        self.autoproxy_direct_timeout
     }
 
     /// Get cloned flow statistic
@@ -99,6 +190,119 @@ impl ServiceContext {
         self.flow_stat.as_ref()
     }
 
+    /// Set the size of segments that the first packet of bypassed TCP tunnels will be split into
+    pub fn set_split_first_packet(&mut self, size: usize) {
+        self.split_first_packet = Some(size);
+    }
+
+    /// Get the size of segments that the first packet of bypassed TCP tunnels will be split into
+    pub fn split_first_packet(&self) -> Option<usize> {
+        self.split_first_packet
+    }
+
+    /// Set whether servers that close TCP tunnels before responding to the first packet should be reported as failed
+    pub fn set_verify_server_response(&mut self, verify: bool) {
+        self.verify_server_response = verify;
+    }
+
+    /// Check whether servers that close TCP tunnels before responding to the first packet should be reported as failed
+    pub fn verify_server_response(&self) -> bool {
+        self.verify_server_response
+    }
+
+    /// Set whether SOCKS5 clients could choose the server by a `server:<name>` field in the username
+    pub fn set_socks5_server_hint(&mut self, enabled: bool) {
+        self.socks5_server_hint = enabled;
+    }
+
+    /// Check whether SOCKS5 clients could choose the server by a `server:<name>` field in the username
+    pub fn socks5_server_hint(&self) -> bool {
+        self.socks5_server_hint
+    }
+
+    /// Set the maximum lifetime of TCP tunnels, regardless of activity
+    pub fn set_max_connection_lifetime(&mut self, lifetime: Duration) {
+        self.max_connection_lifetime = Some(lifetime);
+    }
+
+    /// Get the maximum lifetime of TCP tunnels
+    pub fn max_connection_lifetime(&self) -> Option<Duration> {
+        self.max_connection_lifetime
+    }
+
+    /// Set the upper bound of time for establishing an outbound connection
+    pub fn set_connect_deadline(&mut self, deadline: Duration) {
+        self.connect_deadline = Some(deadline);
+    }
+
+    /// Get the upper bound of time for establishing an outbound connection
+    pub fn connect_deadline(&self) -> Option<Duration> {
+        self.connect_deadline
+    }
+
+    /// Set the maximum number of outbound connections being established concurrently
+    pub fn set_max_pending_connects(&mut self, n: usize) {
+        self.connect_permits = Some(Arc::new(Semaphore::new(n)));
+    }
+
+    /// Get permits for establishing outbound connections, `None` if unlimited
+    ///
+    /// Each pending connect holds one permit until it is finished.
+    pub fn connect_permits(&self) -> Option<Arc<Semaphore>> {
+        self.connect_permits.clone()
+    }
+
+    /// Set whether the network is behind a captive portal, returns the previous state
+    pub fn set_captive_portal_detected(&self, detected: bool) -> bool {
+        self.captive_portal_detected.swap(detected, Ordering::AcqRel)
+    }
+
+    /// Check whether the network is behind a captive portal
+    pub fn captive_portal_detected(&self) -> bool {
+        self.captive_portal_detected.load(Ordering::Acquire)
+    }
+
+    /// Set whether IPv4-mapped IPv6 targets should be connected with IPv4 addresses
+    pub fn set_unmap_ipv4(&mut self, unmap: bool) {
+        self.unmap_ipv4 = unmap;
+    }
+
+    /// Check whether IPv4-mapped IPv6 targets should be connected with IPv4 addresses
+    pub fn unmap_ipv4(&self) -> bool {
+        self.unmap_ipv4
+    }
+
+    /// Set policy for choosing the UDP association to evict when the association table is full
+    pub fn set_udp_eviction_policy(&mut self, policy: UdpEvictionPolicy) {
+        self.udp_eviction_policy = policy;
+    }
+
+    /// Get policy for choosing the UDP association to evict when the association table is full
+    pub fn udp_eviction_policy(&self) -> UdpEvictionPolicy {
+        self.udp_eviction_policy
+    }
+
+    /// Set policy for errors of sending UDP packets
+    pub fn set_udp_send_error_policy(&mut self, policy: UdpSendErrorPolicy) {
+        self.udp_send_error_policy = policy;
+    }
+
+    /// Get policy for errors of sending UDP packets
+    pub fn udp_send_error_policy(&self) -> UdpSendErrorPolicy {
+        self.udp_send_error_policy
+    }
+
+    /// Get statistic reference of UDP send errors
+    pub fn udp_send_error_stat_ref(&self) -> &UdpSendErrorStat {
+        &self.udp_send_error_stat
+    }
+
+    /// Set minimum average size of AEAD chunks received from servers in one connection
+    pub fn set_min_avg_chunk_size(&mut self, size: usize) {
+        let context = Arc::get_mut(&mut self.context).expect("cannot set min_avg_chunk_size on a shared context");
+        context.set_min_avg_chunk_size(size)
+    }
+
     /// Set customized DNS resolver
     pub fn set_dns_resolver(&mut self, resolver: Arc<DnsResolver>) {
         let context = Arc::get_mut(&mut self.context).expect("cannot set DNS resolver on a shared context");
@@ -112,9 +316,9 @@ impl ServiceContext {
 
     /// Check if target should be bypassed
     pub async fn check_target_bypassed(&self, addr: &Address) -> bool {
-        match self.acl {
+        match self.acl() {
             None => false,
-            Some(ref acl) => {
+            Some(acl) => {
                 #[cfg(feature = "local-dns")]
                 {
                     if let Address::SocketAddress(ref saddr) = addr {
@@ -136,10 +340,10 @@ impl ServiceContext {
     #[cfg(feature = "local-dns")]
     pub async fn add_to_reverse_lookup_cache(&self, addr: IpAddr, forward: bool) {
         let is_exception = forward
-            != match self.acl {
+            != match self.acl() {
                 // Proxy everything by default
                 None => true,
-                Some(ref a) => a.check_ip_in_proxy_list(&addr),
+                Some(a) => a.check_ip_in_proxy_list(&addr),
             };
         let mut reverse_lookup_cache = self.reverse_lookup_cache.lock().await;
         match reverse_lookup_cache.get_mut(&addr) {
@@ -487,7 +487,7 @@ impl DnsClient {
         // Start querying name servers
         debug!("DNS lookup {:?} {}", query.query_type(), query.name());
 
-        match should_forward_by_query(self.context.acl(), query) {
+        match should_forward_by_query(self.context.acl().as_deref(), query) {
             Some(true) => {
                 let remote_response = self.lookup_remote(query, remote_addr).await;
                 trace!("pick remote response (query): {:?}", remote_response);
@@ -503,7 +503,7 @@ impl DnsClient {
 
         let decider = async {
             let local_response = self.lookup_local(query, local_addr).await;
-            if should_forward_by_response(self.context.acl(), &local_response, query) {
+            if should_forward_by_response(self.context.acl().as_deref(), &local_response, query) {
                 None
             } else {
                 Some(local_response)
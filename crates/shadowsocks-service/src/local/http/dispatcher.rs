@@ -89,8 +89,9 @@ impl HttpDispatcher {
             // Connect to Shadowsocks' remote
             //
             // FIXME: What STATUS should I return for connection error?
-            let server = self.balancer.best_tcp_server();
-            let stream = AutoProxyClientStream::connect(self.context, server.as_ref(), &host).await?;
+            let server = self.balancer.best_tcp_server_for(self.client_addr.ip());
+            let context = self.context;
+            let stream = AutoProxyClientStream::connect(context.clone(), server.as_ref(), &host).await?;
 
             debug!("CONNECT relay connected {} <-> {}", self.client_addr, host);
 
@@ -112,7 +113,8 @@ impl HttpDispatcher {
                         let (mut shadow_reader, mut shadow_writer) = stream.into_split();
 
                         let _ = establish_tcp_tunnel(
-                            server.server_config(),
+                            &context,
+                            &server,
                             &mut plain_reader,
                             &mut plain_writer,
                             &mut shadow_reader,
@@ -174,7 +176,7 @@ impl HttpDispatcher {
                 // Keep connections for clients in ServerScore::client
                 //
                 // client instance is kept for Keep-Alive connections
-                let server = self.balancer.best_tcp_server();
+                let server = self.balancer.best_tcp_server_for(self.client_addr.ip());
                 let client = self.proxy_client_cache.get_connected(&server).await;
 
                 match client.request(self.req).await {
@@ -2,9 +2,13 @@
 
 pub use self::{
     ping_balancer::{PingBalancer, PingBalancerBuilder, ServerType},
+    server_affinity::ServerAffinity,
     server_data::{ServerIdent, ServerScore},
+    state_file::BalancerState,
 };
 
 pub mod ping_balancer;
+pub mod server_affinity;
 pub mod server_data;
 pub mod server_stat;
+pub mod state_file;
@@ -1,10 +1,18 @@
 //! Load Balancer chooses server by statistic latency data collected from active probing
+//!
+//! With the `Weighted` strategy, servers are chosen randomly by their weights instead (servers without `weight` have
+//! weight 1). Unhealthy servers are not chosen until they pass a probe again, the best server is chosen if all of
+//! them are unhealthy.
+//!
+//! Recent scores could be persisted in a state file, which seeds the balancer after restarting.
 
 use std::{
+    collections::HashMap,
     fmt::{self, Debug},
     future::Future,
     io,
-    net::{Ipv4Addr, SocketAddr},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
     sync::{
         atomic::{AtomicUsize, Ordering},
         Arc,
@@ -14,22 +22,30 @@ use std::{
 
 use byte_string::ByteStr;
 use futures::future::{self, AbortHandle};
-use log::{debug, log, trace, Level};
+use log::{debug, log, trace, warn, Level};
+use rand::{thread_rng, Rng};
 use shadowsocks::relay::{
     socks5::Address,
     tcprelay::proxy_stream::ProxyClientStream,
     udprelay::{proxy_socket::ProxySocket, MAXIMUM_UDP_PAYLOAD_SIZE},
 };
+use spin::Mutex as SpinMutex;
 use tokio::{
     io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    task,
     time,
 };
 
-use crate::{config::Mode, local::context::ServiceContext};
+use crate::{
+    config::{BalancerStrategy, Mode},
+    local::context::ServiceContext,
+};
 
 use super::{
+    server_affinity::ServerAffinity,
     server_data::ServerIdent,
     server_stat::{Score, DEFAULT_CHECK_INTERVAL_SEC, DEFAULT_CHECK_TIMEOUT_SEC},
+    state_file::BalancerState,
 };
 
 /// Remote Server Type
@@ -48,9 +64,19 @@ impl fmt::Display for ServerType {
     }
 }
 
+/// Canary server, which is chosen randomly instead of by score
+struct CanaryServer {
+    server: Arc<ServerIdent>,
+    percentage: f64,
+}
+
 /// Build a `PingBalancer`
 pub struct PingBalancerBuilder {
     servers: Vec<Arc<ServerIdent>>,
+    canary: Option<CanaryServer>,
+    strategy: BalancerStrategy,
+    state_file: Option<PathBuf>,
+    affinity_ttl: Option<Duration>,
     context: Arc<ServiceContext>,
     mode: Mode,
 }
@@ -59,6 +85,10 @@ impl PingBalancerBuilder {
     pub fn new(context: Arc<ServiceContext>, mode: Mode) -> PingBalancerBuilder {
         PingBalancerBuilder {
             servers: Vec::new(),
+            canary: None,
+            strategy: BalancerStrategy::default(),
+            state_file: None,
+            affinity_ttl: None,
             context,
             mode,
         }
@@ -68,17 +98,56 @@ impl PingBalancerBuilder {
         self.servers.push(Arc::new(server));
     }
 
+    /// Set a canary server, which will be chosen for `percentage`% of requests randomly
+    ///
+    /// Canary server is probed as the other servers, but it will never be chosen as the best server. Requests fall
+    /// back to the stable servers while it is unhealthy.
+    pub fn set_canary_server(&mut self, server: ServerIdent, percentage: f64) {
+        assert!(
+            percentage > 0.0 && percentage <= 100.0,
+            "canary percentage must be in (0, 100]"
+        );
+
+        self.canary = Some(CanaryServer {
+            server: Arc::new(server),
+            percentage,
+        });
+    }
+
+    /// Set how the balancer chooses between the stable servers
+    pub fn set_strategy(&mut self, strategy: BalancerStrategy) {
+        self.strategy = strategy;
+    }
+
+    /// Set the file for persisting servers' recent scores
+    ///
+    /// Servers are seeded with scores loaded from it before the initial check, and it is saved after every check.
+    pub fn set_state_file(&mut self, path: PathBuf) {
+        self.state_file = Some(path);
+    }
+
+    /// Send connections from the same client IP to the same server, until the client has been idle for `ttl`
+    pub fn set_server_affinity(&mut self, ttl: Duration) {
+        self.affinity_ttl = Some(ttl);
+    }
+
     pub async fn build(self) -> (PingBalancer, impl Future<Output = ()>) {
         assert!(!self.servers.is_empty(), "build PingBalancer without any servers");
 
         let balancer_context = PingBalancerContext {
+            weighted: self.strategy == BalancerStrategy::Weighted,
             servers: self.servers,
+            canary: self.canary,
             best_tcp_idx: AtomicUsize::new(0),
             best_udp_idx: AtomicUsize::new(0),
+            state_file: self.state_file,
+            affinity: self.affinity_ttl.map(ServerAffinity::new),
+            udp_hints: SpinMutex::new(HashMap::new()),
             context: self.context,
             mode: self.mode,
         };
 
+        balancer_context.load_state().await;
         balancer_context.init_score().await;
 
         let shared_context = Arc::new(balancer_context);
@@ -103,19 +172,204 @@ impl PingBalancerBuilder {
 
 struct PingBalancerContext {
     servers: Vec<Arc<ServerIdent>>,
+    weighted: bool,
+    canary: Option<CanaryServer>,
     best_tcp_idx: AtomicUsize,
     best_udp_idx: AtomicUsize,
+    state_file: Option<PathBuf>,
+    affinity: Option<ServerAffinity>,
+    /// Server hints of UDP associations from each client, the last one is used
+    udp_hints: SpinMutex<HashMap<IpAddr, Vec<Arc<ServerIdent>>>>,
     context: Arc<ServiceContext>,
     mode: Mode,
 }
 
 impl PingBalancerContext {
     fn best_tcp_server(&self) -> Arc<ServerIdent> {
-        self.servers[self.best_tcp_idx.load(Ordering::Relaxed)].clone()
+        if let Some(server) = self.pick_canary_server(ServerType::Tcp) {
+            return server;
+        }
+        self.best_stable_server(ServerType::Tcp)
     }
 
     fn best_udp_server(&self) -> Arc<ServerIdent> {
-        self.servers[self.best_udp_idx.load(Ordering::Relaxed)].clone()
+        if let Some(server) = self.pick_canary_server(ServerType::Udp) {
+            return server;
+        }
+        self.best_stable_server(ServerType::Udp)
+    }
+
+    /// Choose one of the stable servers, never the canary server
+    fn best_stable_server(&self, server_type: ServerType) -> Arc<ServerIdent> {
+        if let Some(server) = self.pick_weighted_server(server_type) {
+            return server;
+        }
+        match server_type {
+            ServerType::Tcp => self.servers[self.best_tcp_idx.load(Ordering::Relaxed)].clone(),
+            ServerType::Udp => self.servers[self.best_udp_idx.load(Ordering::Relaxed)].clone(),
+        }
+    }
+
+    fn best_server_for(&self, server_type: ServerType, client: IpAddr) -> Arc<ServerIdent> {
+        if let ServerType::Udp = server_type {
+            if let Some(server) = self.udp_hints.lock().get(&client).and_then(|hints| hints.last()) {
+                return server.clone();
+            }
+        }
+
+        // Canary is decided for each connection, clients shouldn't stick to it
+        if let Some(server) = self.pick_canary_server(server_type) {
+            return server;
+        }
+
+        let choose = || self.best_stable_server(server_type);
+
+        match self.affinity {
+            Some(ref affinity) => affinity.server_for(server_type, client, choose),
+            None => choose(),
+        }
+    }
+
+    fn set_udp_server_hint(&self, client: IpAddr, server: Arc<ServerIdent>) {
+        self.udp_hints
+            .lock()
+            .entry(client)
+            .or_insert_with(Vec::new)
+            .push(server);
+    }
+
+    fn clear_udp_server_hint(&self, client: IpAddr, server: &Arc<ServerIdent>) {
+        let mut udp_hints = self.udp_hints.lock();
+        if let Some(hints) = udp_hints.get_mut(&client) {
+            if let Some(pos) = hints.iter().rposition(|s| Arc::ptr_eq(s, server)) {
+                hints.remove(pos);
+            }
+            if hints.is_empty() {
+                udp_hints.remove(&client);
+            }
+        }
+    }
+
+    /// Decide randomly whether the request should be sent to the canary server
+    ///
+    /// Requests are sent to the stable servers while the canary server is unhealthy.
+    fn pick_canary_server(&self, server_type: ServerType) -> Option<Arc<ServerIdent>> {
+        let canary = self.canary.as_ref()?;
+
+        let score = match server_type {
+            ServerType::Tcp => canary.server.tcp_score(),
+            ServerType::Udp => canary.server.udp_score(),
+        };
+        if !score.is_healthy() {
+            return None;
+        }
+
+        if thread_rng().gen_bool(canary.percentage / 100.0) {
+            Some(canary.server.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Choose a server randomly by weights, unhealthy servers have no weight
+    ///
+    /// Returns `None` if the strategy is not `Weighted`, or none of the servers is available.
+    fn pick_weighted_server(&self, server_type: ServerType) -> Option<Arc<ServerIdent>> {
+        if !self.weighted {
+            return None;
+        }
+
+        let weight_of = |server: &ServerIdent| -> u64 {
+            let score = match server_type {
+                ServerType::Tcp => server.tcp_score(),
+                ServerType::Udp => server.udp_score(),
+            };
+            if score.is_healthy() {
+                server.server_config().weight().unwrap_or(1) as u64
+            } else {
+                0
+            }
+        };
+
+        let total_weight: u64 = self.servers.iter().map(|s| weight_of(s)).sum();
+        if total_weight == 0 {
+            return None;
+        }
+
+        let mut point = thread_rng().gen_range(0..total_weight);
+        for server in &self.servers {
+            let weight = weight_of(server);
+            if point < weight {
+                return Some(server.clone());
+            }
+            point -= weight;
+        }
+
+        None
+    }
+
+    /// All servers that are probed, including the canary server
+    fn probed_servers(&self) -> impl Iterator<Item = &Arc<ServerIdent>> {
+        let canary_server = self.canary.as_ref().map(|c| &c.server);
+        self.servers.iter().chain(canary_server)
+    }
+
+    /// Seed servers' scores with the state file
+    ///
+    /// Loaded scores are stale, they will be superseded by fresh probes, starting from the initial check.
+    async fn load_state(&self) {
+        let path = match self.state_file {
+            Some(ref p) => p,
+            None => return,
+        };
+
+        let state = match BalancerState::load_from_file(path) {
+            Ok(s) => s,
+            Err(err) => {
+                warn!("failed to load balancer state from {}, error: {}", path.display(), err);
+                return;
+            }
+        };
+
+        for server in self.probed_servers() {
+            let addr = server.server_config().addr().to_string();
+            if let Some(scores) = state.scores(ServerType::Tcp, &addr) {
+                server.tcp_score().seed_scores(scores).await;
+            }
+            if let Some(scores) = state.scores(ServerType::Udp, &addr) {
+                server.udp_score().seed_scores(scores).await;
+            }
+        }
+    }
+
+    /// Save servers' recent scores into the state file
+    async fn save_state(&self) {
+        let path = match self.state_file {
+            Some(ref p) => p.clone(),
+            None => return,
+        };
+
+        let mut state = BalancerState::new();
+        for server in self.probed_servers() {
+            let addr = server.server_config().addr().to_string();
+            if self.mode.enable_tcp() {
+                state.set_scores(ServerType::Tcp, addr.clone(), server.tcp_score().recent_scores().await);
+            }
+            if self.mode.enable_udp() {
+                state.set_scores(ServerType::Udp, addr, server.udp_score().recent_scores().await);
+            }
+        }
+
+        match task::spawn_blocking(move || state.save_to_file(&path)).await {
+            Ok(Ok(..)) => trace!("saved balancer state"),
+            Ok(Err(err)) => warn!("failed to save balancer state, error: {}", err),
+            Err(err) => warn!("failed to save balancer state, error: {}", err),
+        }
+    }
+
+    /// Check if there are more than one servers to be probed
+    fn need_check(&self) -> bool {
+        self.servers.len() > 1 || self.canary.is_some()
     }
 }
 
@@ -123,7 +377,7 @@ impl PingBalancerContext {
     async fn init_score(&self) {
         assert!(!self.servers.is_empty(), "check PingBalancer without any servers");
 
-        if self.servers.len() > 1 {
+        if self.need_check() {
             self.check_once(false).await;
         }
     }
@@ -131,26 +385,29 @@ impl PingBalancerContext {
     async fn checker_task(self: Arc<Self>) {
         assert!(!self.servers.is_empty(), "check PingBalancer without any servers");
 
-        if self.servers.len() == 1 {
+        if !self.need_check() {
             self.checker_task_dummy().await
         } else {
             self.checker_task_real().await
         }
     }
 
-    /// Dummy task that will do nothing if there only have one server in the balancer
+    /// Dummy task that will do nothing if there only have one server (without canary) in the balancer
     async fn checker_task_dummy(self: Arc<Self>) {
         future::pending().await
     }
 
     /// Check each servers' score and update the best server's index
     async fn check_once(&self, print_switch: bool) {
+        let total_servers = self.servers.len() + self.canary.iter().count();
+
         let mut vfut = match self.mode {
-            Mode::TcpAndUdp => Vec::with_capacity(self.servers.len() * 2),
-            Mode::TcpOnly | Mode::UdpOnly => Vec::with_capacity(self.servers.len()),
+            Mode::TcpAndUdp => Vec::with_capacity(total_servers * 2),
+            Mode::TcpOnly | Mode::UdpOnly => Vec::with_capacity(total_servers),
         };
 
-        for server in self.servers.iter() {
+        // Canary server is probed for comparing with the stable servers, but it is not a candidate of the best server
+        for server in self.probed_servers() {
             if self.mode.enable_tcp() {
                 let checker = PingChecker {
                     server: server.clone(),
@@ -229,11 +486,27 @@ impl PingBalancerContext {
                 );
             }
         }
+
+        if let Some(ref canary) = self.canary {
+            debug!(
+                "canary server {} score TCP: {}, UDP: {}; best stable server score TCP: {}, UDP: {}",
+                canary.server.server_config().addr(),
+                canary.server.tcp_score().score(),
+                canary.server.udp_score().score(),
+                self.servers[self.best_tcp_idx.load(Ordering::Relaxed)]
+                    .tcp_score()
+                    .score(),
+                self.servers[self.best_udp_idx.load(Ordering::Relaxed)]
+                    .udp_score()
+                    .score(),
+            );
+        }
     }
 
     async fn checker_task_real(&self) {
         loop {
             self.check_once(true).await;
+            self.save_state().await;
             time::sleep(Duration::from_secs(DEFAULT_CHECK_INTERVAL_SEC)).await;
         }
     }
@@ -277,12 +550,56 @@ impl PingBalancer {
     pub fn best_udp_server(&self) -> Arc<ServerIdent> {
         self.inner.context.best_udp_server()
     }
+
+    /// Pick the TCP server for connections from `client`, which is the sticky one if server affinity is enabled
+    pub fn best_tcp_server_for(&self, client: IpAddr) -> Arc<ServerIdent> {
+        self.inner.context.best_server_for(ServerType::Tcp, client)
+    }
+
+    /// Pick the UDP server for associations from `client`, which is the sticky one if server affinity is enabled
+    pub fn best_udp_server_for(&self, client: IpAddr) -> Arc<ServerIdent> {
+        self.inner.context.best_server_for(ServerType::Udp, client)
+    }
+
+    /// Send UDP associations from `client` through `server`, until `clear_udp_server_hint` is called
+    ///
+    /// SOCKS5 UDP ASSOCIATE requests with a server hint will set it for the lifetime of the control connection.
+    /// If there are multiple hints of the same `client`, the most recent one is used.
+    pub fn set_udp_server_hint(&self, client: IpAddr, server: Arc<ServerIdent>) {
+        self.inner.context.set_udp_server_hint(client, server);
+    }
+
+    /// Remove one UDP server hint of `client` that was set with `server`
+    ///
+    /// Other hints of the same `client` are kept.
+    pub fn clear_udp_server_hint(&self, client: IpAddr, server: &Arc<ServerIdent>) {
+        self.inner.context.clear_udp_server_hint(client, server);
+    }
+
+    /// Get the canary server
+    pub fn canary_server(&self) -> Option<Arc<ServerIdent>> {
+        self.inner.context.canary.as_ref().map(|c| c.server.clone())
+    }
+
+    /// Find a stable server by its `remarks` or `id`
+    pub fn server_by_name(&self, name: &str) -> Option<Arc<ServerIdent>> {
+        self.inner
+            .context
+            .servers
+            .iter()
+            .find(|s| {
+                let svr_cfg = s.server_config();
+                svr_cfg.remarks() == Some(name) || svr_cfg.id() == Some(name)
+            })
+            .cloned()
+    }
 }
 
 impl Debug for PingBalancer {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("PingBalancer")
             .field("servers", &self.inner.context.servers)
+            .field("canary", &self.canary_server())
             .field("best_tcp_idx", &self.inner.context.best_tcp_idx.load(Ordering::Relaxed))
             .field("best_udp_idx", &self.inner.context.best_udp_idx.load(Ordering::Relaxed))
             .finish()
@@ -496,3 +813,181 @@ impl PingChecker {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use shadowsocks::{config::ServerConfig, crypto::v1::CipherKind};
+
+    use super::{super::server_data::MAX_CONSECUTIVE_FAILURES, *};
+
+    fn weighted_context(weights: &[u32]) -> PingBalancerContext {
+        let servers = weights
+            .iter()
+            .enumerate()
+            .map(|(idx, &weight)| {
+                let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8400 + idx as u16);
+                let mut svr_cfg = ServerConfig::new(addr, "password".to_owned(), CipherKind::AES_256_GCM);
+                svr_cfg.set_weight(weight);
+                Arc::new(ServerIdent::new(svr_cfg))
+            })
+            .collect();
+
+        PingBalancerContext {
+            servers,
+            weighted: true,
+            canary: None,
+            best_tcp_idx: AtomicUsize::new(0),
+            best_udp_idx: AtomicUsize::new(0),
+            state_file: None,
+            affinity: None,
+            udp_hints: SpinMutex::new(HashMap::new()),
+            context: Arc::new(ServiceContext::new()),
+            mode: Mode::TcpOnly,
+        }
+    }
+
+    /// Count how many times each server is chosen in `rounds` picks
+    fn pick_counts(context: &PingBalancerContext, rounds: usize) -> Vec<usize> {
+        let mut counts = vec![0; context.servers.len()];
+        for _ in 0..rounds {
+            let server = context.best_tcp_server();
+            let idx = context.servers.iter().position(|s| Arc::ptr_eq(s, &server)).unwrap();
+            counts[idx] += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn weighted_distribution() {
+        let context = weighted_context(&[1, 3]);
+
+        let counts = pick_counts(&context, 10000);
+        // Expecting 2500 and 7500
+        assert!(counts[0] > 2000 && counts[0] < 3000, "{:?}", counts);
+        assert!(counts[1] > 7000 && counts[1] < 8000, "{:?}", counts);
+    }
+
+    #[tokio::test]
+    async fn weighted_failover() {
+        let context = weighted_context(&[1, 1]);
+
+        // A single failed request doesn't take the server out
+        context.servers[1].tcp_score().report_failure().await;
+        assert!(pick_counts(&context, 1000)[1] > 0);
+
+        for _ in 1..MAX_CONSECUTIVE_FAILURES {
+            context.servers[1].tcp_score().report_failure().await;
+        }
+        assert_eq!(pick_counts(&context, 1000), vec![1000, 0]);
+
+        // Back after passing a probe
+        context.servers[1].tcp_score().push_score(Score::Latency(100)).await;
+        assert!(pick_counts(&context, 1000)[1] > 0);
+
+        // The best server is chosen if all of them are unhealthy
+        context.best_tcp_idx.store(1, Ordering::Relaxed);
+        context.servers[0].tcp_score().push_score(Score::Errored).await;
+        context.servers[1].tcp_score().push_score(Score::Errored).await;
+        assert_eq!(pick_counts(&context, 100), vec![0, 100]);
+    }
+
+    #[test]
+    fn affinity_excludes_canary() {
+        let mut context = weighted_context(&[1, 1]);
+        let canary = with_canary(&mut context, 50.0);
+        context.affinity = Some(ServerAffinity::new(Duration::from_secs(600)));
+
+        let client = IpAddr::from([127, 0, 0, 1]);
+
+        let mut canary_count = 0;
+        let mut stable_servers = Vec::new();
+        for _ in 0..200 {
+            let server = context.best_server_for(ServerType::Tcp, client);
+            if Arc::ptr_eq(&server, &canary) {
+                canary_count += 1;
+            } else if !stable_servers.iter().any(|s| Arc::ptr_eq(s, &server)) {
+                stable_servers.push(server);
+            }
+        }
+
+        // Canary is still chosen by chance, the other connections stick to one stable server
+        assert!(canary_count > 0 && canary_count < 200);
+        assert_eq!(stable_servers.len(), 1);
+    }
+
+    #[test]
+    fn udp_server_hint_per_association() {
+        let context = weighted_context(&[1, 1]);
+        let client = IpAddr::from([127, 0, 0, 1]);
+        let (first, second) = (context.servers[0].clone(), context.servers[1].clone());
+
+        context.set_udp_server_hint(client, first.clone());
+        context.set_udp_server_hint(client, second.clone());
+        assert!(Arc::ptr_eq(&context.best_server_for(ServerType::Udp, client), &second));
+
+        // Closing the first association keeps the hint of the second one
+        context.clear_udp_server_hint(client, &first);
+        assert!(Arc::ptr_eq(&context.best_server_for(ServerType::Udp, client), &second));
+
+        context.clear_udp_server_hint(client, &second);
+        assert!(context.udp_hints.lock().is_empty());
+    }
+
+    fn with_canary(context: &mut PingBalancerContext, percentage: f64) -> Arc<ServerIdent> {
+        let canary = ServerConfig::new(
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8410),
+            "password".to_owned(),
+            CipherKind::AES_256_GCM,
+        );
+        let canary = Arc::new(ServerIdent::new(canary));
+        context.canary = Some(CanaryServer {
+            server: canary.clone(),
+            percentage,
+        });
+        canary
+    }
+
+    /// Count how many times the canary server is chosen in `rounds` picks
+    fn canary_count(context: &PingBalancerContext, canary: &Arc<ServerIdent>, rounds: usize) -> usize {
+        (0..rounds)
+            .filter(|_| Arc::ptr_eq(&context.best_tcp_server(), canary))
+            .count()
+    }
+
+    #[tokio::test]
+    async fn canary_selection_ratio() {
+        let mut context = weighted_context(&[1]);
+        context.weighted = false;
+        let canary = with_canary(&mut context, 20.0);
+
+        // Expecting 2000
+        let count = canary_count(&context, &canary, 10000);
+        assert!(count > 1500 && count < 2500, "{}", count);
+
+        // Fall back to the primary server while the canary is unhealthy
+        canary.tcp_score().push_score(Score::Errored).await;
+        assert_eq!(canary_count(&context, &canary, 1000), 0);
+
+        canary.tcp_score().push_score(Score::Latency(100)).await;
+        assert!(canary_count(&context, &canary, 1000) > 0);
+    }
+
+    #[tokio::test]
+    async fn affinity_tolerates_single_failure() {
+        let mut context = weighted_context(&[1, 1]);
+        context.affinity = Some(ServerAffinity::new(Duration::from_secs(600)));
+
+        let client = IpAddr::from([127, 0, 0, 1]);
+        let sticky = context.best_server_for(ServerType::Tcp, client);
+
+        sticky.tcp_score().report_failure().await;
+        for _ in 0..100 {
+            assert!(Arc::ptr_eq(&context.best_server_for(ServerType::Tcp, client), &sticky));
+        }
+
+        for _ in 1..MAX_CONSECUTIVE_FAILURES {
+            sticky.tcp_score().report_failure().await;
+        }
+        assert!(!Arc::ptr_eq(&context.best_server_for(ServerType::Tcp, client), &sticky));
+    }
+}
@@ -0,0 +1,72 @@
+//! Affinity of clients to servers (sticky sessions)
+//!
+//! All connections from the same client IP are sent to the same server, as long as the client keeps making
+//! connections within the TTL, so targets see a consistent source IP of the client. If the sticky server becomes
+//! unhealthy (failed in the latest probe, or a few connections through it failed in a row), the client is reassigned
+//! to the server chosen by the balancer.
+//!
+//! Only stable servers are sticky. Connections sent to the canary server are chosen by chance as usual, and they
+//! don't change the sticky server of the client.
+
+use std::{net::IpAddr, sync::Arc, time::Duration};
+
+use log::info;
+use lru_time_cache::LruCache;
+use spin::Mutex as SpinMutex;
+
+use super::{ping_balancer::ServerType, server_data::ServerIdent};
+
+/// Sticky servers of clients
+pub struct ServerAffinity {
+    tcp: SpinMutex<LruCache<IpAddr, Arc<ServerIdent>>>,
+    udp: SpinMutex<LruCache<IpAddr, Arc<ServerIdent>>>,
+}
+
+impl ServerAffinity {
+    /// Create an empty affinity table, clients are forgotten if they haven't made connections for `ttl`
+    pub fn new(ttl: Duration) -> ServerAffinity {
+        ServerAffinity {
+            tcp: SpinMutex::new(LruCache::with_expiry_duration(ttl)),
+            udp: SpinMutex::new(LruCache::with_expiry_duration(ttl)),
+        }
+    }
+
+    /// Get the sticky server of `client`, or assign the one returned by `choose` if it doesn't have a healthy one
+    pub fn server_for<F>(&self, server_type: ServerType, client: IpAddr, choose: F) -> Arc<ServerIdent>
+    where
+        F: FnOnce() -> Arc<ServerIdent>,
+    {
+        let table = match server_type {
+            ServerType::Tcp => &self.tcp,
+            ServerType::Udp => &self.udp,
+        };
+
+        let sticky = table.lock().get(&client).cloned();
+        if let Some(ref server) = sticky {
+            let score = match server_type {
+                ServerType::Tcp => server.tcp_score(),
+                ServerType::Udp => server.udp_score(),
+            };
+            if score.is_healthy() {
+                return server.clone();
+            }
+        }
+
+        let server = choose();
+        match sticky {
+            // All servers are unhealthy, the balancer chose the same one
+            Some(ref old_server) if Arc::ptr_eq(old_server, &server) => {}
+            Some(old_server) => info!(
+                "sticky {} server {} of client {} is unhealthy, reassigned to {}",
+                server_type,
+                old_server.server_config().addr(),
+                client,
+                server.server_config().addr()
+            ),
+            None => {}
+        }
+
+        table.lock().insert(client, server.clone());
+        server
+    }
+}
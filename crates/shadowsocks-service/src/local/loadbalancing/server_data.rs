@@ -10,10 +10,16 @@ use tokio::sync::Mutex;
 
 use super::server_stat::{Score, ServerStat};
 
+/// Requests failed in a row before a server is considered unhealthy
+///
+/// A single failed request may be caused by the target or the client, it shouldn't take the server out.
+pub const MAX_CONSECUTIVE_FAILURES: u32 = 3;
+
 /// Server's statistic score
 pub struct ServerScore {
     stat_data: Mutex<ServerStat>,
     score: AtomicU32,
+    failures: AtomicU32,
 }
 
 impl ServerScore {
@@ -22,6 +28,7 @@ impl ServerScore {
         ServerScore {
             stat_data: Mutex::new(ServerStat::new()),
             score: AtomicU32::new(0),
+            failures: AtomicU32::new(0),
         }
     }
 
@@ -30,8 +37,29 @@ impl ServerScore {
         self.score.load(Ordering::Acquire)
     }
 
-    /// Append a `Score` into statistic and recalculate score of the server
+    /// Check if the server is available
+    ///
+    /// A server is unhealthy if its latest probe failed, or `MAX_CONSECUTIVE_FAILURES` requests have failed since
+    /// its latest successful probe. Servers are healthy before being probed.
+    pub fn is_healthy(&self) -> bool {
+        self.failures.load(Ordering::Acquire) < MAX_CONSECUTIVE_FAILURES
+    }
+
+    /// Append a probe's `Score` into statistic and recalculate score of the server
     pub async fn push_score(&self, score: Score) -> u32 {
+        self.reset_failures(&score);
+        self.push_stat(score).await
+    }
+
+    fn reset_failures(&self, latest: &Score) {
+        let failures = match *latest {
+            Score::Latency(..) => 0,
+            Score::Errored => MAX_CONSECUTIVE_FAILURES,
+        };
+        self.failures.store(failures, Ordering::Release);
+    }
+
+    async fn push_stat(&self, score: Score) -> u32 {
         let updated_score = {
             let mut stat = self.stat_data.lock().await;
             stat.push_score(score)
@@ -40,9 +68,32 @@ impl ServerScore {
         updated_score
     }
 
-    /// Report request failure of this server, which will eventually records an `Errored` score
+    /// Get server's recent scores, from the oldest to the latest
+    pub async fn recent_scores(&self) -> Vec<Score> {
+        let stat = self.stat_data.lock().await;
+        stat.recent_scores()
+    }
+
+    /// Seed server's statistic with scores of previous runs, from the oldest to the latest
+    pub async fn seed_scores(&self, scores: &[Score]) -> u32 {
+        if let Some(latest) = scores.last() {
+            self.reset_failures(latest);
+        }
+
+        let updated_score = {
+            let mut stat = self.stat_data.lock().await;
+            stat.seed_scores(scores)
+        };
+        self.score.store(updated_score, Ordering::Release);
+        updated_score
+    }
+
+    /// Report request failure of this server, which records an `Errored` score
+    ///
+    /// The server becomes unhealthy after `MAX_CONSECUTIVE_FAILURES` failures, until it passes the next probe.
     pub async fn report_failure(&self) -> u32 {
-        self.push_score(Score::Errored).await
+        self.failures.fetch_add(1, Ordering::AcqRel);
+        self.push_stat(Score::Errored).await
     }
 }
 
@@ -11,7 +11,7 @@ const MAX_SERVER_RTT: u32 = DEFAULT_CHECK_TIMEOUT_SEC as u32 * 1000;
 const MAX_LATENCY_QUEUE_SIZE: usize = 59; // Account for the last 10 minutes.
 
 /// Statistic score
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Score {
     /// Unified latency
     Latency(u32),
@@ -91,6 +91,18 @@ impl ServerStat {
         self.recalculate_score()
     }
 
+    /// Recent scores, from the oldest to the latest
+    pub fn recent_scores(&self) -> Vec<Score> {
+        self.latency_queue.iter().copied().collect()
+    }
+
+    /// Seed statistic with scores of previous runs, from the oldest to the latest
+    pub fn seed_scores(&mut self, scores: &[Score]) -> u32 {
+        let skip = scores.len().saturating_sub(MAX_LATENCY_QUEUE_SIZE);
+        self.latency_queue = scores[skip..].iter().copied().collect();
+        self.recalculate_score()
+    }
+
     fn recalculate_score(&mut self) -> u32 {
         if self.latency_queue.is_empty() {
             return self.score();
@@ -0,0 +1,142 @@
+//! Persisted state of the balancer
+//!
+//! Recent scores of each server are saved after every check, and loaded at startup for seeding the balancer,
+//! so it doesn't have to choose servers blindly after restarting.
+//!
+//! One line for each server and protocol, scores are listed from the oldest to the latest, `e` is an errored probe:
+//!
+//! ```plain
+//! tcp 1.2.3.4:8388 120 135 e 128
+//! udp 1.2.3.4:8388 98 101
+//! ```
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, ErrorKind, Write},
+    path::Path,
+};
+
+use log::debug;
+
+use super::{ping_balancer::ServerType, server_stat::Score};
+
+/// Recent scores of servers, keyed by server's address
+#[derive(Debug, Default)]
+pub struct BalancerState {
+    tcp: HashMap<String, Vec<Score>>,
+    udp: HashMap<String, Vec<Score>>,
+}
+
+impl BalancerState {
+    /// Create an empty state
+    pub fn new() -> BalancerState {
+        BalancerState::default()
+    }
+
+    /// Load state from `path`, it is empty if the file doesn't exist
+    ///
+    /// Malformed lines are ignored, the state is only a hint for the balancer.
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> io::Result<BalancerState> {
+        let path = path.as_ref();
+
+        let mut state = BalancerState::new();
+        let fp = match File::open(path) {
+            Ok(fp) => fp,
+            Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(state),
+            Err(err) => return Err(err),
+        };
+
+        for line in BufReader::new(fp).lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            match parse_line(line) {
+                Some((server_type, addr, scores)) => state.set_scores(server_type, addr, scores),
+                None => debug!("ignored malformed balancer state line {:?}", line),
+            }
+        }
+
+        debug!(
+            "loaded balancer state of {} TCP and {} UDP servers from {}",
+            state.tcp.len(),
+            state.udp.len(),
+            path.display()
+        );
+
+        Ok(state)
+    }
+
+    /// Save state into `path`
+    ///
+    /// State is written into a temporary file and then renamed to `path`, so it won't be truncated if the process
+    /// is killed while saving.
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+
+        {
+            let mut fp = BufWriter::new(File::create(&tmp_path)?);
+            for &(name, servers) in &[("tcp", &self.tcp), ("udp", &self.udp)] {
+                for (addr, scores) in servers {
+                    write!(fp, "{} {}", name, addr)?;
+                    for score in scores {
+                        match *score {
+                            Score::Latency(lat) => write!(fp, " {}", lat)?,
+                            Score::Errored => write!(fp, " e")?,
+                        }
+                    }
+                    writeln!(fp)?;
+                }
+            }
+            fp.flush()?;
+        }
+
+        fs::rename(&tmp_path, path)
+    }
+
+    /// Recent scores of server `addr`
+    pub fn scores(&self, server_type: ServerType, addr: &str) -> Option<&[Score]> {
+        let servers = match server_type {
+            ServerType::Tcp => &self.tcp,
+            ServerType::Udp => &self.udp,
+        };
+        servers.get(addr).map(Vec::as_slice)
+    }
+
+    /// Set recent scores of server `addr`
+    pub fn set_scores(&mut self, server_type: ServerType, addr: String, scores: Vec<Score>) {
+        let servers = match server_type {
+            ServerType::Tcp => &mut self.tcp,
+            ServerType::Udp => &mut self.udp,
+        };
+        servers.insert(addr, scores);
+    }
+}
+
+fn parse_line(line: &str) -> Option<(ServerType, String, Vec<Score>)> {
+    let mut parts = line.split_whitespace();
+
+    let server_type = match parts.next()? {
+        "tcp" => ServerType::Tcp,
+        "udp" => ServerType::Udp,
+        _ => return None,
+    };
+    let addr = parts.next()?.to_owned();
+
+    let mut scores = Vec::new();
+    for part in parts {
+        let score = match part {
+            "e" => Score::Errored,
+            lat => Score::Latency(lat.parse().ok()?),
+        };
+        scores.push(score);
+    }
+
+    Some((server_type, addr, scores))
+}
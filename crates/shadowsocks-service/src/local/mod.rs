@@ -2,7 +2,12 @@
 
 #[cfg(feature = "local-flow-stat")]
 use std::path::PathBuf;
-use std::{io, sync::Arc, time::Duration};
+use std::{
+    future::Future,
+    io::{self, ErrorKind},
+    sync::Arc,
+    time::Duration,
+};
 
 use futures::{future, FutureExt};
 use log::{error, trace, warn};
@@ -10,18 +15,28 @@ use log::{error, trace, warn};
 use shadowsocks::dns_resolver::DnsResolver;
 use shadowsocks::{
     net::{AcceptOpts, ConnectOpts},
-    plugin::{Plugin, PluginMode},
+    plugin::PluginMode,
+    ServerAddr,
 };
 
-use crate::config::{Config, ConfigType, ProtocolType};
+#[cfg(unix)]
+use crate::acl::AccessControl;
 #[cfg(feature = "local-flow-stat")]
 use crate::net::FlowStat;
+use crate::{
+    config::{ClientConfig, Config, ConfigType, ProtocolType, StartupResolvePolicy},
+    plugin::PluginRegistry,
+};
 
 use self::{
+    autoproxy::AutoProxyDomains,
+    captive_portal::captive_portal_task,
     context::ServiceContext,
     loadbalancing::{PingBalancerBuilder, ServerIdent},
 };
 
+pub mod autoproxy;
+pub mod captive_portal;
 pub mod context;
 #[cfg(feature = "local-dns")]
 pub mod dns;
@@ -69,6 +84,9 @@ pub async fn run(mut config: Config) -> io::Result<()> {
         #[cfg(target_os = "android")]
         vpn_protect_path: config.outbound_vpn_protect_path,
 
+        #[cfg(unix)]
+        dscp: config.outbound_dscp,
+
         #[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios"))]
         bind_interface: config.outbound_bind_interface,
 
@@ -76,11 +94,13 @@ pub async fn run(mut config: Config) -> io::Result<()> {
     };
     connect_opts.tcp.send_buffer_size = config.outbound_send_buffer_size;
     connect_opts.tcp.recv_buffer_size = config.outbound_recv_buffer_size;
+    connect_opts.tcp.reuse_addr = config.outbound_reuse_addr;
     context.set_connect_opts(connect_opts);
 
     let mut accept_opts = AcceptOpts::default();
     accept_opts.tcp.send_buffer_size = config.inbound_send_buffer_size;
     accept_opts.tcp.recv_buffer_size = config.inbound_recv_buffer_size;
+    accept_opts.backlog = config.listen_backlog;
     accept_opts.tcp.nodelay = config.no_delay;
 
     // #[cfg(all(feature = "local-dns", feature = "trust-dns"))]
@@ -161,10 +181,46 @@ pub async fn run(mut config: Config) -> io::Result<()> {
         context.set_acl(acl);
     }
 
+    if let Some(size) = config.split_first_packet {
+        context.set_split_first_packet(size);
+    }
+    context.set_verify_server_response(config.verify_server_response);
+    context.set_socks5_server_hint(config.socks5_server_hint);
+    if let Some(s) = config.min_avg_chunk_size {
+        context.set_min_avg_chunk_size(s);
+    }
+    if let Some(lifetime) = config.max_connection_lifetime {
+        context.set_max_connection_lifetime(lifetime);
+    }
+    if let Some(d) = config.connect_deadline {
+        context.set_connect_deadline(d);
+    }
+    if let Some(n) = config.max_pending_connects {
+        context.set_max_pending_connects(n);
+    }
+    context.set_unmap_ipv4(config.unmap_ipv4);
+    context.set_udp_eviction_policy(config.udp_eviction_policy);
+    context.set_udp_send_error_policy(config.udp_send_error_policy);
+
+    if config.autoproxy {
+        let domains = match config.autoproxy_path {
+            Some(ref path) => AutoProxyDomains::load_from_file(path)?,
+            None => AutoProxyDomains::new(),
+        };
+        context.set_autoproxy_domains(domains);
+        if let Some(timeout) = config.autoproxy_direct_timeout {
+            context.set_autoproxy_direct_timeout(timeout);
+        }
+    }
+
     let client_config = config.local_addr.expect("local server requires local address");
 
     let context = Arc::new(context);
 
+    if config.startup_resolve_policy != StartupResolvePolicy::Defer {
+        apply_startup_resolve_policy(&context, &mut config).await?;
+    }
+
     let mut vfut = Vec::new();
 
     let enable_tcp = match config.local_protocol {
@@ -181,20 +237,27 @@ pub async fn run(mut config: Config) -> io::Result<()> {
 
     if enable_tcp {
         // Start plugins for TCP proxies
+        //
+        // Servers with the same address and plugin configuration share one plugin process
 
+        let mut plugin_registry = PluginRegistry::new(config.max_plugin_processes);
+        if let Some(level) = config.plugin_log_level {
+            plugin_registry.set_log_level(level);
+        }
         let mut plugins = Vec::with_capacity(config.server.len());
 
-        for server in &mut config.server {
+        let canary_server = config.canary.as_mut().map(|c| &mut c.server);
+        for server in config.server.iter_mut().chain(canary_server) {
             if let Some(c) = server.plugin() {
-                let plugin = Plugin::start(c, server.addr(), PluginMode::Client)?;
-                server.set_plugin_addr(plugin.local_addr().into());
-                plugins.push(plugin);
+                let (plugin_addr, plugin) = plugin_registry.start_shared(c, server.addr(), PluginMode::Client)?;
+                server.set_plugin_addr(plugin_addr.into());
+                plugins.extend(plugin);
             }
         }
 
         // Load balancer will check all servers' score before server's actual start.
         // So we have to ensure all plugins have been started before that.
-        if config.server.len() > 1 && !plugins.is_empty() {
+        if (config.server.len() > 1 || config.canary.is_some()) && !plugins.is_empty() {
             let mut check_fut = Vec::with_capacity(plugins.len());
 
             for plugin in &plugins {
@@ -232,9 +295,19 @@ pub async fn run(mut config: Config) -> io::Result<()> {
     // XXX: This have to be called after allocating plugins' addresses
     let balancer = {
         let mut balancer_builder = PingBalancerBuilder::new(context.clone(), config.mode);
+        balancer_builder.set_strategy(config.balancer_strategy);
         for server in config.server {
             balancer_builder.add_server(ServerIdent::new(server));
         }
+        if let Some(canary) = config.canary {
+            balancer_builder.set_canary_server(ServerIdent::new(canary.server), canary.percentage);
+        }
+        if let Some(path) = config.balancer_state_file {
+            balancer_builder.set_state_file(path);
+        }
+        if let Some(affinity) = config.server_affinity {
+            balancer_builder.set_server_affinity(affinity.ttl);
+        }
         let (balancer, checker) = balancer_builder.build().await;
         tokio::spawn(checker);
 
@@ -247,13 +320,26 @@ pub async fn run(mut config: Config) -> io::Result<()> {
 
         let local_addr = config.local_dns_addr.expect("missing local_dns_addr");
         let remote_addr = config.remote_dns_addr.expect("missing remote_dns_addr");
+        let mode = config.mode;
 
-        let bind_addr = config.dns_bind_addr.as_ref().unwrap_or_else(|| &client_config);
+        let serve = {
+            let context = context.clone();
+            let balancer = balancer.clone();
+            move |bind_addr: ClientConfig| {
+                let mut server = Dns::with_context(context.clone(), local_addr.clone(), remote_addr.clone());
+                server.set_mode(mode);
 
-        let mut server = Dns::with_context(context.clone(), local_addr, remote_addr);
-        server.set_mode(config.mode);
+                let balancer = balancer.clone();
+                async move { server.run(&bind_addr, balancer).await }
+            }
+        };
 
-        vfut.push(server.run(bind_addr, balancer.clone()).boxed());
+        match config.dns_bind_addr {
+            Some(ref bind_addr) => vfut.push(serve(bind_addr.clone()).boxed()),
+            None => {
+                vfut.push(serve_with_fallback(client_config.clone(), config.local_addr_fallback.clone(), serve).boxed())
+            }
+        }
     }
 
     #[cfg(feature = "local-flow-stat")]
@@ -264,27 +350,50 @@ pub async fn run(mut config: Config) -> io::Result<()> {
         vfut.push(report_fut.boxed());
     }
 
+    #[cfg(unix)]
+    if context.acl().is_some() {
+        vfut.push(acl_reload_task(context.clone()).boxed());
+    }
+
+    if let Some(check) = config.captive_portal_check {
+        vfut.push(captive_portal_task(context.clone(), check).boxed());
+    }
+
+    // Listeners are bound on the first of `local_addr` and its fallbacks that could be bound
+    let fallbacks = config.local_addr_fallback;
+    let mode = config.mode;
+    let udp_max_associations = config.udp_max_associations;
+    let udp_timeout = config.udp_timeout;
+    let no_delay = config.no_delay;
+
     match config.local_protocol {
         ProtocolType::Socks => {
             use self::socks::Socks;
 
-            let mut server = Socks::with_context(context);
-            server.set_mode(config.mode);
+            let udp_bind_addr = config.udp_bind_addr;
 
-            if let Some(c) = config.udp_max_associations {
-                server.set_udp_capacity(c);
-            }
-            if let Some(d) = config.udp_timeout {
-                server.set_udp_expiry_duration(d);
-            }
-            if let Some(b) = config.udp_bind_addr {
-                server.set_udp_bind_addr(b);
-            }
-            if config.no_delay {
-                server.set_nodelay(true);
-            }
+            let serve = move |client_config: ClientConfig| {
+                let mut server = Socks::with_context(context.clone());
+                server.set_mode(mode);
+
+                if let Some(c) = udp_max_associations {
+                    server.set_udp_capacity(c);
+                }
+                if let Some(d) = udp_timeout {
+                    server.set_udp_expiry_duration(d);
+                }
+                if let Some(ref b) = udp_bind_addr {
+                    server.set_udp_bind_addr(b.clone());
+                }
+                if no_delay {
+                    server.set_nodelay(true);
+                }
+
+                let balancer = balancer.clone();
+                async move { server.run(&client_config, balancer).await }
+            };
 
-            vfut.push(server.run(&client_config, balancer).boxed());
+            vfut.push(serve_with_fallback(client_config, fallbacks, serve).boxed());
         }
         #[cfg(feature = "local-tunnel")]
         ProtocolType::Tunnel => {
@@ -292,47 +401,66 @@ pub async fn run(mut config: Config) -> io::Result<()> {
 
             let forward_addr = config.forward.expect("tunnel requires forward address");
 
-            let mut server = Tunnel::with_context(context, forward_addr);
+            let serve = move |client_config: ClientConfig| {
+                let mut server = Tunnel::with_context(context.clone(), forward_addr.clone());
 
-            if let Some(c) = config.udp_max_associations {
-                server.set_udp_capacity(c);
-            }
-            if let Some(d) = config.udp_timeout {
-                server.set_udp_expiry_duration(d);
-            }
-            server.set_mode(config.mode);
-            if config.no_delay {
-                server.set_nodelay(true);
-            }
+                if let Some(c) = udp_max_associations {
+                    server.set_udp_capacity(c);
+                }
+                if let Some(d) = udp_timeout {
+                    server.set_udp_expiry_duration(d);
+                }
+                server.set_mode(mode);
+                if no_delay {
+                    server.set_nodelay(true);
+                }
+
+                let balancer = balancer.clone();
+                async move { server.run(&client_config, balancer).await }
+            };
 
-            vfut.push(server.run(&client_config, balancer).boxed());
+            vfut.push(serve_with_fallback(client_config, fallbacks, serve).boxed());
         }
         #[cfg(feature = "local-http")]
         ProtocolType::Http => {
             use self::http::Http;
 
-            let server = Http::with_context(context);
-            vfut.push(server.run(&client_config, balancer).boxed());
+            let serve = move |client_config: ClientConfig| {
+                let server = Http::with_context(context.clone());
+
+                let balancer = balancer.clone();
+                async move { server.run(&client_config, balancer).await }
+            };
+
+            vfut.push(serve_with_fallback(client_config, fallbacks, serve).boxed());
         }
         #[cfg(feature = "local-redir")]
         ProtocolType::Redir => {
             use self::redir::Redir;
 
-            let mut server = Redir::with_context(context);
-            if let Some(c) = config.udp_max_associations {
-                server.set_udp_capacity(c);
-            }
-            if let Some(d) = config.udp_timeout {
-                server.set_udp_expiry_duration(d);
-            }
-            server.set_mode(config.mode);
-            if config.no_delay {
-                server.set_nodelay(true);
-            }
-            server.set_tcp_redir(config.tcp_redir);
-            server.set_udp_redir(config.udp_redir);
+            let tcp_redir = config.tcp_redir;
+            let udp_redir = config.udp_redir;
 
-            vfut.push(server.run(&client_config, balancer).boxed());
+            let serve = move |client_config: ClientConfig| {
+                let mut server = Redir::with_context(context.clone());
+                if let Some(c) = udp_max_associations {
+                    server.set_udp_capacity(c);
+                }
+                if let Some(d) = udp_timeout {
+                    server.set_udp_expiry_duration(d);
+                }
+                server.set_mode(mode);
+                if no_delay {
+                    server.set_nodelay(true);
+                }
+                server.set_tcp_redir(tcp_redir);
+                server.set_udp_redir(udp_redir);
+
+                let balancer = balancer.clone();
+                async move { server.run(&client_config, balancer).await }
+            };
+
+            vfut.push(serve_with_fallback(client_config, fallbacks, serve).boxed());
         }
         #[cfg(feature = "local-dns")]
         ProtocolType::Dns => {}
@@ -342,6 +470,152 @@ pub async fn run(mut config: Config) -> io::Result<()> {
     res
 }
 
+/// Run the local server with `serve` on `local_addr`, or on the first of its fallbacks that could be bound
+///
+/// Servers bind their listeners directly. If any of them fails to bind, all the listeners of this address are closed,
+/// and the next address is tried, so the address actually bound is used for all of them.
+async fn serve_with_fallback<S, F>(local_addr: ClientConfig, fallbacks: Vec<ClientConfig>, serve: S) -> io::Result<()>
+where
+    S: Fn(ClientConfig) -> F,
+    F: Future<Output = io::Result<()>>,
+{
+    let mut fallbacks = fallbacks.into_iter();
+    let mut addr = local_addr;
+
+    loop {
+        match serve(addr.clone()).await {
+            Err(err) if is_bind_error(&err) => match fallbacks.next() {
+                Some(fallback) => {
+                    warn!(
+                        "local address {} couldn't be bound, trying fallback address {}, error: {}",
+                        addr, fallback, err
+                    );
+                    addr = fallback;
+                }
+                None => return Err(err),
+            },
+            r => return r,
+        }
+    }
+}
+
+fn is_bind_error(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        ErrorKind::AddrInUse | ErrorKind::AddrNotAvailable | ErrorKind::PermissionDenied
+    )
+}
+
+/// Resolve host names of servers, and apply `startup_resolve_policy` to the ones that couldn't be resolved
+async fn apply_startup_resolve_policy(context: &ServiceContext, config: &mut Config) -> io::Result<()> {
+    async fn resolve(context: &ServiceContext, addr: &ServerAddr) -> io::Result<()> {
+        match *addr {
+            ServerAddr::SocketAddr(..) => Ok(()),
+            ServerAddr::DomainName(ref dname, port) => context.context_ref().dns_resolve(dname, port).await.map(|_| ()),
+        }
+    }
+
+    let policy = config.startup_resolve_policy;
+
+    let mut servers = Vec::with_capacity(config.server.len());
+    for server in config.server.drain(..) {
+        match resolve(context, server.addr()).await {
+            Ok(..) => servers.push(server),
+            Err(err) => match policy {
+                StartupResolvePolicy::Fail => {
+                    let err = io::Error::new(
+                        err.kind(),
+                        format!("server {} couldn't be resolved, error: {}", server.addr(), err),
+                    );
+                    return Err(err);
+                }
+                StartupResolvePolicy::Defer => servers.push(server),
+                StartupResolvePolicy::Skip => {
+                    warn!(
+                        "skipped server {} which couldn't be resolved, error: {}",
+                        server.addr(),
+                        err
+                    );
+                }
+            },
+        }
+    }
+
+    if servers.is_empty() {
+        let err = io::Error::new(io::ErrorKind::Other, "none of the servers could be resolved");
+        return Err(err);
+    }
+    config.server = servers;
+
+    if let Some(ref canary) = config.canary {
+        if let Err(err) = resolve(context, canary.server.addr()).await {
+            match policy {
+                StartupResolvePolicy::Fail => {
+                    let err = io::Error::new(
+                        err.kind(),
+                        format!(
+                            "canary server {} couldn't be resolved, error: {}",
+                            canary.server.addr(),
+                            err
+                        ),
+                    );
+                    return Err(err);
+                }
+                StartupResolvePolicy::Defer => {}
+                StartupResolvePolicy::Skip => {
+                    warn!(
+                        "skipped canary server {} which couldn't be resolved, error: {}",
+                        canary.server.addr(),
+                        err
+                    );
+                    config.canary = None;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Reload ACL from the file it was loaded from when receiving `SIGUSR1`
+///
+/// Servers, listeners and established connections are kept untouched. If the new rules couldn't be loaded,
+/// the current rules are kept.
+#[cfg(unix)]
+async fn acl_reload_task(context: Arc<ServiceContext>) -> io::Result<()> {
+    use log::info;
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigusr1 = signal(SignalKind::user_defined1())?;
+
+    while sigusr1.recv().await.is_some() {
+        let path = match context.acl() {
+            Some(acl) => acl.file_path().to_owned(),
+            None => continue,
+        };
+
+        let load_path = path.clone();
+        match tokio::task::spawn_blocking(move || AccessControl::load_from_file(load_path)).await {
+            Ok(Ok(acl)) => {
+                context.reload_acl(acl).await;
+                info!("received SIGUSR1, reloaded ACL from {}", path.display());
+            }
+            Ok(Err(err)) => {
+                error!(
+                    "received SIGUSR1, failed to reload ACL from {}, keeping the current rules, error: {}",
+                    path.display(),
+                    err
+                );
+            }
+            Err(err) => {
+                error!("received SIGUSR1, failed to reload ACL, error: {}", err);
+            }
+        }
+    }
+
+    Ok(())
+}
+
 #[cfg(feature = "local-flow-stat")]
 async fn flow_report_task(stat_path: PathBuf, flow_stat: Arc<FlowStat>) -> io::Result<()> {
     use std::slice;
@@ -384,3 +658,75 @@ async fn flow_report_task(stat_path: PathBuf, flow_stat: Arc<FlowStat>) -> io::R
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use async_trait::async_trait;
+    use shadowsocks::{
+        config::ServerConfig,
+        crypto::v1::CipherKind,
+        dns_resolver::{DnsResolve, DnsResolver},
+    };
+
+    use super::*;
+
+    /// Fails to resolve any domain name, without depending on the system's resolver
+    struct FailingResolver;
+
+    #[async_trait]
+    impl DnsResolve for FailingResolver {
+        async fn resolve(&self, addr: &str, _port: u16) -> io::Result<Vec<SocketAddr>> {
+            Err(io::Error::new(
+                ErrorKind::NotFound,
+                format!("{} is not resolvable", addr),
+            ))
+        }
+    }
+
+    fn failing_resolver_context() -> ServiceContext {
+        let mut context = ServiceContext::new();
+        context.set_dns_resolver(Arc::new(DnsResolver::custom_resolver(FailingResolver)));
+        context
+    }
+
+    fn unresolvable_config(policy: StartupResolvePolicy) -> Config {
+        let mut config = Config::new(ConfigType::Local);
+        config.server = vec![
+            ServerConfig::new(
+                ServerAddr::DomainName("unresolvable.invalid".to_owned(), 8410),
+                "password".to_owned(),
+                CipherKind::AES_256_GCM,
+            ),
+            ServerConfig::new(
+                SocketAddr::from(([127, 0, 0, 1], 8410)),
+                "password".to_owned(),
+                CipherKind::AES_256_GCM,
+            ),
+        ];
+        config.startup_resolve_policy = policy;
+        config
+    }
+
+    #[tokio::test]
+    async fn startup_resolve_policy_skip_unresolvable() {
+        let context = failing_resolver_context();
+
+        let mut config = unresolvable_config(StartupResolvePolicy::Skip);
+        apply_startup_resolve_policy(&context, &mut config).await.unwrap();
+        assert_eq!(config.server.len(), 1);
+        assert_eq!(
+            config.server[0].addr(),
+            &ServerAddr::SocketAddr(SocketAddr::from(([127, 0, 0, 1], 8410)))
+        );
+
+        let mut config = unresolvable_config(StartupResolvePolicy::Fail);
+        assert!(apply_startup_resolve_policy(&context, &mut config).await.is_err());
+
+        // Fails if none of the servers is left
+        let mut config = unresolvable_config(StartupResolvePolicy::Skip);
+        config.server.truncate(1);
+        assert!(apply_startup_resolve_policy(&context, &mut config).await.is_err());
+    }
+}
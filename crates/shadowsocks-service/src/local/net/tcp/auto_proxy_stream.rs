@@ -1,13 +1,16 @@
 //! A `ProxyStream` that bypasses or proxies data through proxy server automatically
 
 use std::{
-    io::{self, IoSlice},
+    future::Future,
+    io::{self, ErrorKind, IoSlice},
     net::SocketAddr,
     pin::Pin,
     sync::Arc,
     task::{self, Poll},
+    time::Duration,
 };
 
+use log::{debug, trace};
 use pin_project::pin_project;
 use shadowsocks::{
     net::TcpStream,
@@ -22,6 +25,8 @@ use tokio::{
         tcp::{OwnedReadHalf, OwnedWriteHalf},
         TcpStream as TokioTcpStream,
     },
+    sync::Semaphore,
+    time,
 };
 
 use crate::{
@@ -48,12 +53,64 @@ impl AutoProxyClientStream {
     where
         A: Into<Address>,
     {
-        let addr = addr.into();
+        connect_limited(context, addr.into(), |context, addr| {
+            AutoProxyClientStream::connect_impl(context, server, addr)
+        })
+        .await
+    }
+
+    async fn connect_impl(
+        context: Arc<ServiceContext>,
+        server: &ServerIdent,
+        addr: Address,
+    ) -> io::Result<AutoProxyClientStream> {
         if context.check_target_bypassed(&addr).await {
-            AutoProxyClientStream::connect_bypassed(context, addr).await
-        } else {
-            AutoProxyClientStream::connect_proxied(context, server, addr).await
+            return AutoProxyClientStream::connect_bypassed_impl(context, addr).await;
+        }
+
+        if let Address::DomainNameAddress(ref host, ..) = addr {
+            if AutoProxyClientStream::should_try_direct(&context, host) {
+                // Nothing has been sent to the target before the connection is established,
+                // so falling back to proxy won't send the request data twice.
+                match time::timeout(
+                    context.autoproxy_direct_timeout(),
+                    AutoProxyClientStream::connect_bypassed_impl(context.clone(), addr.clone()),
+                )
+                .await
+                {
+                    Ok(Ok(s)) => return Ok(s),
+                    Ok(Err(err)) => {
+                        debug!("autoproxy connect {} directly failed, error: {}", addr, err);
+                    }
+                    Err(..) => {
+                        debug!("autoproxy connect {} directly timeout", addr);
+                    }
+                }
+
+                if let Some(domains) = context.autoproxy_domains() {
+                    domains.insert(host).await;
+                }
+            }
+        }
+
+        AutoProxyClientStream::connect_proxied_impl(context, server, addr).await
+    }
+
+    /// Check if target `host` should be connected directly first in autoproxy mode
+    fn should_try_direct(context: &ServiceContext, host: &str) -> bool {
+        let domains = match context.autoproxy_domains() {
+            Some(d) => d,
+            None => return false,
+        };
+
+        // Domain names in [proxy_list] are always proxied
+        if let Some(acl) = context.acl() {
+            if acl.check_host_in_proxy_list(host) == Some(true) {
+                return false;
+            }
         }
+
+        !domains.contains(host)
     }
 
     /// Connect directly to target `addr`
@@ -61,8 +118,11 @@ impl AutoProxyClientStream {
     where
         A: Into<Address>,
     {
+        connect_limited(context, addr.into(), AutoProxyClientStream::connect_bypassed_impl).await
+    }
+
+    async fn connect_bypassed_impl(context: Arc<ServiceContext>, addr: Address) -> io::Result<AutoProxyClientStream> {
         // Connect directly.
-        let addr = addr.into();
         let stream =
             TcpStream::connect_remote_with_opts(context.context_ref(), &addr, context.connect_opts_ref()).await?;
         Ok(AutoProxyClientStream::Bypassed(stream.into()))
@@ -77,6 +137,26 @@ impl AutoProxyClientStream {
     where
         A: Into<Address>,
     {
+        connect_limited(context, addr.into(), |context, addr| {
+            AutoProxyClientStream::connect_proxied_impl(context, server, addr)
+        })
+        .await
+    }
+
+    async fn connect_proxied_impl(
+        context: Arc<ServiceContext>,
+        server: &ServerIdent,
+        addr: Address,
+    ) -> io::Result<AutoProxyClientStream> {
+        // Servers couldn't be reached until the portal is cleared, they shouldn't be reported as failed
+        if context.captive_portal_detected() {
+            debug!("connect {} through proxy paused, captive portal detected", addr);
+            return Err(io::Error::new(
+                ErrorKind::Other,
+                "captive portal detected, proxying is paused",
+            ));
+        }
+
         let flow_stat = context.flow_stat();
         let stream = match ProxyClientStream::connect_with_opts_map(
             context.context(),
@@ -111,6 +191,77 @@ impl AutoProxyClientStream {
     }
 }
 
+/// Connect to `addr` with `connect`, which is limited by the connect deadline and the pending connects permits
+///
+/// IPv4-mapped IPv6 `addr` is unmapped first if `unmap_ipv4` is enabled.
+async fn connect_limited<C, F>(
+    context: Arc<ServiceContext>,
+    addr: Address,
+    connect: C,
+) -> io::Result<AutoProxyClientStream>
+where
+    C: FnOnce(Arc<ServiceContext>, Address) -> F,
+    F: Future<Output = io::Result<AutoProxyClientStream>>,
+{
+    let addr = if context.unmap_ipv4() { addr.unmap_ipv4() } else { addr };
+    let deadline = context.connect_deadline();
+    let permits = context.connect_permits();
+    with_connect_deadline(
+        deadline,
+        &addr,
+        with_connect_permit(permits, &addr, connect(context, addr.clone())),
+    )
+    .await
+}
+
+/// Bound the whole process of connecting `addr`, including all the attempts made inside `fut`
+async fn with_connect_deadline<F>(
+    deadline: Option<Duration>,
+    addr: &Address,
+    fut: F,
+) -> io::Result<AutoProxyClientStream>
+where
+    F: Future<Output = io::Result<AutoProxyClientStream>>,
+{
+    let deadline = match deadline {
+        Some(d) => d,
+        None => return fut.await,
+    };
+
+    match time::timeout(deadline, fut).await {
+        Ok(r) => r,
+        Err(..) => {
+            debug!("connect {} deadline {:?} exceeded", addr, deadline);
+            Err(io::Error::new(
+                ErrorKind::TimedOut,
+                format!("connect {} deadline exceeded", addr),
+            ))
+        }
+    }
+}
+
+async fn with_connect_permit<F>(
+    permits: Option<Arc<Semaphore>>,
+    addr: &Address,
+    fut: F,
+) -> io::Result<AutoProxyClientStream>
+where
+    F: Future<Output = io::Result<AutoProxyClientStream>>,
+{
+    let permits = match permits {
+        Some(p) => p,
+        None => return fut.await,
+    };
+
+    if permits.available_permits() == 0 {
+        trace!("connect {} queued, too many pending connects", addr);
+    }
+
+    // Permit is released after the connect finishes, whether it succeeds or not
+    let _permit = permits.acquire_owned().await.expect("connect permits closed");
+    fut.await
+}
+
 impl AutoProxyIo for AutoProxyClientStream {
     fn is_proxied(&self) -> bool {
         matches!(*self, AutoProxyClientStream::Proxied(..))
@@ -253,3 +404,57 @@ impl AsyncWrite for AutoProxyClientStreamWriteHalf {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use shadowsocks::{config::ServerConfig, crypto::v1::CipherKind};
+    use tokio::net::TcpListener;
+
+    use crate::local::autoproxy::AutoProxyDomains;
+
+    use super::*;
+
+    /// Address that nothing listens on
+    async fn closed_addr() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    #[tokio::test]
+    async fn autoproxy_learn_domain() {
+        let mut context = ServiceContext::new();
+        context.set_autoproxy_domains(AutoProxyDomains::new());
+        context.set_autoproxy_direct_timeout(Duration::from_secs(1));
+        let context = Arc::new(context);
+
+        let server = ServerIdent::new(ServerConfig::new(
+            closed_addr().await,
+            "password".to_owned(),
+            CipherKind::AES_256_GCM,
+        ));
+
+        // Connected directly, nothing is learned
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_addr = Address::DomainNameAddress("localhost".to_owned(), target.local_addr().unwrap().port());
+        let stream = AutoProxyClientStream::connect(context.clone(), &server, target_addr)
+            .await
+            .unwrap();
+        assert!(!stream.is_proxied());
+        assert!(AutoProxyClientStream::should_try_direct(&context, "localhost"));
+
+        // Direct connection failed, falls back to proxy and learns the domain name
+        let target_addr = Address::DomainNameAddress("localhost".to_owned(), closed_addr().await.port());
+        assert!(AutoProxyClientStream::connect(context.clone(), &server, target_addr)
+            .await
+            .is_err());
+        assert!(context.autoproxy_domains().unwrap().contains("localhost"));
+        assert!(!AutoProxyClientStream::should_try_direct(&context, "LocalHost"));
+    }
+
+    #[test]
+    fn autoproxy_disabled() {
+        // Never connect directly if autoproxy is not enabled
+        let context = ServiceContext::new();
+        assert!(!AutoProxyClientStream::should_try_direct(&context, "localhost"));
+    }
+}
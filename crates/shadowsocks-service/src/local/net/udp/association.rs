@@ -29,7 +29,11 @@ use tokio::{
 
 use crate::{
     local::{context::ServiceContext, loadbalancing::PingBalancer},
-    net::MonProxySocket,
+    net::{
+        udp_eviction::{evict_if_full, UdpAssociationUsage},
+        udp_send_error::{handle_send_error, UdpSendErrorKind},
+        MonProxySocket,
+    },
 };
 
 /// Writer for sending packets back to client
@@ -50,6 +54,7 @@ where
     respond_writer: W,
     context: Arc<ServiceContext>,
     assoc_map: Arc<Mutex<LruCache<SocketAddr, UdpAssociation<W>>>>,
+    capacity: Option<usize>,
     cleanup_abortable: AbortHandle,
     balancer: PingBalancer,
 }
@@ -76,10 +81,8 @@ where
         balancer: PingBalancer,
     ) -> UdpAssociationManager<W> {
         let time_to_live = time_to_live.unwrap_or(crate::DEFAULT_UDP_EXPIRY_DURATION);
-        let assoc_map = Arc::new(Mutex::new(match capacity {
-            Some(capacity) => LruCache::with_expiry_duration_and_capacity(time_to_live, capacity),
-            None => LruCache::with_expiry_duration(time_to_live),
-        }));
+        // Capacity is kept by `evict_if_full` with the configured eviction policy
+        let assoc_map = Arc::new(Mutex::new(LruCache::with_expiry_duration(time_to_live)));
 
         let cleanup_abortable = {
             let assoc_map = assoc_map.clone();
@@ -99,6 +102,7 @@ where
             respond_writer,
             context,
             assoc_map,
+            capacity,
             cleanup_abortable,
             balancer,
         }
@@ -106,10 +110,23 @@ where
 
     /// Sends `data` from `peer_addr` to `target_addr`
     pub async fn send_to(&self, peer_addr: SocketAddr, target_addr: Address, data: &[u8]) -> io::Result<()> {
+        let mut assoc_map = self.assoc_map.lock().await;
+
+        if let Some(capacity) = self.capacity {
+            evict_if_full(
+                &mut assoc_map,
+                &peer_addr,
+                capacity,
+                self.context.udp_eviction_policy(),
+                |assoc| &assoc.usage,
+            );
+        }
+
         // Check or (re)create an association
-        match self.assoc_map.lock().await.entry(peer_addr) {
+        match assoc_map.entry(peer_addr) {
             Entry::Occupied(occ) => {
                 let assoc = occ.into_mut();
+                assoc.usage.touch();
                 assoc.try_send((target_addr, Bytes::copy_from_slice(data)))
             }
             Entry::Vacant(vac) => {
@@ -121,6 +138,7 @@ where
                     self.respond_writer.clone(),
                 ));
                 trace!("created udp association for {}", peer_addr);
+                assoc.usage.touch();
                 assoc.try_send((target_addr, Bytes::copy_from_slice(data)))
             }
         }
@@ -133,6 +151,7 @@ where
 {
     assoc: Arc<UdpAssociationContext<W>>,
     sender: mpsc::Sender<(Address, Bytes)>,
+    usage: UdpAssociationUsage,
 }
 
 impl<W> Drop for UdpAssociation<W>
@@ -158,7 +177,11 @@ where
         respond_writer: W,
     ) -> UdpAssociation<W> {
         let (assoc, sender) = UdpAssociationContext::new(context, peer_addr, assoc_map, balancer, respond_writer);
-        UdpAssociation { assoc, sender }
+        UdpAssociation {
+            assoc,
+            sender,
+            usage: UdpAssociationUsage::new(),
+        }
     }
 
     fn try_send(&self, data: (Address, Bytes)) -> io::Result<()> {
@@ -296,7 +319,11 @@ where
     }
 
     async fn copy_l2r(self: Arc<Self>, mut receiver: mpsc::Receiver<(Address, Bytes)>) {
-        while let Some((target_addr, data)) = receiver.recv().await {
+        while let Some((mut target_addr, data)) = receiver.recv().await {
+            if self.context.unmap_ipv4() {
+                target_addr = target_addr.unmap_ipv4();
+            }
+
             let bypassed = self.context.check_target_bypassed(&target_addr).await;
 
             trace!(
@@ -391,7 +418,22 @@ where
             }
         };
 
-        let n = socket.send_to(data, target_addr).await?;
+        let n = match socket.send_to(data, target_addr).await {
+            Ok(n) => n,
+            Err(err) => {
+                handle_send_error(
+                    &self.assoc_map,
+                    self.context.udp_send_error_stat_ref(),
+                    self.context.udp_send_error_policy(),
+                    self.peer_addr,
+                    target_addr,
+                    data.len(),
+                    &err,
+                )
+                .await;
+                return Ok(());
+            }
+        };
         if n != data.len() {
             warn!(
                 "{} -> {} sent {} bytes != expected {} bytes",
@@ -446,7 +488,22 @@ where
             }
         };
 
-        let n = socket.send_to(data, target_addr).await?;
+        let n = match socket.send_to(data, target_addr).await {
+            Ok(n) => n,
+            Err(err) => {
+                handle_send_error(
+                    &self.assoc_map,
+                    self.context.udp_send_error_stat_ref(),
+                    self.context.udp_send_error_policy(),
+                    self.peer_addr,
+                    target_addr,
+                    data.len(),
+                    &err,
+                )
+                .await;
+                return Ok(());
+            }
+        };
         if n != data.len() {
             warn!(
                 "{} -> {} sent {} bytes != expected {} bytes",
@@ -471,7 +528,7 @@ where
                     UdpAssociationProxyState::Empty => {
                         // Create a new connection to proxy server
 
-                        let server = self.balancer.best_udp_server();
+                        let server = self.balancer.best_udp_server_for(self.peer_addr.ip());
                         let svr_cfg = server.server_config();
 
                         let socket = ProxySocket::connect_with_opts(
@@ -525,8 +582,15 @@ where
                         tried + 1,
                         err
                     );
+
+                    let too_large = UdpSendErrorKind::from_io_error(&err) == UdpSendErrorKind::MessageTooLarge;
                     last_err = err;
 
+                    // Retrying won't help a packet that is too large
+                    if too_large {
+                        break;
+                    }
+
                     // Reset for reconnecting
                     self.proxied_socket.lock().reset();
 
@@ -535,7 +599,17 @@ where
             }
         }
 
-        Err(last_err)
+        handle_send_error(
+            &self.assoc_map,
+            self.context.udp_send_error_stat_ref(),
+            self.context.udp_send_error_policy(),
+            self.peer_addr,
+            target_addr,
+            data.len(),
+            &last_err,
+        )
+        .await;
+        Ok(())
     }
 
     async fn copy_proxied_r2l(self: Arc<Self>, outbound: Arc<MonProxySocket>) -> io::Result<()> {
@@ -544,7 +618,9 @@ where
             let (n, addr) = match outbound.recv(&mut buffer).await {
                 Ok(n) => {
                     // Keep association alive in map
-                    let _ = self.assoc_map.lock().await.get(&self.peer_addr);
+                    if let Some(assoc) = self.assoc_map.lock().await.get_mut(&self.peer_addr) {
+                        assoc.usage.touch();
+                    }
                     n
                 }
                 Err(err) => {
@@ -584,7 +660,9 @@ where
             let (n, addr) = match outbound.recv_from(&mut buffer).await {
                 Ok(n) => {
                     // Keep association alive in map
-                    let _ = self.assoc_map.lock().await.get(&self.peer_addr);
+                    if let Some(assoc) = self.assoc_map.lock().await.get_mut(&self.peer_addr) {
+                        assoc.usage.touch();
+                    }
                     n
                 }
                 Err(err) => {
@@ -18,6 +18,7 @@ use crate::{
         redir::redir_ext::{TcpListenerRedirExt, TcpStreamRedirExt},
         utils::establish_tcp_tunnel,
     },
+    net::utils::is_benign_close_error,
 };
 
 mod sys;
@@ -33,10 +34,10 @@ async fn establish_client_tcp_redir<'a>(
     addr: &Address,
     nodelay: bool,
 ) -> io::Result<()> {
-    let server = balancer.best_tcp_server();
+    let server = balancer.best_tcp_server_for(peer_addr.ip());
     let svr_cfg = server.server_config();
 
-    let remote = AutoProxyClientStream::connect(context, &server, addr).await?;
+    let remote = AutoProxyClientStream::connect(context.clone(), &server, addr).await?;
 
     if nodelay {
         remote.set_nodelay(true)?;
@@ -58,7 +59,8 @@ async fn establish_client_tcp_redir<'a>(
     let (mut shadow_reader, mut shadow_writer) = remote.into_split();
 
     establish_tcp_tunnel(
-        svr_cfg,
+        &context,
+        &server,
         &mut plain_reader,
         &mut plain_writer,
         &mut shadow_reader,
@@ -84,8 +86,17 @@ async fn handle_redir_client(
     // }
 
     if nodelay {
-        if let Err(err) = s.set_nodelay(true) {
-            error!("failed to set TCP_NODELAY on accepted socket, error: {:?}", err);
+        match s.set_nodelay(true) {
+            Ok(..) => {}
+            Err(err) if is_benign_close_error(&err) => {
+                debug!(
+                    "failed to set TCP_NODELAY on accepted socket, client closed connection, error: {:?}",
+                    err
+                );
+            }
+            Err(err) => {
+                error!("failed to set TCP_NODELAY on accepted socket, error: {:?}", err);
+            }
         }
     }
 
@@ -120,6 +131,10 @@ pub async fn run_tcp_redir(
     loop {
         let (socket, peer_addr) = match listener.accept().await {
             Ok(s) => s,
+            Err(err) if is_benign_close_error(&err) => {
+                debug!("accept failed, client closed connection, error: {}", err);
+                continue;
+            }
             Err(err) => {
                 error!("accept failed with error: {}", err);
                 time::sleep(Duration::from_secs(1)).await;
@@ -134,6 +149,13 @@ pub async fn run_tcp_redir(
         tokio::spawn(async move {
             let dst_addr = match socket.destination_addr(redir_ty) {
                 Ok(d) => d,
+                Err(err) if is_benign_close_error(&err) => {
+                    debug!(
+                        "TCP redirect couldn't get destination, client closed connection, peer: {}, error: {}",
+                        peer_addr, err
+                    );
+                    return;
+                }
                 Err(err) => {
                     error!(
                         "TCP redirect couldn't get destination, peer: {}, error: {}",
@@ -3,13 +3,14 @@
 use std::{io, net::SocketAddr, sync::Arc, time::Duration};
 
 use futures::{future, FutureExt};
-use log::{error, info};
+use log::{debug, error, info};
 use shadowsocks::{lookup_then, net::TcpListener as ShadowTcpListener};
 use tokio::{net::TcpStream, time};
 
 use crate::{
     config::{ClientConfig, Mode},
     local::{context::ServiceContext, loadbalancing::PingBalancer},
+    net::utils::is_benign_close_error,
 };
 
 #[cfg(feature = "local-socks4")]
@@ -125,6 +126,10 @@ impl Socks {
         loop {
             let (stream, peer_addr) = match listener.accept().await {
                 Ok(s) => s,
+                Err(err) if is_benign_close_error(&err) => {
+                    debug!("accept failed, client closed connection, error: {}", err);
+                    continue;
+                }
                 Err(err) => {
                     error!("accept failed with error: {}", err);
                     time::sleep(Duration::from_secs(1)).await;
@@ -82,11 +82,10 @@ impl Socks4TcpHandler {
             return Ok(());
         }
 
-        let server = self.balancer.best_tcp_server();
-        let svr_cfg = server.server_config();
+        let server = self.balancer.best_tcp_server_for(peer_addr.ip());
         let target_addr = target_addr.into();
 
-        let mut remote = match AutoProxyClientStream::connect(self.context, &server, &target_addr).await {
+        let mut remote = match AutoProxyClientStream::connect(self.context.clone(), &server, &target_addr).await {
             Ok(remote) => {
                 // Tell the client that we are ready
                 let handshake_rsp = HandshakeResponse::new(ResultCode::RequestGranted);
@@ -127,7 +126,8 @@ impl Socks4TcpHandler {
         let (mut shadow_reader, mut shadow_writer) = remote.into_split();
 
         establish_tcp_tunnel(
-            svr_cfg,
+            &self.context,
+            &server,
             &mut plain_reader,
             &mut plain_writer,
             &mut shadow_reader,
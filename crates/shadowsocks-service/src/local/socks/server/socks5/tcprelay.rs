@@ -1,35 +1,87 @@
 //! SOCKS5 TCP Server
+//!
+//! If `socks5_server_hint` is enabled, clients could choose the server for a connection by authenticating with a
+//! username (RFC1929) containing a `server:<name>` field, where `<name>` is the `remarks` or `id` of a server in the
+//! configuration. Fields in the username are separated by `;`, for example `alice;server:tokyo-1` or just
+//! `server:tokyo-1`.
+//!
+//! No authentication is preferred if the client offers both methods, so clients have to offer only the
+//! username/password method to send a hint. The password is not verified, any credential is accepted.
+//!
+//! The hint field is removed from the username, the remaining fields are the credential username. Connections will be
+//! sent to the best server chosen by the balancer if no hint was given or the named server doesn't exist. A hint sent
+//! with UDP ASSOCIATE applies to UDP packets from the client's IP until the control connection is closed.
 
 use std::{
     io::{self, ErrorKind},
     net::{Ipv4Addr, SocketAddr},
     sync::Arc,
+    time::Duration,
 };
 
-use log::{debug, error, trace, warn};
+use log::{debug, trace, warn};
 use shadowsocks::relay::socks5::{
     self,
     Address,
     Command,
     HandshakeRequest,
     HandshakeResponse,
+    PasswdAuthRequest,
+    PasswdAuthResponse,
     Reply,
     TcpRequestHeader,
     TcpResponseHeader,
 };
-use tokio::net::TcpStream;
+use tokio::{
+    net::TcpStream,
+    time::{self, Instant},
+};
 
 use crate::{
     config::{ClientConfig, Mode},
     local::{
         context::ServiceContext,
-        loadbalancing::PingBalancer,
+        loadbalancing::{PingBalancer, ServerIdent},
         net::AutoProxyClientStream,
         utils::establish_tcp_tunnel,
     },
     net::utils::ignore_until_end,
 };
 
+/// Timeout for the whole handshake, from the method selection to the request header
+///
+/// Clients that claim more bytes than they actually send, like a domain name with a lying length prefix, would
+/// otherwise hold the connection forever.
+const SOCKS5_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+const SERVER_HINT_FIELD_SEPARATOR: &str = ";";
+const SERVER_HINT_PREFIX: &str = "server:";
+
+/// Split the `server:<name>` hint out of the SOCKS5 username
+///
+/// Returns the credential username with the hint field removed and the hinted server name.
+fn split_server_hint(uname: &str) -> (String, Option<&str>) {
+    let mut server_name = None;
+    let mut fields = Vec::new();
+
+    for field in uname.split(SERVER_HINT_FIELD_SEPARATOR) {
+        if let Some(name) = field.strip_prefix(SERVER_HINT_PREFIX) {
+            if !name.is_empty() {
+                server_name = Some(name);
+            }
+        } else {
+            fields.push(field);
+        }
+    }
+
+    (fields.join(SERVER_HINT_FIELD_SEPARATOR), server_name)
+}
+
+fn handshake_timed_out(peer_addr: SocketAddr) -> io::Error {
+    warn!("socks5 client {} handshake timed out", peer_addr);
+    io::Error::new(ErrorKind::TimedOut, "socks5 handshake timed out")
+}
+
 pub struct Socks5TcpHandler {
     context: Arc<ServiceContext>,
     udp_bind_addr: Option<Arc<ClientConfig>>,
@@ -58,11 +110,54 @@ impl Socks5TcpHandler {
     pub async fn handle_socks5_client(self, mut stream: TcpStream, peer_addr: SocketAddr) -> io::Result<()> {
         // 1. Handshake
 
-        let handshake_req = HandshakeRequest::read_from(&mut stream).await?;
+        let deadline = Instant::now() + SOCKS5_HANDSHAKE_TIMEOUT;
+
+        let handshake_req = match time::timeout_at(deadline, HandshakeRequest::read_from(&mut stream)).await {
+            Ok(r) => r?,
+            Err(..) => return Err(handshake_timed_out(peer_addr)),
+        };
 
         trace!("socks5 {:?}", handshake_req);
 
-        if !handshake_req.methods.contains(&socks5::SOCKS5_AUTH_METHOD_NONE) {
+        let mut server_hint = None;
+
+        if handshake_req.methods.contains(&socks5::SOCKS5_AUTH_METHOD_NONE) {
+            // Reply to client
+            let resp = HandshakeResponse::new(socks5::SOCKS5_AUTH_METHOD_NONE);
+            trace!("reply handshake {:?}", resp);
+            resp.write_to(&mut stream).await?;
+        } else if self.context.socks5_server_hint()
+            && handshake_req.methods.contains(&socks5::SOCKS5_AUTH_METHOD_PASSWORD)
+        {
+            // Username/password only carries the server hint, the password is not verified
+            let resp = HandshakeResponse::new(socks5::SOCKS5_AUTH_METHOD_PASSWORD);
+            trace!("reply handshake {:?}", resp);
+            resp.write_to(&mut stream).await?;
+
+            let auth_req = match time::timeout_at(deadline, PasswdAuthRequest::read_from(&mut stream)).await {
+                Ok(r) => r?,
+                Err(..) => return Err(handshake_timed_out(peer_addr)),
+            };
+            let uname = String::from_utf8_lossy(&auth_req.uname);
+            let (user, server_name) = split_server_hint(&uname);
+
+            trace!("socks5 authentication user {:?}, server hint {:?}", user, server_name);
+
+            if let Some(server_name) = server_name {
+                match self.balancer.server_by_name(server_name) {
+                    Some(server) => server_hint = Some(server),
+                    None => {
+                        warn!(
+                            "socks5 client {} requested unknown server {:?}, fallback to the balancer",
+                            peer_addr, server_name
+                        );
+                    }
+                }
+            }
+
+            let resp = PasswdAuthResponse::new(socks5::SOCKS5_PASSWORD_AUTH_SUCCEEDED);
+            resp.write_to(&mut stream).await?;
+        } else {
             use std::io::Error;
 
             let resp = HandshakeResponse::new(socks5::SOCKS5_AUTH_METHOD_NOT_ACCEPTABLE);
@@ -72,22 +167,26 @@ impl Socks5TcpHandler {
                 ErrorKind::Other,
                 "currently shadowsocks-rust does not support authentication",
             ));
-        } else {
-            // Reply to client
-            let resp = HandshakeResponse::new(socks5::SOCKS5_AUTH_METHOD_NONE);
-            trace!("reply handshake {:?}", resp);
-            resp.write_to(&mut stream).await?;
         }
 
         // 2. Fetch headers
-        let header = match TcpRequestHeader::read_from(&mut stream).await {
-            Ok(h) => h,
-            Err(err) => {
-                error!("failed to get TcpRequestHeader: {}", err);
+        let header = match time::timeout_at(deadline, TcpRequestHeader::read_from(&mut stream)).await {
+            Ok(Ok(h)) => h,
+            Ok(Err(err)) => {
+                warn!(
+                    "socks5 client {} sent malformed request header, error: {}",
+                    peer_addr, err
+                );
                 let rh = TcpResponseHeader::new(err.as_reply(), Address::SocketAddress(peer_addr));
                 rh.write_to(&mut stream).await?;
                 return Err(err.into());
             }
+            Err(..) => {
+                let err = handshake_timed_out(peer_addr);
+                let rh = TcpResponseHeader::new(Reply::GeneralFailure, Address::SocketAddress(peer_addr));
+                rh.write_to(&mut stream).await?;
+                return Err(err);
+            }
         };
 
         trace!("socks5 {:?}", header);
@@ -99,12 +198,12 @@ impl Socks5TcpHandler {
             Command::TcpConnect => {
                 debug!("CONNECT {}", addr);
 
-                self.handle_tcp_connect(stream, peer_addr, addr).await
+                self.handle_tcp_connect(stream, peer_addr, addr, server_hint).await
             }
             Command::UdpAssociate => {
                 debug!("UDP ASSOCIATE from {}", addr);
 
-                self.handle_udp_associate(stream, addr).await
+                self.handle_udp_associate(stream, peer_addr, addr, server_hint).await
             }
             Command::TcpBind => {
                 warn!("BIND is not supported");
@@ -121,6 +220,7 @@ impl Socks5TcpHandler {
         mut stream: TcpStream,
         peer_addr: SocketAddr,
         target_addr: Address,
+        server_hint: Option<Arc<ServerIdent>>,
     ) -> io::Result<()> {
         if !self.mode.enable_tcp() {
             warn!("TCP CONNECT is disabled");
@@ -131,8 +231,17 @@ impl Socks5TcpHandler {
             return Ok(());
         }
 
-        let server = self.balancer.best_tcp_server();
-        let svr_cfg = server.server_config();
+        let server = match server_hint {
+            Some(server) => {
+                debug!(
+                    "CONNECT {} through hinted server {}",
+                    target_addr,
+                    server.server_config().addr()
+                );
+                server
+            }
+            None => self.balancer.best_tcp_server_for(peer_addr.ip()),
+        };
 
         let remote = match AutoProxyClientStream::connect(self.context.clone(), &server, &target_addr).await {
             Ok(remote) => {
@@ -168,7 +277,8 @@ impl Socks5TcpHandler {
         let (mut shadow_reader, mut shadow_writer) = remote.into_split();
 
         establish_tcp_tunnel(
-            svr_cfg,
+            &self.context,
+            &server,
             &mut plain_reader,
             &mut plain_writer,
             &mut shadow_reader,
@@ -179,7 +289,13 @@ impl Socks5TcpHandler {
         .await
     }
 
-    async fn handle_udp_associate(self, mut stream: TcpStream, client_addr: Address) -> io::Result<()> {
+    async fn handle_udp_associate(
+        self,
+        mut stream: TcpStream,
+        peer_addr: SocketAddr,
+        client_addr: Address,
+        server_hint: Option<Arc<ServerIdent>>,
+    ) -> io::Result<()> {
         match self.udp_bind_addr {
             None => {
                 warn!("socks5 udp is disabled");
@@ -192,13 +308,30 @@ impl Socks5TcpHandler {
             Some(bind_addr) => {
                 // shadowsocks accepts both TCP and UDP from the same address
 
+                // Associations are identified by the client's IP, the hint lasts until the control connection closes.
+                // Closing this connection only removes its own hint, other associations of the same client keep theirs.
+                if let Some(ref server) = server_hint {
+                    debug!(
+                        "UDP ASSOCIATE from {} through hinted server {}",
+                        peer_addr,
+                        server.server_config().addr()
+                    );
+                    self.balancer.set_udp_server_hint(peer_addr.ip(), server.clone());
+                }
+
                 let rh = TcpResponseHeader::new(socks5::Reply::Succeeded, bind_addr.as_ref().into());
-                rh.write_to(&mut stream).await?;
+                let result = rh.write_to(&mut stream).await;
 
-                // Hold connection until EOF.
-                let _ = ignore_until_end(&mut stream).await;
+                if result.is_ok() {
+                    // Hold connection until EOF.
+                    let _ = ignore_until_end(&mut stream).await;
+                }
 
-                Ok(())
+                if let Some(ref server) = server_hint {
+                    self.balancer.clear_udp_server_hint(peer_addr.ip(), server);
+                }
+
+                result
             }
         }
     }
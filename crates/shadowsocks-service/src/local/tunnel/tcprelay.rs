@@ -2,7 +2,7 @@
 
 use std::{io, net::SocketAddr, sync::Arc, time::Duration};
 
-use log::{error, info, trace};
+use log::{debug, error, info, trace};
 use shadowsocks::{lookup_then, net::TcpListener as ShadowTcpListener, relay::socks5::Address};
 use tokio::{net::TcpStream, time};
 
@@ -14,6 +14,7 @@ use crate::{
         net::AutoProxyClientStream,
         utils::establish_tcp_tunnel,
     },
+    net::utils::is_benign_close_error,
 };
 
 pub async fn run_tcp_tunnel(
@@ -38,6 +39,10 @@ pub async fn run_tcp_tunnel(
     loop {
         let (stream, peer_addr) = match listener.accept().await {
             Ok(s) => s,
+            Err(err) if is_benign_close_error(&err) => {
+                debug!("accept failed, client closed connection, error: {}", err);
+                continue;
+            }
             Err(err) => {
                 error!("accept failed with error: {}", err);
                 time::sleep(Duration::from_secs(1)).await;
@@ -71,7 +76,7 @@ async fn handle_tcp_client(
     forward_addr: Address,
     nodelay: bool,
 ) -> io::Result<()> {
-    let server = balancer.best_tcp_server();
+    let server = balancer.best_tcp_server_for(peer_addr.ip());
     let svr_cfg = server.server_config();
     trace!(
         "establishing tcp tunnel {} <-> {} through sever {} (outbound: {})",
@@ -81,7 +86,7 @@ async fn handle_tcp_client(
         svr_cfg.addr(),
     );
 
-    let remote = AutoProxyClientStream::connect_proxied(context, &server, &forward_addr).await?;
+    let remote = AutoProxyClientStream::connect_proxied(context.clone(), &server, &forward_addr).await?;
 
     if nodelay {
         remote.set_nodelay(true)?;
@@ -91,7 +96,8 @@ async fn handle_tcp_client(
     let (mut shadow_reader, mut shadow_writer) = remote.into_split();
 
     establish_tcp_tunnel(
-        svr_cfg,
+        &context,
+        &server,
         &mut plain_reader,
         &mut plain_writer,
         &mut shadow_reader,
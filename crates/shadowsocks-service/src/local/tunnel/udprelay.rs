@@ -25,12 +25,17 @@ use tokio::{
 use crate::{
     config::ClientConfig,
     local::{context::ServiceContext, loadbalancing::PingBalancer},
-    net::MonProxySocket,
+    net::{
+        udp_eviction::{evict_if_full, UdpAssociationUsage},
+        udp_send_error::{handle_send_error, UdpSendErrorKind},
+        MonProxySocket,
+    },
 };
 
 pub struct UdpTunnel {
     context: Arc<ServiceContext>,
     assoc_map: Arc<Mutex<LruCache<SocketAddr, UdpAssociation>>>,
+    capacity: Option<usize>,
     cleanup_abortable: AbortHandle,
 }
 
@@ -43,10 +48,8 @@ impl Drop for UdpTunnel {
 impl UdpTunnel {
     pub fn new(context: Arc<ServiceContext>, time_to_live: Option<Duration>, capacity: Option<usize>) -> UdpTunnel {
         let time_to_live = time_to_live.unwrap_or(crate::DEFAULT_UDP_EXPIRY_DURATION);
-        let assoc_map = Arc::new(Mutex::new(match capacity {
-            Some(capacity) => LruCache::with_expiry_duration_and_capacity(time_to_live, capacity),
-            None => LruCache::with_expiry_duration(time_to_live),
-        }));
+        // Capacity is kept by `evict_if_full` with the configured eviction policy
+        let assoc_map = Arc::new(Mutex::new(LruCache::with_expiry_duration(time_to_live)));
 
         let cleanup_abortable = {
             let assoc_map = assoc_map.clone();
@@ -65,6 +68,7 @@ impl UdpTunnel {
         UdpTunnel {
             context,
             assoc_map,
+            capacity,
             cleanup_abortable,
         }
     }
@@ -126,9 +130,21 @@ impl UdpTunnel {
         data: &[u8],
     ) -> io::Result<()> {
         let mut assoc_map = self.assoc_map.lock().await;
+
+        if let Some(capacity) = self.capacity {
+            evict_if_full(
+                &mut assoc_map,
+                &peer_addr,
+                capacity,
+                self.context.udp_eviction_policy(),
+                |assoc| &assoc.usage,
+            );
+        }
+
         match assoc_map.entry(peer_addr) {
             Entry::Occupied(occ) => {
                 let assoc = occ.into_mut();
+                assoc.usage.touch();
                 assoc.try_send(Bytes::copy_from_slice(data))
             }
             Entry::Vacant(vac) => {
@@ -141,6 +157,7 @@ impl UdpTunnel {
                     balancer.clone(),
                 ));
                 trace!("created udp association for {}", peer_addr);
+                assoc.usage.touch();
                 assoc.try_send(Bytes::copy_from_slice(data))
             }
         }
@@ -150,6 +167,7 @@ impl UdpTunnel {
 struct UdpAssociation {
     sender: mpsc::Sender<Bytes>,
     assoc: Arc<UdpAssociationContext>,
+    usage: UdpAssociationUsage,
 }
 
 impl Drop for UdpAssociation {
@@ -169,7 +187,11 @@ impl UdpAssociation {
     ) -> UdpAssociation {
         let (assoc, sender) =
             UdpAssociationContext::new(context, inbound, peer_addr, forward_addr, assoc_map, balancer);
-        UdpAssociation { sender, assoc }
+        UdpAssociation {
+            sender,
+            assoc,
+            usage: UdpAssociationUsage::new(),
+        }
     }
 
     fn try_send(&self, data: Bytes) -> io::Result<()> {
@@ -295,7 +317,7 @@ impl UdpAssociationContext {
                     UdpAssociationState::Empty => {
                         // Create a new connection to proxy server
 
-                        let server = self.balancer.best_udp_server();
+                        let server = self.balancer.best_udp_server_for(self.peer_addr.ip());
                         let svr_cfg = server.server_config();
 
                         let socket = ProxySocket::connect_with_opts(
@@ -349,8 +371,15 @@ impl UdpAssociationContext {
                         tried + 1,
                         err
                     );
+
+                    let too_large = UdpSendErrorKind::from_io_error(&err) == UdpSendErrorKind::MessageTooLarge;
                     last_err = err;
 
+                    // Retrying won't help a packet that is too large
+                    if too_large {
+                        break;
+                    }
+
                     // Reset for reconnecting
                     self.proxied_socket.lock().reset();
 
@@ -359,7 +388,17 @@ impl UdpAssociationContext {
             }
         }
 
-        Err(last_err)
+        handle_send_error(
+            &self.assoc_map,
+            self.context.udp_send_error_stat_ref(),
+            self.context.udp_send_error_policy(),
+            self.peer_addr,
+            target_addr,
+            data.len(),
+            &last_err,
+        )
+        .await;
+        Ok(())
     }
 
     async fn copy_proxied_r2l(self: Arc<Self>, outbound: Arc<MonProxySocket>) -> io::Result<()> {
@@ -368,7 +407,9 @@ impl UdpAssociationContext {
             let (n, _) = match outbound.recv(&mut buffer).await {
                 Ok(n) => {
                     // Keep association alive in map
-                    let _ = self.assoc_map.lock().await.get(&self.peer_addr);
+                    if let Some(assoc) = self.assoc_map.lock().await.get_mut(&self.peer_addr) {
+                        assoc.usage.touch();
+                    }
                     n
                 }
                 Err(err) => {
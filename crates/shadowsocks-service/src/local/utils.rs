@@ -1,25 +1,38 @@
 //! Shadowsocks Local Utilities
 
-use std::{io, net::SocketAddr, time::Duration};
-
-use futures::future::{self, Either};
-use log::trace;
-use shadowsocks::{
-    config::ServerConfig,
-    relay::{
-        socks5::Address,
-        tcprelay::utils::{copy_from_encrypted, copy_to_encrypted},
-    },
+use std::{
+    io::{self, ErrorKind},
+    net::SocketAddr,
+    time::Duration,
+};
+
+use log::{trace, warn};
+use shadowsocks::relay::{
+    socks5::Address,
+    tcprelay::utils::{copy_from_encrypted, copy_to_encrypted},
 };
 use tokio::{
     io::{copy, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
     time,
 };
 
-use crate::local::net::AutoProxyIo;
+use crate::{
+    local::{context::ServiceContext, loadbalancing::ServerIdent, net::AutoProxyIo},
+    net::utils::{
+        lifetime_deadline,
+        relay_tcp_tunnel,
+        shutdown_on_lifetime_exceeded,
+        LifetimeReader,
+        TcpTunnelCloseReason,
+    },
+};
+
+/// Delay between segments of the split first packet
+const SPLIT_FIRST_PACKET_DELAY: Duration = Duration::from_millis(10);
 
 pub async fn establish_tcp_tunnel<PR, PW, SR, SW>(
-    svr_cfg: &ServerConfig,
+    context: &ServiceContext,
+    server: &ServerIdent,
     plain_reader: &mut PR,
     plain_writer: &mut PW,
     shadow_reader: &mut SR,
@@ -33,6 +46,8 @@ where
     SR: AsyncRead + AutoProxyIo + Unpin,
     SW: AsyncWrite + AutoProxyIo + Unpin,
 {
+    let svr_cfg = server.server_config();
+
     if shadow_reader.is_proxied() && shadow_writer.is_proxied() {
         trace!(
             "established tcp tunnel {} <-> {} through sever {} (outbound: {})",
@@ -44,6 +59,7 @@ where
     } else {
         trace!("established tcp tunnel {} <-> {} bypassed", peer_addr, target_addr);
         return establish_tcp_tunnel_bypassed(
+            context,
             plain_reader,
             plain_writer,
             shadow_reader,
@@ -59,6 +75,10 @@ where
     // Protocols like FTP, clients will wait for servers to send Welcome Message without sending anything.
     //
     // Wait at most 500ms, and then sends handshake packet to remote servers.
+    //
+    // The first packet is sent together with the target address in the handshake. `split_first_packet` is not
+    // applied here, the target address and data are encrypted, splitting would only change the AEAD chunks.
+    let mut sent_first_packet = false;
     {
         let mut buffer = [0u8; 8192];
         match time::timeout(Duration::from_millis(500), plain_reader.read(&mut buffer)).await {
@@ -69,6 +89,7 @@ where
             Ok(Ok(n)) => {
                 // Send the first packet.
                 shadow_writer.write_all(&buffer[..n]).await?;
+                sent_first_packet = true;
             }
             Ok(Err(err)) => return Err(err),
             Err(..) => {
@@ -84,31 +105,62 @@ where
         }
     }
 
-    let l2r = copy_to_encrypted(svr_cfg.method(), plain_reader, shadow_writer);
-    let r2l = copy_from_encrypted(svr_cfg.method(), shadow_reader, plain_writer);
+    // Servers respond only after targets respond, so they could only be verified if client has sent something.
+    // Dead routes (resets after TCP handshake, or closed before responding) are reported to the balancer, which
+    // counts against the server's score. Slow responses are not failures, targets may take their time.
+    let verify_server_response = sent_first_packet && shadow_reader.is_proxied() && context.verify_server_response();
 
-    tokio::pin!(l2r);
-    tokio::pin!(r2l);
+    let deadline = lifetime_deadline(context.max_connection_lifetime());
 
-    match future::select(l2r, r2l).await {
-        Either::Left((Ok(..), ..)) => {
-            trace!("tcp tunnel {} -> {} closed", peer_addr, target_addr);
-        }
-        Either::Left((Err(err), ..)) => {
-            trace!("tcp tunnel {} -> {} closed with error: {}", peer_addr, target_addr, err);
-        }
-        Either::Right((Ok(..), ..)) => {
-            trace!("tcp tunnel {} <- {} closed", peer_addr, target_addr);
-        }
-        Either::Right((Err(err), ..)) => {
-            trace!("tcp tunnel {} <- {} closed with error: {}", peer_addr, target_addr, err);
+    let mut plain_reader = LifetimeReader::new(plain_reader, deadline);
+
+    let l2r = copy_to_encrypted(svr_cfg.method(), &mut plain_reader, shadow_writer);
+    let r2l = async {
+        if verify_server_response {
+            let mut buffer = [0u8; 8192];
+            let n = match shadow_reader.read(&mut buffer).await {
+                Ok(0) => {
+                    warn!(
+                        "tcp tunnel {} <- {} server {} closed before responding",
+                        peer_addr,
+                        target_addr,
+                        svr_cfg.addr()
+                    );
+                    server.tcp_score().report_failure().await;
+                    return Ok(0);
+                }
+                Ok(n) => n,
+                Err(err) => {
+                    if let ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted = err.kind() {
+                        warn!(
+                            "tcp tunnel {} <- {} server {} reset before responding, error: {}",
+                            peer_addr,
+                            target_addr,
+                            svr_cfg.addr(),
+                            err
+                        );
+                        server.tcp_score().report_failure().await;
+                    }
+                    return Err(err);
+                }
+            };
+
+            plain_writer.write_all(&buffer[..n]).await?;
         }
+
+        let mut shadow_reader = LifetimeReader::new(&mut *shadow_reader, deadline);
+        copy_from_encrypted(svr_cfg.method(), &mut shadow_reader, plain_writer).await
+    };
+
+    if let TcpTunnelCloseReason::LifetimeExceeded = relay_tcp_tunnel(peer_addr, target_addr, deadline, l2r, r2l).await {
+        shutdown_on_lifetime_exceeded(shadow_writer, plain_writer).await;
     }
 
     Ok(())
 }
 
 async fn establish_tcp_tunnel_bypassed<PR, PW, SR, SW>(
+    context: &ServiceContext,
     plain_reader: &mut PR,
     plain_writer: &mut PW,
     shadow_reader: &mut SR,
@@ -122,26 +174,58 @@ where
     SR: AsyncRead + Unpin,
     SW: AsyncWrite + Unpin,
 {
-    let l2r = copy(plain_reader, shadow_writer);
-    let r2l = copy(shadow_reader, plain_writer);
+    // The first packet is sent without modification if it is not going to be split, so it can be simply copied with
+    // the rest, connections of servers that send their messages first are not delayed.
+    //
+    // Otherwise don't wait too long for the first packet, in case of servers that send their messages first.
+    if context.split_first_packet().is_some() {
+        let mut buffer = [0u8; 8192];
+        if let Ok(r) = time::timeout(Duration::from_millis(500), plain_reader.read(&mut buffer)).await {
+            match r? {
+                0 => return Ok(()),
+                n => write_first_packet(context, shadow_writer, &buffer[..n]).await?,
+            }
+        }
+    }
 
-    tokio::pin!(l2r);
-    tokio::pin!(r2l);
+    let deadline = lifetime_deadline(context.max_connection_lifetime());
 
-    match future::select(l2r, r2l).await {
-        Either::Left((Ok(..), ..)) => {
-            trace!("tcp tunnel {} -> {} closed", peer_addr, target_addr);
-        }
-        Either::Left((Err(err), ..)) => {
-            trace!("tcp tunnel {} -> {} closed with error: {}", peer_addr, target_addr, err);
-        }
-        Either::Right((Ok(..), ..)) => {
-            trace!("tcp tunnel {} <- {} closed", peer_addr, target_addr);
-        }
-        Either::Right((Err(err), ..)) => {
-            trace!("tcp tunnel {} <- {} closed with error: {}", peer_addr, target_addr, err);
-        }
+    let mut plain_reader = LifetimeReader::new(plain_reader, deadline);
+    let mut shadow_reader = LifetimeReader::new(shadow_reader, deadline);
+
+    let l2r = copy(&mut plain_reader, shadow_writer);
+    let r2l = copy(&mut shadow_reader, plain_writer);
+
+    if let TcpTunnelCloseReason::LifetimeExceeded = relay_tcp_tunnel(peer_addr, target_addr, deadline, l2r, r2l).await {
+        shutdown_on_lifetime_exceeded(shadow_writer, plain_writer).await;
     }
 
     Ok(())
 }
+
+/// Write the first packet of a bypassed TCP tunnel
+///
+/// The packet will be split into segments with `split_first_packet` bytes and sent with a tiny delay between them,
+/// which makes it harder for DPI to reassemble the first packet (TLS ClientHello with SNI for example).
+/// This is a best-effort evasion, the segments could still be merged by the network stack, such as TFO
+/// sending the first packet with SYN, or Nagle's algorithm when `TCP_NODELAY` is not enabled.
+async fn write_first_packet<W>(context: &ServiceContext, writer: &mut W, buf: &[u8]) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    match context.split_first_packet() {
+        Some(size) if size > 0 && buf.len() > size => {
+            let mut segments = buf.chunks(size).peekable();
+            while let Some(segment) = segments.next() {
+                writer.write_all(segment).await?;
+                writer.flush().await?;
+
+                if segments.peek().is_some() {
+                    time::sleep(SPLIT_FIRST_PACKET_DELAY).await;
+                }
+            }
+            Ok(())
+        }
+        _ => writer.write_all(buf).await,
+    }
+}
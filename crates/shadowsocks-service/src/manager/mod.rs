@@ -2,9 +2,10 @@
 //!
 //! Service for managing multiple relay servers. [Manage Multiple Users](https://github.com/shadowsocks/shadowsocks/wiki/Manage-Multiple-Users)
 
-use std::io::{self, ErrorKind};
-#[cfg(feature = "trust-dns")]
-use std::sync::Arc;
+use std::{
+    io::{self, ErrorKind},
+    sync::Arc,
+};
 
 use log::{trace, warn};
 use shadowsocks::{
@@ -12,7 +13,10 @@ use shadowsocks::{
     net::{AcceptOpts, ConnectOpts},
 };
 
-use crate::config::{Config, ConfigType};
+use crate::{
+    config::{Config, ConfigType},
+    plugin::PluginRegistry,
+};
 
 pub use self::server::Manager;
 
@@ -64,6 +68,9 @@ pub async fn run(config: Config) -> io::Result<()> {
         #[cfg(target_os = "android")]
         vpn_protect_path: config.outbound_vpn_protect_path,
 
+        #[cfg(unix)]
+        dscp: config.outbound_dscp,
+
         bind_local_addr: match config.local_addr {
             None => None,
             Some(ServerAddr::SocketAddr(sa)) => Some(sa.ip()),
@@ -83,11 +90,13 @@ pub async fn run(config: Config) -> io::Result<()> {
 
     connect_opts.tcp.send_buffer_size = config.outbound_send_buffer_size;
     connect_opts.tcp.recv_buffer_size = config.outbound_recv_buffer_size;
+    connect_opts.tcp.reuse_addr = config.outbound_reuse_addr;
     connect_opts.tcp.nodelay = config.no_delay;
 
     let mut accept_opts = AcceptOpts::default();
     accept_opts.tcp.send_buffer_size = config.inbound_send_buffer_size;
     accept_opts.tcp.recv_buffer_size = config.inbound_recv_buffer_size;
+    accept_opts.backlog = config.listen_backlog;
     accept_opts.tcp.nodelay = config.no_delay;
 
     manager.set_connect_opts(connect_opts);
@@ -101,6 +110,32 @@ pub async fn run(config: Config) -> io::Result<()> {
         manager.set_udp_expiry_duration(d);
     }
 
+    manager.set_udp_eviction_policy(config.udp_eviction_policy);
+    manager.set_udp_send_error_policy(config.udp_send_error_policy);
+    manager.set_unmap_ipv4(config.unmap_ipv4);
+    if let Some(d) = config.max_connection_lifetime {
+        manager.set_max_connection_lifetime(d);
+    }
+    manager.set_udp_source_verify(config.udp_source_verify);
+
+    let mut plugin_registry = PluginRegistry::new(config.max_plugin_processes);
+    if let Some(level) = config.plugin_log_level {
+        plugin_registry.set_log_level(level);
+    }
+    manager.set_plugin_registry(Arc::new(plugin_registry));
+
+    if let Some(s) = config.min_avg_chunk_size {
+        manager.set_min_avg_chunk_size(s);
+    }
+
+    if let Some(r) = config.per_ip_ramp {
+        manager.set_per_ip_ramp(r);
+    }
+
+    if let Some(collector) = config.netflow_collector {
+        manager.set_netflow_collector(collector);
+    }
+
     for svr_cfg in config.server {
         manager.add_server(svr_cfg, None).await;
     }
@@ -30,9 +30,10 @@ use tokio::sync::Mutex;
 
 use crate::{
     acl::AccessControl,
-    config::{ManagerConfig, ManagerServerHost, Mode},
+    config::{ManagerConfig, ManagerServerHost, Mode, RampConfig, UdpEvictionPolicy, UdpSendErrorPolicy},
     net::FlowStat,
-    server::Server,
+    plugin::PluginRegistry,
+    server::{ConnectionRamp, Server},
 };
 
 struct ServerInstance {
@@ -57,7 +58,16 @@ pub struct Manager {
     accept_opts: AcceptOpts,
     udp_expiry_duration: Option<Duration>,
     udp_capacity: Option<usize>,
+    udp_eviction_policy: UdpEvictionPolicy,
+    udp_send_error_policy: UdpSendErrorPolicy,
+    unmap_ipv4: bool,
+    max_connection_lifetime: Option<Duration>,
+    udp_source_verify: bool,
+    min_avg_chunk_size: Option<usize>,
+    connection_ramp: Option<Arc<ConnectionRamp>>,
+    netflow_collector: Option<SocketAddr>,
     acl: Option<Arc<AccessControl>>,
+    plugin_registry: Arc<PluginRegistry>,
 }
 
 impl Manager {
@@ -77,7 +87,16 @@ impl Manager {
             accept_opts: AcceptOpts::default(),
             udp_expiry_duration: None,
             udp_capacity: None,
+            udp_eviction_policy: UdpEvictionPolicy::default(),
+            udp_send_error_policy: UdpSendErrorPolicy::default(),
+            unmap_ipv4: true,
+            max_connection_lifetime: None,
+            udp_source_verify: false,
+            min_avg_chunk_size: None,
+            connection_ramp: None,
+            netflow_collector: None,
             acl: None,
+            plugin_registry: Arc::new(PluginRegistry::default()),
         }
     }
 
@@ -101,6 +120,53 @@ impl Manager {
         self.udp_capacity = Some(c);
     }
 
+    /// Set policy for choosing the UDP association to evict when there are already `udp_capacity` associations
+    pub fn set_udp_eviction_policy(&mut self, policy: UdpEvictionPolicy) {
+        self.udp_eviction_policy = policy;
+    }
+
+    /// Set policy for errors of sending UDP packets to targets
+    pub fn set_udp_send_error_policy(&mut self, policy: UdpSendErrorPolicy) {
+        self.udp_send_error_policy = policy;
+    }
+
+    /// Set whether IPv4-mapped IPv6 targets should be connected with IPv4 addresses
+    pub fn set_unmap_ipv4(&mut self, unmap: bool) {
+        self.unmap_ipv4 = unmap;
+    }
+
+    /// Set the maximum lifetime of TCP tunnels, regardless of activity
+    pub fn set_max_connection_lifetime(&mut self, lifetime: Duration) {
+        self.max_connection_lifetime = Some(lifetime);
+    }
+
+    /// Set whether UDP packets are only accepted from sources validated by TCP connections
+    pub fn set_udp_source_verify(&mut self, verify: bool) {
+        self.udp_source_verify = verify;
+    }
+
+    /// Set registry for starting plugin processes of all managed servers
+    ///
+    /// Servers added before this is called keep the registry they were started with.
+    pub fn set_plugin_registry(&mut self, registry: Arc<PluginRegistry>) {
+        self.plugin_registry = registry;
+    }
+
+    /// Set minimum average size of AEAD chunks received in one connection
+    pub fn set_min_avg_chunk_size(&mut self, size: usize) {
+        self.min_avg_chunk_size = Some(size);
+    }
+
+    /// Set connections ramp for each client IP, shared by all managed servers
+    pub fn set_per_ip_ramp(&mut self, config: RampConfig) {
+        self.connection_ramp = Some(Arc::new(ConnectionRamp::new(config)));
+    }
+
+    /// Set IPFIX collector's address for exporting TCP flow records of all managed servers
+    pub fn set_netflow_collector(&mut self, collector: SocketAddr) {
+        self.netflow_collector = Some(collector);
+    }
+
     /// Set server's default mode
     pub fn set_mode(&mut self, mode: Mode) {
         self.mode = mode;
@@ -185,6 +251,27 @@ impl Manager {
             server.set_udp_capacity(c);
         }
 
+        server.set_udp_eviction_policy(self.udp_eviction_policy);
+        server.set_udp_send_error_policy(self.udp_send_error_policy);
+        server.set_unmap_ipv4(self.unmap_ipv4);
+        if let Some(d) = self.max_connection_lifetime {
+            server.set_max_connection_lifetime(d);
+        }
+        server.set_udp_source_verify(self.udp_source_verify);
+        server.set_plugin_registry(self.plugin_registry.clone());
+
+        if let Some(s) = self.min_avg_chunk_size {
+            server.set_min_avg_chunk_size(s);
+        }
+
+        if let Some(ref ramp) = self.connection_ramp {
+            server.set_connection_ramp(ramp.clone());
+        }
+
+        if let Some(collector) = self.netflow_collector {
+            server.set_netflow_collector(collector);
+        }
+
         server.set_mode(mode.unwrap_or(self.mode));
 
         if let Some(ref acl) = self.acl {
@@ -206,7 +293,15 @@ impl Manager {
         let flow_stat = server.flow_stat().clone();
 
         let (server_fut, abortable) = future::abortable(async move { server.run().await });
-        tokio::spawn(server_fut);
+        tokio::spawn(async move {
+            // Servers may fail to start, for example, exceeded `max_plugin_processes`
+            if let Ok(Err(err)) = server_fut.await {
+                error!(
+                    "managed server listening on port {} exited with error: {}",
+                    server_port, err
+                );
+            }
+        });
 
         servers.insert(
             server_port,
@@ -5,4 +5,6 @@ pub use self::{flow::FlowStat, mon_socket::MonProxySocket, mon_stream::MonProxyS
 pub mod flow;
 pub mod mon_socket;
 pub mod mon_stream;
+pub mod udp_eviction;
+pub mod udp_send_error;
 pub mod utils;
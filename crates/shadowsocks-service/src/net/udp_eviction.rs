@@ -0,0 +1,171 @@
+//! Eviction of UDP associations
+//!
+//! When the association table has reached its capacity (`udp_max_associations`), one association is evicted before
+//! creating a new one. The association to evict is chosen by an `EvictionPolicy` from the usages of all associations
+//! in the table.
+
+use std::{
+    fmt,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use log::debug;
+use lru_time_cache::LruCache;
+
+use crate::config::UdpEvictionPolicy;
+
+/// Usage of a UDP association
+#[derive(Debug, Clone)]
+pub struct UdpAssociationUsage {
+    created: Instant,
+    last_used: Instant,
+    packets: u64,
+}
+
+impl UdpAssociationUsage {
+    /// Create usage for a new association
+    pub fn new() -> UdpAssociationUsage {
+        let now = Instant::now();
+        UdpAssociationUsage {
+            created: now,
+            last_used: now,
+            packets: 0,
+        }
+    }
+
+    /// Count a packet relayed by the association, in either direction
+    pub fn touch(&mut self) {
+        self.last_used = Instant::now();
+        self.packets += 1;
+    }
+
+    /// Time when the association was created
+    pub fn created(&self) -> Instant {
+        self.created
+    }
+
+    /// Time when the association relayed its last packet
+    pub fn last_used(&self) -> Instant {
+        self.last_used
+    }
+
+    /// Number of packets relayed by the association
+    pub fn packets(&self) -> u64 {
+        self.packets
+    }
+}
+
+impl Default for UdpAssociationUsage {
+    fn default() -> UdpAssociationUsage {
+        UdpAssociationUsage::new()
+    }
+}
+
+impl fmt::Display for UdpAssociationUsage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let now = Instant::now();
+        write!(
+            f,
+            "created {:?} ago, last used {:?} ago, {} packets",
+            elapsed(now, self.created),
+            elapsed(now, self.last_used),
+            self.packets
+        )
+    }
+}
+
+fn elapsed(now: Instant, t: Instant) -> Duration {
+    now.checked_duration_since(t).unwrap_or_default()
+}
+
+/// Policy for choosing the association to evict
+pub trait EvictionPolicy {
+    /// Check if association with usage `lhs` should be evicted before the one with usage `rhs`
+    fn evict_before(&self, lhs: &UdpAssociationUsage, rhs: &UdpAssociationUsage) -> bool;
+}
+
+/// Evicts the least recently used association
+pub struct LruEviction;
+
+impl EvictionPolicy for LruEviction {
+    fn evict_before(&self, lhs: &UdpAssociationUsage, rhs: &UdpAssociationUsage) -> bool {
+        lhs.last_used < rhs.last_used
+    }
+}
+
+/// Evicts the least frequently used association, the least recently used one if they have relayed the same number
+/// of packets
+pub struct LfuEviction;
+
+impl EvictionPolicy for LfuEviction {
+    fn evict_before(&self, lhs: &UdpAssociationUsage, rhs: &UdpAssociationUsage) -> bool {
+        (lhs.packets, lhs.last_used) < (rhs.packets, rhs.last_used)
+    }
+}
+
+/// Evicts the association created earliest
+pub struct OldestEviction;
+
+impl EvictionPolicy for OldestEviction {
+    fn evict_before(&self, lhs: &UdpAssociationUsage, rhs: &UdpAssociationUsage) -> bool {
+        lhs.created < rhs.created
+    }
+}
+
+/// Get the implementation of `policy`
+pub fn eviction_policy(policy: UdpEvictionPolicy) -> &'static (dyn EvictionPolicy + Send + Sync) {
+    match policy {
+        UdpEvictionPolicy::Lru => &LruEviction,
+        UdpEvictionPolicy::Lfu => &LfuEviction,
+        UdpEvictionPolicy::Oldest => &OldestEviction,
+    }
+}
+
+/// Choose the association to evict from `usages` by `policy`
+pub fn select_victim<'a, K, I>(policy: &dyn EvictionPolicy, usages: I) -> Option<&'a K>
+where
+    I: IntoIterator<Item = (&'a K, &'a UdpAssociationUsage)>,
+{
+    let mut victim: Option<(&'a K, &'a UdpAssociationUsage)> = None;
+    for (key, usage) in usages {
+        match victim {
+            Some((_, victim_usage)) if !policy.evict_before(usage, victim_usage) => {}
+            _ => victim = Some((key, usage)),
+        }
+    }
+    victim.map(|(key, _)| key)
+}
+
+/// Evict one association from `assoc_map` if it is full, before creating an association for `peer_addr`
+pub fn evict_if_full<V, F>(
+    assoc_map: &mut LruCache<SocketAddr, V>,
+    peer_addr: &SocketAddr,
+    capacity: usize,
+    policy: UdpEvictionPolicy,
+    usage: F,
+) where
+    F: Fn(&V) -> &UdpAssociationUsage,
+{
+    if assoc_map.len() < capacity || assoc_map.contains_key(peer_addr) {
+        return;
+    }
+
+    let victim = select_victim(
+        eviction_policy(policy),
+        assoc_map.peek_iter().map(|(addr, assoc)| (addr, usage(assoc))),
+    )
+    .copied();
+
+    if let Some(addr) = victim {
+        if let Some(assoc) = assoc_map.remove(&addr) {
+            debug!(
+                "udp association for {} evicted by {} policy, {} associations at capacity, {}",
+                addr,
+                policy,
+                capacity,
+                usage(&assoc)
+            );
+        }
+    }
+}
@@ -0,0 +1,226 @@
+//! Errors of sending UDP packets to targets
+//!
+//! Some errors only concern the packet that is being sent, for example `EMSGSIZE` for a packet larger than the path
+//! MTU (with `DF` set) or the maximum datagram size. The other packets of the same association could still be sent
+//! successfully, so these errors never tear down the association, whatever the `UdpSendErrorPolicy` is.
+//!
+//! Errors are counted by kind in each server's context, the counts are reported in the log along with every error.
+
+use std::{
+    fmt,
+    io,
+    net::SocketAddr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use log::{error, warn};
+use lru_time_cache::LruCache;
+use tokio::sync::Mutex;
+
+use crate::config::UdpSendErrorPolicy;
+
+#[cfg(windows)]
+const WSAEMSGSIZE: i32 = 10040;
+#[cfg(windows)]
+const WSAENETUNREACH: i32 = 10051;
+#[cfg(windows)]
+const WSAEHOSTUNREACH: i32 = 10065;
+
+/// Kind of errors of sending UDP packets
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UdpSendErrorKind {
+    /// Packet is too large to be sent (`EMSGSIZE`)
+    MessageTooLarge,
+    /// Network or host is unreachable (`ENETUNREACH`, `EHOSTUNREACH`)
+    Unreachable,
+    /// Any other errors
+    Other,
+}
+
+impl UdpSendErrorKind {
+    /// Classify an error returned by `send_to`
+    pub fn from_io_error(err: &io::Error) -> UdpSendErrorKind {
+        match err.raw_os_error() {
+            #[cfg(unix)]
+            Some(libc::EMSGSIZE) => UdpSendErrorKind::MessageTooLarge,
+            #[cfg(unix)]
+            Some(libc::ENETUNREACH) | Some(libc::EHOSTUNREACH) => UdpSendErrorKind::Unreachable,
+            #[cfg(windows)]
+            Some(WSAEMSGSIZE) => UdpSendErrorKind::MessageTooLarge,
+            #[cfg(windows)]
+            Some(WSAENETUNREACH) | Some(WSAEHOSTUNREACH) => UdpSendErrorKind::Unreachable,
+            _ => UdpSendErrorKind::Other,
+        }
+    }
+
+    /// Action to take for this kind of error with `policy`
+    pub fn action(self, policy: UdpSendErrorPolicy) -> UdpSendErrorAction {
+        match self {
+            UdpSendErrorKind::MessageTooLarge => UdpSendErrorAction::DropPacket,
+            UdpSendErrorKind::Unreachable | UdpSendErrorKind::Other => match policy {
+                UdpSendErrorPolicy::DropPacket => UdpSendErrorAction::DropPacket,
+                UdpSendErrorPolicy::DropAssociation => UdpSendErrorAction::DropAssociation,
+            },
+        }
+    }
+}
+
+impl fmt::Display for UdpSendErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UdpSendErrorKind::MessageTooLarge => f.write_str("message too large"),
+            UdpSendErrorKind::Unreachable => f.write_str("unreachable"),
+            UdpSendErrorKind::Other => f.write_str("other"),
+        }
+    }
+}
+
+/// Action to take after failing to send a UDP packet
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UdpSendErrorAction {
+    /// Drop the packet, the association is kept
+    DropPacket,
+    /// Drop the packet and close the association
+    DropAssociation,
+}
+
+/// Count and log `err` of sending a packet of `len` bytes from `peer_addr` to `target_addr`
+///
+/// Returns the action to take with `policy`, the caller is responsible for dropping the association.
+pub fn report_send_error<A: fmt::Display>(
+    stat: &UdpSendErrorStat,
+    policy: UdpSendErrorPolicy,
+    peer_addr: SocketAddr,
+    target_addr: A,
+    len: usize,
+    err: &io::Error,
+) -> UdpSendErrorAction {
+    let kind = UdpSendErrorKind::from_io_error(err);
+    let count = stat.incr(kind);
+
+    let action = kind.action(policy);
+    match action {
+        UdpSendErrorAction::DropPacket => {
+            warn!(
+                "udp relay {} -> {} dropped packet {} bytes, {} error ({} in total): {}",
+                peer_addr, target_addr, len, kind, count, err
+            );
+        }
+        UdpSendErrorAction::DropAssociation => {
+            error!(
+                "udp relay {} -> {} dropped packet {} bytes and association, {} error ({} in total): {}",
+                peer_addr, target_addr, len, kind, count, err
+            );
+        }
+    }
+    action
+}
+
+/// Count and log `err` of sending a packet of `len` bytes from `peer_addr` to `target_addr`, and then drop the
+/// association of `peer_addr` from `assoc_map` if `policy` requires
+///
+/// Outbound sockets are aborted when the association is dropped.
+pub async fn handle_send_error<V, A: fmt::Display>(
+    assoc_map: &Mutex<LruCache<SocketAddr, V>>,
+    stat: &UdpSendErrorStat,
+    policy: UdpSendErrorPolicy,
+    peer_addr: SocketAddr,
+    target_addr: A,
+    len: usize,
+    err: &io::Error,
+) {
+    let action = report_send_error(stat, policy, peer_addr, target_addr, len, err);
+    if action == UdpSendErrorAction::DropAssociation {
+        assoc_map.lock().await.remove(&peer_addr);
+    }
+}
+
+/// Number of UDP send errors of each kind
+pub struct UdpSendErrorStat {
+    message_too_large: AtomicUsize,
+    unreachable: AtomicUsize,
+    other: AtomicUsize,
+}
+
+impl UdpSendErrorStat {
+    /// Create an empty statistic
+    pub fn new() -> UdpSendErrorStat {
+        UdpSendErrorStat {
+            message_too_large: AtomicUsize::new(0),
+            unreachable: AtomicUsize::new(0),
+            other: AtomicUsize::new(0),
+        }
+    }
+
+    fn counter(&self, kind: UdpSendErrorKind) -> &AtomicUsize {
+        match kind {
+            UdpSendErrorKind::MessageTooLarge => &self.message_too_large,
+            UdpSendErrorKind::Unreachable => &self.unreachable,
+            UdpSendErrorKind::Other => &self.other,
+        }
+    }
+
+    /// Count an error of `kind`, returns the total number of errors of that kind
+    pub fn incr(&self, kind: UdpSendErrorKind) -> usize {
+        self.counter(kind).fetch_add(1, Ordering::AcqRel) + 1
+    }
+
+    /// Number of errors of `kind`
+    pub fn count(&self, kind: UdpSendErrorKind) -> usize {
+        self.counter(kind).load(Ordering::Relaxed)
+    }
+}
+
+impl Default for UdpSendErrorStat {
+    fn default() -> UdpSendErrorStat {
+        UdpSendErrorStat::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn udp_send_error_unreachable() {
+        for &code in &[libc::ENETUNREACH, libc::EHOSTUNREACH] {
+            let err = io::Error::from_raw_os_error(code);
+            let kind = UdpSendErrorKind::from_io_error(&err);
+            assert_eq!(kind, UdpSendErrorKind::Unreachable);
+
+            assert_eq!(
+                kind.action(UdpSendErrorPolicy::DropPacket),
+                UdpSendErrorAction::DropPacket
+            );
+            assert_eq!(
+                kind.action(UdpSendErrorPolicy::DropAssociation),
+                UdpSendErrorAction::DropAssociation
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn udp_send_error_drop_association() {
+        let peer_addr = SocketAddr::from(([127, 0, 0, 1], 8400));
+        let target_addr = SocketAddr::from(([127, 0, 0, 1], 8401));
+        let assoc_map = Mutex::new(LruCache::with_capacity(16));
+        assoc_map.lock().await.insert(peer_addr, ());
+
+        let stat = UdpSendErrorStat::new();
+        let policy = UdpSendErrorPolicy::DropAssociation;
+
+        // EMSGSIZE only concerns the packet
+        let err = io::Error::from_raw_os_error(libc::EMSGSIZE);
+        handle_send_error(&assoc_map, &stat, policy, peer_addr, target_addr, 65535, &err).await;
+        assert!(assoc_map.lock().await.contains_key(&peer_addr));
+
+        let err = io::Error::from_raw_os_error(libc::EHOSTUNREACH);
+        handle_send_error(&assoc_map, &stat, policy, peer_addr, target_addr, 100, &err).await;
+        assert!(!assoc_map.lock().await.contains_key(&peer_addr));
+
+        assert_eq!(stat.count(UdpSendErrorKind::MessageTooLarge), 1);
+        assert_eq!(stat.count(UdpSendErrorKind::Unreachable), 1);
+    }
+}
@@ -1,8 +1,24 @@
 //! Network Utilities
 
-use std::io;
+use std::{
+    fmt::Display,
+    future::Future,
+    io::{self, ErrorKind},
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
 
-use tokio::io::{AsyncRead, AsyncReadExt};
+use futures::future::{self, Either};
+use log::{debug, trace};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf},
+    time::{self, Instant, Sleep},
+};
+
+/// Time to wait for draining and shutting down a connection closed by `max_connection_lifetime`
+const LIFETIME_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// Consumes all data from `reader` and throws away until EOF
 pub async fn ignore_until_end<R>(reader: &mut R) -> io::Result<()>
@@ -20,3 +36,152 @@ where
 
     Ok(())
 }
+
+/// Check if `err` is caused by the peer resetting a connection that is being closed
+///
+/// When both ends close a connection (nearly) simultaneously, the one whose `FIN` arrives after the other end has
+/// already released the socket gets a `RST`, which surfaces as one of these errors on `accept`, `read`, `write` or
+/// `setsockopt`. They are part of a normal teardown and shouldn't be reported as failures.
+pub fn is_benign_close_error(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe | ErrorKind::NotConnected
+    )
+}
+
+/// Deadline of a connection started now, which has a lifetime of `lifetime`
+pub fn lifetime_deadline(lifetime: Option<Duration>) -> Option<Instant> {
+    lifetime.map(|d| Instant::now() + d)
+}
+
+/// Reader of a connection with `max_connection_lifetime`, which reaches EOF at `deadline`
+///
+/// Copies reading from it end like the peer has closed, after writing out the data they have already read.
+pub struct LifetimeReader<R> {
+    reader: R,
+    deadline: Option<Pin<Box<Sleep>>>,
+}
+
+impl<R> LifetimeReader<R> {
+    /// Wrap `reader`, which never reaches the deadline if `deadline` is `None`
+    pub fn new(reader: R, deadline: Option<Instant>) -> LifetimeReader<R> {
+        LifetimeReader {
+            reader,
+            deadline: deadline.map(|d| Box::pin(time::sleep_until(d))),
+        }
+    }
+}
+
+impl<R> AsyncRead for LifetimeReader<R>
+where
+    R: AsyncRead + Unpin,
+{
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        if let Some(ref mut deadline) = self.deadline {
+            if deadline.as_mut().poll(cx).is_ready() {
+                // Nothing filled, which is EOF
+                return Poll::Ready(Ok(()));
+            }
+        }
+        Pin::new(&mut self.reader).poll_read(cx, buf)
+    }
+}
+
+/// Reason of closing a TCP tunnel relayed by `relay_tcp_tunnel`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TcpTunnelCloseReason {
+    /// Either direction is closed by EOF or error
+    Closed,
+    /// The tunnel has exceeded its lifetime, `max_connection_lifetime`
+    LifetimeExceeded,
+}
+
+/// Relay both directions of a TCP tunnel, until either of them is closed
+///
+/// Readers of `l2r` and `r2l` should be `LifetimeReader`s with `deadline`. If the tunnel is closed because it has
+/// exceeded its lifetime, the other direction is drained, so data it has already read are written out.
+///
+/// Returns why the tunnel is closed. Writers should be shut down by `shutdown_on_lifetime_exceeded` if it is
+/// `TcpTunnelCloseReason::LifetimeExceeded`.
+pub async fn relay_tcp_tunnel<L, R, A>(
+    peer_addr: SocketAddr,
+    target_addr: &A,
+    deadline: Option<Instant>,
+    l2r: L,
+    r2l: R,
+) -> TcpTunnelCloseReason
+where
+    L: Future<Output = io::Result<u64>>,
+    R: Future<Output = io::Result<u64>>,
+    A: Display + ?Sized,
+{
+    tokio::pin!(l2r);
+    tokio::pin!(r2l);
+
+    let remaining = match future::select(l2r, r2l).await {
+        Either::Left((Ok(..), r2l)) => {
+            trace!("tcp tunnel {} -> {} closed", peer_addr, target_addr);
+            Either::Left(r2l)
+        }
+        Either::Left((Err(err), r2l)) => {
+            trace!("tcp tunnel {} -> {} closed with error: {}", peer_addr, target_addr, err);
+            Either::Left(r2l)
+        }
+        Either::Right((Ok(..), l2r)) => {
+            trace!("tcp tunnel {} <- {} closed", peer_addr, target_addr);
+            Either::Right(l2r)
+        }
+        Either::Right((Err(err), l2r)) => {
+            trace!("tcp tunnel {} <- {} closed with error: {}", peer_addr, target_addr, err);
+            Either::Right(l2r)
+        }
+    };
+
+    let lifetime_exceeded = match deadline {
+        Some(deadline) => Instant::now() >= deadline,
+        None => false,
+    };
+
+    if !lifetime_exceeded {
+        return TcpTunnelCloseReason::Closed;
+    }
+
+    debug!("tcp tunnel {} <-> {} closed, lifetime exceeded", peer_addr, target_addr);
+
+    // Reader of the other direction has reached EOF too, wait for its copy to write out the remaining data.
+    // The peer may not be reading, so it couldn't be waited forever.
+    let _ = time::timeout(LIFETIME_SHUTDOWN_TIMEOUT, remaining).await;
+
+    TcpTunnelCloseReason::LifetimeExceeded
+}
+
+/// Half-close both directions of a connection which has exceeded its lifetime (sending `FIN`)
+///
+/// The connection is closed after the streams are dropped. Writers that can't be shut down in time are abandoned.
+pub async fn shutdown_on_lifetime_exceeded<W1, W2>(w1: &mut W1, w2: &mut W2)
+where
+    W1: AsyncWrite + Unpin + ?Sized,
+    W2: AsyncWrite + Unpin + ?Sized,
+{
+    let shutdown = async { tokio::join!(w1.shutdown(), w2.shutdown()) };
+    let _ = time::timeout(LIFETIME_SHUTDOWN_TIMEOUT, shutdown).await;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn relay_tcp_tunnel_close_reason() {
+        let peer_addr = SocketAddr::from(([127, 0, 0, 1], 8400));
+        let closed = || async { Ok(0u64) };
+
+        let reason = relay_tcp_tunnel(peer_addr, "target", None, closed(), future::pending()).await;
+        assert_eq!(reason, TcpTunnelCloseReason::Closed);
+
+        // Readers return EOF after the deadline
+        let deadline = Some(Instant::now());
+        let reason = relay_tcp_tunnel(peer_addr, "target", deadline, closed(), closed()).await;
+        assert_eq!(reason, TcpTunnelCloseReason::LifetimeExceeded);
+    }
+}
@@ -0,0 +1,205 @@
+//! Registry of running plugin (SIP003) processes
+//!
+//! Limits how many plugin processes could run concurrently in one service, and shares plugin processes between
+//! servers with the same address and exactly the same plugin configuration.
+//!
+//! Plugins of `ssserver` listen on servers' inbound addresses, which are unique, so only local servers' plugins
+//! could be shared.
+
+use std::{
+    collections::HashMap,
+    io::{self, ErrorKind},
+    net::SocketAddr,
+    process::ExitStatus,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+        Weak,
+    },
+    time::Duration,
+};
+
+use log::{debug, Level};
+use shadowsocks::{
+    config::ServerAddr,
+    plugin::{Plugin, PluginConfig, PluginMode},
+};
+use spin::Mutex as SpinMutex;
+
+/// Plugin processes with equal keys are interchangeable
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct PluginKey {
+    plugin: String,
+    plugin_opts: Option<String>,
+    plugin_args: Vec<String>,
+    remote_addr: String,
+}
+
+impl PluginKey {
+    fn new(c: &PluginConfig, remote_addr: &ServerAddr) -> PluginKey {
+        PluginKey {
+            plugin: c.plugin.clone(),
+            plugin_opts: c.plugin_opts.clone(),
+            plugin_args: c.plugin_args.clone(),
+            remote_addr: remote_addr.to_string(),
+        }
+    }
+}
+
+/// Counts a plugin process in the registry until it is dropped
+struct PluginSlot {
+    running: Arc<AtomicUsize>,
+    // Shared plugin process is valid as long as this is alive
+    alive: Arc<()>,
+}
+
+impl Drop for PluginSlot {
+    fn drop(&mut self) {
+        self.running.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// A plugin process started by `PluginRegistry`
+///
+/// It is counted in the registry until it is dropped, which terminates the process.
+pub struct RegisteredPlugin {
+    plugin: Plugin,
+    slot: PluginSlot,
+}
+
+impl RegisteredPlugin {
+    /// Join until plugin exits
+    pub async fn join(self) -> io::Result<ExitStatus> {
+        let RegisteredPlugin { plugin, slot } = self;
+        let result = plugin.join().await;
+        drop(slot);
+        result
+    }
+
+    /// Check if plugin have been started
+    pub async fn wait_started(&self, timeout: Duration) -> bool {
+        self.plugin.wait_started(timeout).await
+    }
+
+    /// Get listen address of plugin
+    pub fn local_addr(&self) -> SocketAddr {
+        self.plugin.local_addr()
+    }
+}
+
+/// Registry of running plugin processes
+pub struct PluginRegistry {
+    max_processes: Option<usize>,
+    log_level: Option<Level>,
+    running: Arc<AtomicUsize>,
+    shared: SpinMutex<HashMap<PluginKey, (SocketAddr, Weak<()>)>>,
+}
+
+impl Default for PluginRegistry {
+    fn default() -> PluginRegistry {
+        PluginRegistry::new(None)
+    }
+}
+
+impl PluginRegistry {
+    /// Create a registry, which allows at most `max_processes` plugin processes running concurrently
+    pub fn new(max_processes: Option<usize>) -> PluginRegistry {
+        PluginRegistry {
+            max_processes,
+            log_level: None,
+            running: Arc::new(AtomicUsize::new(0)),
+            shared: SpinMutex::new(HashMap::new()),
+        }
+    }
+
+    /// Set maximum number of plugin processes running concurrently
+    pub fn set_max_processes(&mut self, max_processes: usize) {
+        self.max_processes = Some(max_processes);
+    }
+
+    /// Log lines written to stderr by plugin processes with `level`, instead of letting them write to stderr directly
+    pub fn set_log_level(&mut self, level: Level) {
+        self.log_level = Some(level);
+    }
+
+    /// Number of plugin processes running
+    pub fn running_processes(&self) -> usize {
+        self.running.load(Ordering::Acquire)
+    }
+
+    /// Start a plugin process, fails if `max_processes` is exceeded
+    pub fn start(&self, c: &PluginConfig, remote_addr: &ServerAddr, mode: PluginMode) -> io::Result<RegisteredPlugin> {
+        let max_processes = self.max_processes;
+        let reserved = self
+            .running
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| match max_processes {
+                Some(m) if n >= m => None,
+                _ => Some(n + 1),
+            });
+
+        if reserved.is_err() {
+            let err = io::Error::new(
+                ErrorKind::Other,
+                format!(
+                    "couldn't start plugin \"{}\" for server {}, exceeded max_plugin_processes {}",
+                    c.plugin,
+                    remote_addr,
+                    max_processes.unwrap_or(0)
+                ),
+            );
+            return Err(err);
+        }
+
+        let result = match self.log_level {
+            Some(level) => Plugin::start_with_log_level(c, remote_addr, mode, level),
+            None => Plugin::start(c, remote_addr, mode),
+        };
+
+        match result {
+            Ok(plugin) => Ok(RegisteredPlugin {
+                plugin,
+                slot: PluginSlot {
+                    running: self.running.clone(),
+                    alive: Arc::new(()),
+                },
+            }),
+            Err(err) => {
+                self.running.fetch_sub(1, Ordering::AcqRel);
+                Err(err)
+            }
+        }
+    }
+
+    /// Reuse a running plugin process with the same `remote_addr` and plugin configuration, or start a new one
+    ///
+    /// Returns plugin's listen address, and the new process if it was started. The shared process is only valid
+    /// as long as the `RegisteredPlugin` which started it is alive.
+    pub fn start_shared(
+        &self,
+        c: &PluginConfig,
+        remote_addr: &ServerAddr,
+        mode: PluginMode,
+    ) -> io::Result<(SocketAddr, Option<RegisteredPlugin>)> {
+        let key = PluginKey::new(c, remote_addr);
+
+        if let Some((local_addr, alive)) = self.shared.lock().get(&key) {
+            if alive.upgrade().is_some() {
+                debug!(
+                    "shared plugin \"{}\" on {} for server {}",
+                    c.plugin, local_addr, remote_addr
+                );
+                return Ok((*local_addr, None));
+            }
+        }
+
+        // The lock is not held while the process is spawning. Servers starting the same plugin concurrently
+        // may each get their own process, the last one started is shared with the servers after them.
+        let plugin = self.start(c, remote_addr, mode)?;
+        let local_addr = plugin.local_addr();
+        self.shared
+            .lock()
+            .insert(key, (local_addr, Arc::downgrade(&plugin.slot.alive)));
+
+        Ok((local_addr, Some(plugin)))
+    }
+}
@@ -1,6 +1,6 @@
 //! Shadowsocks Local Server Context
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use shadowsocks::{
     config::ServerType,
@@ -10,7 +10,11 @@ use shadowsocks::{
     relay::Address,
 };
 
-use crate::{acl::AccessControl, net::FlowStat};
+use crate::{
+    acl::AccessControl,
+    config::{UdpEvictionPolicy, UdpSendErrorPolicy},
+    net::{udp_send_error::UdpSendErrorStat, FlowStat},
+};
 
 /// Server Service Context
 pub struct ServiceContext {
@@ -22,6 +26,19 @@ pub struct ServiceContext {
 
     // Flow statistic report
     flow_stat: Arc<FlowStat>,
+
+    // UDP associations
+    udp_eviction_policy: UdpEvictionPolicy,
+
+    // UDP send errors
+    udp_send_error_policy: UdpSendErrorPolicy,
+    udp_send_error_stat: UdpSendErrorStat,
+
+    // Connect IPv4-mapped IPv6 targets with IPv4 addresses
+    unmap_ipv4: bool,
+
+    // TCP tunnels are closed after they have been relayed for this long
+    max_connection_lifetime: Option<Duration>,
 }
 
 impl ServiceContext {
@@ -32,6 +49,11 @@ impl ServiceContext {
             connect_opts: ConnectOpts::default(),
             acl: None,
             flow_stat: Arc::new(FlowStat::new()),
+            udp_eviction_policy: UdpEvictionPolicy::default(),
+            udp_send_error_policy: UdpSendErrorPolicy::default(),
+            udp_send_error_stat: UdpSendErrorStat::new(),
+            unmap_ipv4: true,
+            max_connection_lifetime: None,
         }
     }
 
@@ -75,22 +97,82 @@ impl ServiceContext {
         self.flow_stat.as_ref()
     }
 
+    /// Set policy for choosing the UDP association to evict when the association table is full
+    pub fn set_udp_eviction_policy(&mut self, policy: UdpEvictionPolicy) {
+        self.udp_eviction_policy = policy;
+    }
+
+    /// Get policy for choosing the UDP association to evict when the association table is full
+    pub fn udp_eviction_policy(&self) -> UdpEvictionPolicy {
+        self.udp_eviction_policy
+    }
+
+    /// Set policy for errors of sending UDP packets to targets
+    pub fn set_udp_send_error_policy(&mut self, policy: UdpSendErrorPolicy) {
+        self.udp_send_error_policy = policy;
+    }
+
+    /// Get policy for errors of sending UDP packets to targets
+    pub fn udp_send_error_policy(&self) -> UdpSendErrorPolicy {
+        self.udp_send_error_policy
+    }
+
+    /// Get statistic reference of UDP send errors
+    pub fn udp_send_error_stat_ref(&self) -> &UdpSendErrorStat {
+        &self.udp_send_error_stat
+    }
+
+    /// Set whether IPv4-mapped IPv6 targets should be connected with IPv4 addresses
+    pub fn set_unmap_ipv4(&mut self, unmap: bool) {
+        self.unmap_ipv4 = unmap;
+    }
+
+    /// Check whether IPv4-mapped IPv6 targets should be connected with IPv4 addresses
+    pub fn unmap_ipv4(&self) -> bool {
+        self.unmap_ipv4
+    }
+
+    /// Set the maximum lifetime of TCP tunnels, regardless of activity
+    pub fn set_max_connection_lifetime(&mut self, lifetime: Duration) {
+        self.max_connection_lifetime = Some(lifetime);
+    }
+
+    /// Get the maximum lifetime of TCP tunnels
+    pub fn max_connection_lifetime(&self) -> Option<Duration> {
+        self.max_connection_lifetime
+    }
+
     /// Set customized DNS resolver
     pub fn set_dns_resolver(&mut self, resolver: Arc<DnsResolver>) {
         let context = Arc::get_mut(&mut self.context).expect("cannot set DNS resolver on a shared context");
         context.set_dns_resolver(resolver)
     }
 
+    /// Set minimum average size of AEAD chunks received in one connection
+    pub fn set_min_avg_chunk_size(&mut self, size: usize) {
+        let context = Arc::get_mut(&mut self.context).expect("cannot set min_avg_chunk_size on a shared context");
+        context.set_min_avg_chunk_size(size)
+    }
+
     /// Get reference of DNS resolver
     pub fn dns_resolver(&self) -> &DnsResolver {
         self.context.dns_resolver()
     }
 
     /// Check if target should be bypassed
+    ///
+    /// IPv4-mapped IPv6 targets are checked with IPv4 rules if they are going to be connected with IPv4 addresses.
     pub async fn check_outbound_blocked(&self, addr: &Address) -> bool {
         match self.acl {
             None => false,
-            Some(ref acl) => acl.check_outbound_blocked(&self.context, addr).await,
+            Some(ref acl) => {
+                if self.unmap_ipv4 {
+                    acl.check_outbound_blocked(&self.context, &addr.clone().unmap_ipv4())
+                        .await
+                } else {
+                    acl.check_outbound_blocked(&self.context, addr).await
+                }
+            }
         }
     }
 }
@@ -0,0 +1,374 @@
+//! IPFIX flow record exporter
+//!
+//! Exports one flow record for each finished TCP tunnel to an IPFIX ([RFC 7011](https://tools.ietf.org/html/rfc7011))
+//! collector over UDP.
+//!
+//! ```plain
+//! Template 256
+//! +----------------------------------+-----+--------+
+//! | Information Element              | ID  | Length |
+//! +----------------------------------+-----+--------+
+//! | sourceIPv6Address                | 27  | 16     |
+//! | sourceTransportPort              | 7   | 2      |
+//! | destinationIPv6Address           | 28  | 16     |
+//! | destinationTransportPort         | 11  | 2      |
+//! | postNATDestinationIPv6Address    | 282 | 16     |
+//! | postNAPTDestinationTransportPort | 228 | 2      |
+//! | protocolIdentifier               | 4   | 1      |
+//! | initiatorOctets                  | 231 | 8      |
+//! | responderOctets                  | 232 | 8      |
+//! | initiatorPackets                 | 298 | 8      |
+//! | responderPackets                 | 299 | 8      |
+//! | flowStartMilliseconds            | 152 | 8      |
+//! | flowEndMilliseconds              | 153 | 8      |
+//! +----------------------------------+-----+--------+
+//! ```
+//!
+//! - Flows are translated by servers like NAPT. Source is the client, destination is the server's address that the
+//!   client connected to, which identifies the server used. Post-NAT destination is the target address that the
+//!   server connected to. IPv4 addresses are exported in IPv4-mapped IPv6 form.
+//! - Octets are counted on the client's side of the tunnel, which is the encrypted traffic.
+//! - Packets are TCP segments of the client's connection, read from `TCP_INFO`. They are 0 on platforms other than
+//!   Linux, or kernels older than 4.2.
+//! - Observation Domain ID is the listening port of the server which relayed the flow.
+//!
+//! Templates are sent with the first message and then periodically, because UDP collectors may lose them.
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::{BufMut, BytesMut};
+use log::{debug, error, trace};
+use tokio::{
+    net::UdpSocket,
+    sync::mpsc::{self, error::TrySendError},
+    time,
+};
+
+const IPFIX_VERSION: u16 = 10;
+const TEMPLATE_SET_ID: u16 = 2;
+const TEMPLATE_ID: u16 = 256;
+const PROTOCOL_TCP: u8 = 6;
+
+// (Information Element ID, Field Length)
+const TEMPLATE_FIELDS: [(u16, u16); 13] = [
+    (27, 16),
+    (7, 2),
+    (28, 16),
+    (11, 2),
+    (282, 16),
+    (228, 2),
+    (4, 1),
+    (231, 8),
+    (232, 8),
+    (298, 8),
+    (299, 8),
+    (152, 8),
+    (153, 8),
+];
+
+const MESSAGE_HEADER_LEN: usize = 16;
+const SET_HEADER_LEN: usize = 4;
+const TEMPLATE_SET_LEN: usize = SET_HEADER_LEN + 4 + TEMPLATE_FIELDS.len() * 4;
+const RECORD_LEN: usize = 16 + 2 + 16 + 2 + 16 + 2 + 1 + 8 + 8 + 8 + 8 + 8 + 8;
+
+// Keep messages smaller than the common path MTU to avoid IP fragmentation
+const MAX_MESSAGE_LEN: usize = 1400;
+const MAX_RECORDS_PER_MESSAGE: usize =
+    (MAX_MESSAGE_LEN - MESSAGE_HEADER_LEN - TEMPLATE_SET_LEN - SET_HEADER_LEN) / RECORD_LEN;
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+const TEMPLATE_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+const EXPORT_QUEUE_SIZE: usize = 4096;
+
+/// Flow record of a finished TCP tunnel
+#[derive(Debug, Clone)]
+pub struct FlowRecord {
+    pub source: SocketAddr,
+    pub server: SocketAddr,
+    pub target: SocketAddr,
+    pub initiator_octets: u64,
+    pub responder_octets: u64,
+    pub initiator_packets: u64,
+    pub responder_packets: u64,
+    pub start_time: SystemTime,
+    pub end_time: SystemTime,
+}
+
+/// Handle for sending flow records to the exporting task
+///
+/// The exporting task exits after all handles are dropped.
+#[derive(Clone)]
+pub struct FlowExporter {
+    tx: mpsc::Sender<FlowRecord>,
+}
+
+impl FlowExporter {
+    /// Start exporting flow records to `collector`
+    pub async fn start(collector: SocketAddr, observation_domain_id: u32) -> io::Result<FlowExporter> {
+        let bind_addr = match collector {
+            SocketAddr::V4(..) => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+            SocketAddr::V6(..) => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0),
+        };
+
+        let socket = UdpSocket::bind(bind_addr).await?;
+        socket.connect(collector).await?;
+
+        debug!(
+            "IPFIX exporter started, collector {}, observation domain {}",
+            collector, observation_domain_id
+        );
+
+        let (tx, rx) = mpsc::channel(EXPORT_QUEUE_SIZE);
+        tokio::spawn(export_task(socket, observation_domain_id, rx));
+
+        Ok(FlowExporter { tx })
+    }
+
+    /// Queue a flow record for exporting
+    ///
+    /// Records are dropped if the exporting task couldn't keep up.
+    pub fn export(&self, record: FlowRecord) {
+        match self.tx.try_send(record) {
+            Ok(..) => {}
+            Err(TrySendError::Full(record)) => {
+                debug!("IPFIX export queue is full, dropped flow record {:?}", record);
+            }
+            Err(TrySendError::Closed(..)) => {
+                error!("IPFIX exporter is already closed");
+            }
+        }
+    }
+}
+
+async fn export_task(socket: UdpSocket, observation_domain_id: u32, mut rx: mpsc::Receiver<FlowRecord>) {
+    let mut encoder = MessageEncoder::new(observation_domain_id);
+    let mut records = Vec::with_capacity(MAX_RECORDS_PER_MESSAGE);
+    let mut flush_interval = time::interval(FLUSH_INTERVAL);
+
+    loop {
+        tokio::select! {
+            record = rx.recv() => match record {
+                Some(record) => {
+                    records.push(record);
+                    if records.len() < MAX_RECORDS_PER_MESSAGE {
+                        continue;
+                    }
+                }
+                None => {
+                    if !records.is_empty() {
+                        send_message(&socket, &mut encoder, &mut records).await;
+                    }
+                    break;
+                }
+            },
+            _ = flush_interval.tick() => {
+                if records.is_empty() {
+                    continue;
+                }
+            }
+        }
+
+        send_message(&socket, &mut encoder, &mut records).await;
+    }
+
+    debug!("IPFIX exporter of observation domain {} exited", observation_domain_id);
+}
+
+async fn send_message(socket: &UdpSocket, encoder: &mut MessageEncoder, records: &mut Vec<FlowRecord>) {
+    let mut buf = BytesMut::with_capacity(MAX_MESSAGE_LEN);
+    encoder.encode(records, &mut buf);
+
+    match socket.send(&buf).await {
+        Ok(..) => {
+            trace!(
+                "IPFIX exported {} flow records, message {} bytes",
+                records.len(),
+                buf.len()
+            );
+        }
+        Err(err) => {
+            error!("IPFIX export {} flow records failed, error: {}", records.len(), err);
+        }
+    }
+
+    records.clear();
+}
+
+struct MessageEncoder {
+    observation_domain_id: u32,
+    sequence_number: u32,
+    last_template_time: Option<Instant>,
+}
+
+impl MessageEncoder {
+    fn new(observation_domain_id: u32) -> MessageEncoder {
+        MessageEncoder {
+            observation_domain_id,
+            sequence_number: 0,
+            last_template_time: None,
+        }
+    }
+
+    fn encode(&mut self, records: &[FlowRecord], buf: &mut BytesMut) {
+        let now = Instant::now();
+        let send_template = match self.last_template_time {
+            None => true,
+            Some(t) => now - t >= TEMPLATE_REFRESH_INTERVAL,
+        };
+
+        let mut message_len = MESSAGE_HEADER_LEN + SET_HEADER_LEN + records.len() * RECORD_LEN;
+        if send_template {
+            message_len += TEMPLATE_SET_LEN;
+            self.last_template_time = Some(now);
+        }
+
+        // Message Header
+        buf.put_u16(IPFIX_VERSION);
+        buf.put_u16(message_len as u16);
+        buf.put_u32(unix_time(SystemTime::now()).as_secs() as u32);
+        buf.put_u32(self.sequence_number);
+        buf.put_u32(self.observation_domain_id);
+
+        // Template Set
+        if send_template {
+            buf.put_u16(TEMPLATE_SET_ID);
+            buf.put_u16(TEMPLATE_SET_LEN as u16);
+            buf.put_u16(TEMPLATE_ID);
+            buf.put_u16(TEMPLATE_FIELDS.len() as u16);
+            for &(id, len) in TEMPLATE_FIELDS.iter() {
+                buf.put_u16(id);
+                buf.put_u16(len);
+            }
+        }
+
+        // Data Set
+        buf.put_u16(TEMPLATE_ID);
+        buf.put_u16((SET_HEADER_LEN + records.len() * RECORD_LEN) as u16);
+        for record in records {
+            put_ipv6_addr(buf, &record.source.ip());
+            buf.put_u16(record.source.port());
+            put_ipv6_addr(buf, &record.server.ip());
+            buf.put_u16(record.server.port());
+            put_ipv6_addr(buf, &record.target.ip());
+            buf.put_u16(record.target.port());
+            buf.put_u8(PROTOCOL_TCP);
+            buf.put_u64(record.initiator_octets);
+            buf.put_u64(record.responder_octets);
+            buf.put_u64(record.initiator_packets);
+            buf.put_u64(record.responder_packets);
+            buf.put_u64(unix_time(record.start_time).as_millis() as u64);
+            buf.put_u64(unix_time(record.end_time).as_millis() as u64);
+        }
+
+        // Sequence Number is the total number of data records sent, modulo 2^32
+        self.sequence_number = self.sequence_number.wrapping_add(records.len() as u32);
+    }
+}
+
+fn put_ipv6_addr(buf: &mut BytesMut, addr: &IpAddr) {
+    let v6 = match *addr {
+        IpAddr::V4(ref v4) => v4.to_ipv6_mapped(),
+        IpAddr::V6(ref v6) => *v6,
+    };
+    buf.put_slice(&v6.octets());
+}
+
+fn unix_time(t: SystemTime) -> Duration {
+    t.duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn flow_record() -> FlowRecord {
+        FlowRecord {
+            source: SocketAddr::new(Ipv4Addr::new(192, 0, 2, 1).into(), 50000),
+            server: SocketAddr::new(Ipv4Addr::new(198, 51, 100, 1).into(), 8388),
+            target: SocketAddr::new(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).into(), 443),
+            initiator_octets: 1000,
+            responder_octets: 20000,
+            initiator_packets: 10,
+            responder_packets: 20,
+            start_time: UNIX_EPOCH + Duration::from_millis(1_600_000_000_000),
+            end_time: UNIX_EPOCH + Duration::from_millis(1_600_000_001_500),
+        }
+    }
+
+    #[test]
+    fn encode_template_and_record() {
+        let mut encoder = MessageEncoder::new(8388);
+        let mut buf = BytesMut::new();
+        encoder.encode(&[flow_record()], &mut buf);
+
+        let message_len = MESSAGE_HEADER_LEN + TEMPLATE_SET_LEN + SET_HEADER_LEN + RECORD_LEN;
+        assert_eq!(buf.len(), message_len);
+
+        // Message Header, Export Time is skipped
+        assert_eq!(&buf[0..2], &IPFIX_VERSION.to_be_bytes());
+        assert_eq!(&buf[2..4], &(message_len as u16).to_be_bytes());
+        assert_eq!(&buf[8..12], &0u32.to_be_bytes());
+        assert_eq!(&buf[12..16], &8388u32.to_be_bytes());
+
+        // Template Set
+        let template = &buf[MESSAGE_HEADER_LEN..MESSAGE_HEADER_LEN + TEMPLATE_SET_LEN];
+        let expected_template: &[u8] = &[
+            0, 2, 0, 60, // Set ID 2, length
+            1, 0, 0, 13, // Template ID 256, field count
+            0, 27, 0, 16, // sourceIPv6Address
+            0, 7, 0, 2, // sourceTransportPort
+            0, 28, 0, 16, // destinationIPv6Address
+            0, 11, 0, 2, // destinationTransportPort
+            1, 26, 0, 16, // postNATDestinationIPv6Address
+            0, 228, 0, 2, // postNAPTDestinationTransportPort
+            0, 4, 0, 1, // protocolIdentifier
+            0, 231, 0, 8, // initiatorOctets
+            0, 232, 0, 8, // responderOctets
+            1, 42, 0, 8, // initiatorPackets
+            1, 43, 0, 8, // responderPackets
+            0, 152, 0, 8, // flowStartMilliseconds
+            0, 153, 0, 8, // flowEndMilliseconds
+        ];
+        assert_eq!(template, expected_template);
+
+        // Data Set
+        let data = &buf[MESSAGE_HEADER_LEN + TEMPLATE_SET_LEN..];
+        let mut expected_data = vec![1, 0, 0, (SET_HEADER_LEN + RECORD_LEN) as u8];
+        expected_data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 192, 0, 2, 1]);
+        expected_data.extend_from_slice(&50000u16.to_be_bytes());
+        expected_data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 198, 51, 100, 1]);
+        expected_data.extend_from_slice(&8388u16.to_be_bytes());
+        expected_data.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        expected_data.extend_from_slice(&443u16.to_be_bytes());
+        expected_data.push(PROTOCOL_TCP);
+        expected_data.extend_from_slice(&1000u64.to_be_bytes());
+        expected_data.extend_from_slice(&20000u64.to_be_bytes());
+        expected_data.extend_from_slice(&10u64.to_be_bytes());
+        expected_data.extend_from_slice(&20u64.to_be_bytes());
+        expected_data.extend_from_slice(&1_600_000_000_000u64.to_be_bytes());
+        expected_data.extend_from_slice(&1_600_000_001_500u64.to_be_bytes());
+        assert_eq!(data, &expected_data[..]);
+    }
+
+    #[test]
+    fn encode_sequence_without_template() {
+        let mut encoder = MessageEncoder::new(8388);
+
+        let mut buf = BytesMut::new();
+        encoder.encode(&[flow_record(), flow_record()], &mut buf);
+
+        // Template has been sent, Sequence Number counts records sent before this message
+        let mut buf = BytesMut::new();
+        encoder.encode(&[flow_record()], &mut buf);
+        assert_eq!(buf.len(), MESSAGE_HEADER_LEN + SET_HEADER_LEN + RECORD_LEN);
+        assert_eq!(&buf[8..12], &2u32.to_be_bytes());
+        assert_eq!(
+            &buf[MESSAGE_HEADER_LEN..MESSAGE_HEADER_LEN + 2],
+            &TEMPLATE_ID.to_be_bytes()
+        );
+    }
+}
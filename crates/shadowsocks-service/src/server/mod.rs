@@ -14,12 +14,19 @@ use shadowsocks::{
     net::{AcceptOpts, ConnectOpts},
 };
 
-use crate::config::{Config, ConfigType};
+use crate::{
+    config::{Config, ConfigType},
+    plugin::PluginRegistry,
+};
 
+pub(crate) use self::ramp::ConnectionRamp;
 pub use self::server::Server;
 
 pub mod context;
+mod ipfix;
+mod ramp;
 pub mod server;
+mod source_verify;
 mod tcprelay;
 mod udprelay;
 
@@ -47,6 +54,24 @@ pub async fn run(config: Config) -> io::Result<()> {
         }
     }
 
+    #[cfg(target_os = "linux")]
+    {
+        use crate::sys::{has_net_bind_service_capability, unprivileged_port_start};
+
+        let port_start = unprivileged_port_start();
+        if let Some(svr_cfg) = config.server.iter().find(|s| s.addr().port() < port_start) {
+            match has_net_bind_service_capability() {
+                Ok(true) => trace!("CAP_NET_BIND_SERVICE is present for binding privileged ports"),
+                Ok(false) => warn!(
+                    "server {} listens on a privileged port but CAP_NET_BIND_SERVICE is missing, \
+                     grant it with `setcap cap_net_bind_service=+ep /path/to/ssserver`",
+                    svr_cfg.addr()
+                ),
+                Err(err) => warn!("failed to check CAP_NET_BIND_SERVICE, error: {}", err),
+            }
+        }
+    }
+
     let mut servers = Vec::new();
 
     let mut connect_opts = ConnectOpts {
@@ -56,6 +81,9 @@ pub async fn run(config: Config) -> io::Result<()> {
         #[cfg(target_os = "android")]
         vpn_protect_path: config.outbound_vpn_protect_path,
 
+        #[cfg(unix)]
+        dscp: config.outbound_dscp,
+
         bind_local_addr: match config.local_addr {
             None => None,
             Some(ServerAddr::SocketAddr(sa)) => Some(sa.ip()),
@@ -75,11 +103,13 @@ pub async fn run(config: Config) -> io::Result<()> {
 
     connect_opts.tcp.send_buffer_size = config.outbound_send_buffer_size;
     connect_opts.tcp.recv_buffer_size = config.outbound_recv_buffer_size;
+    connect_opts.tcp.reuse_addr = config.outbound_reuse_addr;
     connect_opts.tcp.nodelay = config.no_delay;
 
     let mut accept_opts = AcceptOpts::default();
     accept_opts.tcp.send_buffer_size = config.inbound_send_buffer_size;
     accept_opts.tcp.recv_buffer_size = config.inbound_recv_buffer_size;
+    accept_opts.backlog = config.listen_backlog;
     accept_opts.tcp.nodelay = config.no_delay;
 
     #[cfg(feature = "trust-dns")]
@@ -104,6 +134,13 @@ pub async fn run(config: Config) -> io::Result<()> {
     };
 
     let acl = config.acl.map(Arc::new);
+    // Shared by all servers, so clients' connections are counted across ports
+    let ramp = config.per_ip_ramp.map(|r| Arc::new(ConnectionRamp::new(r)));
+    let mut plugin_registry = PluginRegistry::new(config.max_plugin_processes);
+    if let Some(level) = config.plugin_log_level {
+        plugin_registry.set_log_level(level);
+    }
+    let plugin_registry = Arc::new(plugin_registry);
 
     for svr_cfg in config.server {
         let mut server = Server::new(svr_cfg);
@@ -122,10 +159,27 @@ pub async fn run(config: Config) -> io::Result<()> {
         if let Some(d) = config.udp_timeout {
             server.set_udp_expiry_duration(d);
         }
+        server.set_udp_eviction_policy(config.udp_eviction_policy);
+        server.set_udp_send_error_policy(config.udp_send_error_policy);
+        server.set_unmap_ipv4(config.unmap_ipv4);
+        if let Some(d) = config.max_connection_lifetime {
+            server.set_max_connection_lifetime(d);
+        }
+        server.set_udp_source_verify(config.udp_source_verify);
+        if let Some(s) = config.min_avg_chunk_size {
+            server.set_min_avg_chunk_size(s);
+        }
         server.set_mode(config.mode);
         if let Some(ref m) = config.manager {
             server.set_manager_addr(m.addr.clone());
         }
+        if let Some(collector) = config.netflow_collector {
+            server.set_netflow_collector(collector);
+        }
+        if let Some(ref ramp) = ramp {
+            server.set_connection_ramp(ramp.clone());
+        }
+        server.set_plugin_registry(plugin_registry.clone());
 
         if let Some(ref acl) = acl {
             server.set_acl(acl.clone());
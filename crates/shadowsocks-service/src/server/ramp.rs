@@ -0,0 +1,187 @@
+//! Connections ramp for each client IP
+//!
+//! Accepted connections of each client IP are counted in a sliding window, which decays exponentially with the time
+//! constant `window`. A client IP could have `burst` recent connections started immediately. Connections beyond that
+//! are delayed before being served, the `n`-th one beyond `burst` waits for `n * step_delay`, but never longer than
+//! `max_delay`:
+//!
+//! ```plain
+//! delay
+//!   ^
+//!   |                  +------------ max_delay
+//!   |             +----+
+//!   |        +----+
+//!   |   +----+ step_delay
+//!   +---+------------------------> recent connections
+//!     burst
+//! ```
+//!
+//! Connections are never rejected. Clients sharing one IP behind NAT are slowed down when they are bursting together,
+//! but not starved, and recover after they have slowed down for a few `window`s. Long-lived connections don't count
+//! once they have been accepted, only the rate of new connections does.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use spin::Mutex as SpinMutex;
+
+use crate::config::RampConfig;
+
+/// Clients whose counts have decayed below this are forgotten
+const RAMP_FORGET_THRESHOLD: f64 = 0.01;
+
+/// Decayed count of recent connections of a client IP
+struct RecentAccepts {
+    count: f64,
+    updated: Instant,
+}
+
+impl RecentAccepts {
+    fn decay(&mut self, now: Instant, window: Duration) {
+        let elapsed = now.saturating_duration_since(self.updated);
+        self.count *= (-elapsed.as_secs_f64() / window.as_secs_f64()).exp();
+        self.updated = now;
+    }
+}
+
+struct RampState {
+    clients: HashMap<IpAddr, RecentAccepts>,
+    last_cleanup: Instant,
+}
+
+/// Recent connections of each client IP
+pub struct ConnectionRamp {
+    config: RampConfig,
+    state: SpinMutex<RampState>,
+}
+
+impl ConnectionRamp {
+    pub fn new(config: RampConfig) -> ConnectionRamp {
+        ConnectionRamp {
+            config,
+            state: SpinMutex::new(RampState {
+                clients: HashMap::new(),
+                last_cleanup: Instant::now(),
+            }),
+        }
+    }
+
+    /// Count a new connection from `ip`, returns how long it should wait before being served
+    pub fn enter(&self, ip: IpAddr) -> Duration {
+        self.enter_at(ip, Instant::now())
+    }
+
+    fn enter_at(&self, ip: IpAddr, now: Instant) -> Duration {
+        let window = self.config.window;
+
+        let recent = {
+            let mut state = self.state.lock();
+
+            // Forget clients that have been quiet, at most once per window
+            if now.saturating_duration_since(state.last_cleanup) >= window {
+                state.clients.retain(|_, c| {
+                    c.decay(now, window);
+                    c.count >= RAMP_FORGET_THRESHOLD
+                });
+                state.last_cleanup = now;
+            }
+
+            let client = state.clients.entry(ip).or_insert(RecentAccepts {
+                count: 0.0,
+                updated: now,
+            });
+            client.decay(now, window);
+            client.count += 1.0;
+            client.count
+        };
+
+        self.delay(recent)
+    }
+
+    fn delay(&self, recent: f64) -> Duration {
+        let recent = recent.ceil() as usize;
+        if recent <= self.config.burst {
+            return Duration::from_secs(0);
+        }
+
+        let steps = (recent - self.config.burst) as u32;
+        match self.config.step_delay.checked_mul(steps) {
+            Some(d) if d < self.config.max_delay => d,
+            _ => self.config.max_delay,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ramp() -> ConnectionRamp {
+        ConnectionRamp::new(RampConfig {
+            burst: 2,
+            step_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(250),
+            window: Duration::from_secs(10),
+        })
+    }
+
+    #[test]
+    fn ramp_delay_curve() {
+        let ramp = ramp();
+        let ip = IpAddr::from([127, 0, 0, 1]);
+        let now = Instant::now();
+
+        let delays = (0..5).map(|_| ramp.enter_at(ip, now)).collect::<Vec<_>>();
+        assert_eq!(
+            delays,
+            vec![
+                Duration::from_millis(0),
+                Duration::from_millis(0),
+                Duration::from_millis(100),
+                Duration::from_millis(200),
+                Duration::from_millis(250),
+            ]
+        );
+
+        // Other clients are not affected
+        assert_eq!(
+            ramp.enter_at(IpAddr::from([127, 0, 0, 2]), now),
+            Duration::from_millis(0)
+        );
+    }
+
+    #[test]
+    fn ramp_recover_after_window() {
+        let ramp = ramp();
+        let ip = IpAddr::from([127, 0, 0, 1]);
+        let now = Instant::now();
+
+        for _ in 0..10 {
+            ramp.enter_at(ip, now);
+        }
+        assert_eq!(ramp.enter_at(ip, now), Duration::from_millis(250));
+
+        // Still ramping within the window
+        assert!(ramp.enter_at(ip, now + Duration::from_secs(5)) > Duration::from_millis(0));
+
+        // Recent connections have decayed after a few windows
+        assert_eq!(
+            ramp.enter_at(ip, now + Duration::from_secs(40)),
+            Duration::from_millis(0)
+        );
+    }
+
+    #[test]
+    fn ramp_forget_quiet_clients() {
+        let ramp = ramp();
+        let now = Instant::now();
+
+        ramp.enter_at(IpAddr::from([127, 0, 0, 1]), now);
+        ramp.enter_at(IpAddr::from([127, 0, 0, 2]), now + Duration::from_secs(100));
+
+        assert_eq!(ramp.state.lock().clients.len(), 1);
+    }
+}
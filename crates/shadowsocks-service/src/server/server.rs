@@ -3,6 +3,7 @@
 use std::{
     collections::HashMap,
     io::{self, ErrorKind},
+    net::SocketAddr,
     sync::Arc,
     time::Duration,
 };
@@ -13,14 +14,26 @@ use shadowsocks::{
     config::{ManagerAddr, ServerConfig},
     dns_resolver::DnsResolver,
     net::{AcceptOpts, ConnectOpts},
-    plugin::{Plugin, PluginMode},
+    plugin::PluginMode,
     ManagerClient,
 };
 use tokio::time;
 
-use crate::{acl::AccessControl, config::Mode, net::FlowStat};
+use crate::{
+    acl::AccessControl,
+    config::{Mode, UdpEvictionPolicy, UdpSendErrorPolicy},
+    net::FlowStat,
+    plugin::PluginRegistry,
+};
 
-use super::{context::ServiceContext, tcprelay::TcpServer, udprelay::UdpServer};
+use super::{
+    context::ServiceContext,
+    ipfix::FlowExporter,
+    ramp::ConnectionRamp,
+    source_verify::UdpSourceVerifier,
+    tcprelay::TcpServer,
+    udprelay::UdpServer,
+};
 
 /// Shadowsocks Server
 pub struct Server {
@@ -31,6 +44,10 @@ pub struct Server {
     udp_capacity: Option<usize>,
     manager_addr: Option<ManagerAddr>,
     accept_opts: AcceptOpts,
+    netflow_collector: Option<SocketAddr>,
+    connection_ramp: Option<Arc<ConnectionRamp>>,
+    udp_source_verify: bool,
+    plugin_registry: Arc<PluginRegistry>,
 }
 
 impl Server {
@@ -49,6 +66,10 @@ impl Server {
             udp_capacity: None,
             manager_addr: None,
             accept_opts: AcceptOpts::default(),
+            netflow_collector: None,
+            connection_ramp: None,
+            udp_source_verify: false,
+            plugin_registry: Arc::new(PluginRegistry::default()),
         }
     }
 
@@ -78,6 +99,40 @@ impl Server {
         self.udp_capacity = Some(c);
     }
 
+    /// Set policy for choosing the UDP association to evict when there are already `udp_capacity` associations
+    pub fn set_udp_eviction_policy(&mut self, policy: UdpEvictionPolicy) {
+        let context = Arc::get_mut(&mut self.context).expect("cannot set udp_eviction_policy on a shared context");
+        context.set_udp_eviction_policy(policy);
+    }
+
+    /// Set policy for errors of sending UDP packets to targets
+    pub fn set_udp_send_error_policy(&mut self, policy: UdpSendErrorPolicy) {
+        let context = Arc::get_mut(&mut self.context).expect("cannot set udp_send_error_policy on a shared context");
+        context.set_udp_send_error_policy(policy);
+    }
+
+    /// Set whether IPv4-mapped IPv6 targets should be connected with IPv4 addresses
+    pub fn set_unmap_ipv4(&mut self, unmap: bool) {
+        let context = Arc::get_mut(&mut self.context).expect("cannot set unmap_ipv4 on a shared context");
+        context.set_unmap_ipv4(unmap);
+    }
+
+    /// Set the maximum lifetime of TCP tunnels, regardless of activity
+    pub fn set_max_connection_lifetime(&mut self, lifetime: Duration) {
+        let context = Arc::get_mut(&mut self.context).expect("cannot set max_connection_lifetime on a shared context");
+        context.set_max_connection_lifetime(lifetime);
+    }
+
+    /// Set whether UDP packets are only accepted from sources validated by TCP connections
+    pub fn set_udp_source_verify(&mut self, verify: bool) {
+        self.udp_source_verify = verify;
+    }
+
+    /// Set registry for starting plugin processes, which could be shared by servers for limiting plugin processes
+    pub fn set_plugin_registry(&mut self, registry: Arc<PluginRegistry>) {
+        self.plugin_registry = registry;
+    }
+
     /// Set server's mode
     pub fn set_mode(&mut self, mode: Mode) {
         self.mode = mode;
@@ -88,6 +143,16 @@ impl Server {
         self.manager_addr = Some(manager_addr);
     }
 
+    /// Set IPFIX collector's address for exporting TCP flow records
+    pub fn set_netflow_collector(&mut self, collector: SocketAddr) {
+        self.netflow_collector = Some(collector);
+    }
+
+    /// Set connections ramp for each client IP
+    pub(crate) fn set_connection_ramp(&mut self, ramp: Arc<ConnectionRamp>) {
+        self.connection_ramp = Some(ramp);
+    }
+
     /// Get server's configuration
     pub fn config(&self) -> &ServerConfig {
         &self.svr_cfg
@@ -105,6 +170,12 @@ impl Server {
         context.set_acl(acl);
     }
 
+    /// Set minimum average size of AEAD chunks received in one connection
+    pub fn set_min_avg_chunk_size(&mut self, size: usize) {
+        let context = Arc::get_mut(&mut self.context).expect("cannot set min_avg_chunk_size on a shared context");
+        context.set_min_avg_chunk_size(size);
+    }
+
     /// Set `AcceptOpts` for accepting new connections
     pub fn set_accept_opts(&mut self, opts: AcceptOpts) {
         self.accept_opts = opts;
@@ -114,9 +185,32 @@ impl Server {
     pub async fn run(mut self) -> io::Result<()> {
         let mut vfut = Vec::new();
 
+        // Validated by TCP server, verified by UDP server
+        let source_verifier = if self.udp_source_verify {
+            // TCP connections come from the plugin, UDP clients couldn't be validated.
+            // Configuration files are refused by `check_integrity`, this catches servers added by managers.
+            if self.svr_cfg.plugin().is_some() {
+                let err = io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "udp_source_verify couldn't be enabled on server {} with plugin",
+                        self.svr_cfg.addr()
+                    ),
+                );
+                return Err(err);
+            }
+
+            let time_to_live = self.udp_expiry_duration.unwrap_or(crate::DEFAULT_UDP_EXPIRY_DURATION);
+            Some(Arc::new(UdpSourceVerifier::new(time_to_live)))
+        } else {
+            None
+        };
+
         if self.mode.enable_tcp() {
             if let Some(plugin_cfg) = self.svr_cfg.plugin() {
-                let plugin = Plugin::start(plugin_cfg, self.svr_cfg.addr(), PluginMode::Server)?;
+                let plugin = self
+                    .plugin_registry
+                    .start(plugin_cfg, self.svr_cfg.addr(), PluginMode::Server)?;
                 self.svr_cfg.set_plugin_addr(plugin.local_addr().into());
                 vfut.push(
                     async move {
@@ -135,12 +229,12 @@ impl Server {
                 );
             }
 
-            let tcp_fut = self.run_tcp_server().boxed();
+            let tcp_fut = self.run_tcp_server(source_verifier.clone()).boxed();
             vfut.push(tcp_fut);
         }
 
         if self.mode.enable_udp() {
-            let udp_fut = self.run_udp_server().boxed();
+            let udp_fut = self.run_udp_server(source_verifier).boxed();
             vfut.push(udp_fut);
         }
 
@@ -155,13 +249,30 @@ impl Server {
         Err(err)
     }
 
-    async fn run_tcp_server(&self) -> io::Result<()> {
-        let server = TcpServer::new(self.context.clone(), self.accept_opts.clone());
+    async fn run_tcp_server(&self, source_verifier: Option<Arc<UdpSourceVerifier>>) -> io::Result<()> {
+        let mut server = TcpServer::new(self.context.clone(), self.accept_opts.clone());
+
+        if let Some(collector) = self.netflow_collector {
+            let exporter = FlowExporter::start(collector, self.svr_cfg.addr().port() as u32).await?;
+            server.set_flow_exporter(exporter);
+        }
+
+        if let Some(ref ramp) = self.connection_ramp {
+            server.set_connection_ramp(ramp.clone());
+        }
+
+        if let Some(verifier) = source_verifier {
+            server.set_source_verifier(verifier);
+        }
+
         server.run(&self.svr_cfg).await
     }
 
-    async fn run_udp_server(&self) -> io::Result<()> {
-        let server = UdpServer::new(self.context.clone(), self.udp_expiry_duration, self.udp_capacity);
+    async fn run_udp_server(&self, source_verifier: Option<Arc<UdpSourceVerifier>>) -> io::Result<()> {
+        let mut server = UdpServer::new(self.context.clone(), self.udp_expiry_duration, self.udp_capacity);
+        if let Some(verifier) = source_verifier {
+            server.set_source_verifier(verifier);
+        }
         server.run(&self.svr_cfg).await
     }
 
@@ -0,0 +1,38 @@
+//! Verification of UDP packets' sources
+//!
+//! Shadowsocks UDP packets could be sent with a spoofed source address, by anyone who has captured (or owns) a valid
+//! packet, and responses of targets would be reflected to the spoofed address. Packets don't carry a handshake or a
+//! session, so a source is only validated by passing the handshake of a TCP connection to the same server, which
+//! couldn't be done without receiving the server's SYN-ACK.
+//!
+//! A validated source could create UDP associations until it hasn't made any new TCP connections for the
+//! association timeout. Established associations are kept until they expire, whether or not the source is still
+//! validated.
+
+use std::{net::IpAddr, time::Duration};
+
+use lru_time_cache::LruCache;
+use spin::Mutex as SpinMutex;
+
+/// Sources validated by TCP connections
+pub struct UdpSourceVerifier {
+    validated: SpinMutex<LruCache<IpAddr, ()>>,
+}
+
+impl UdpSourceVerifier {
+    pub fn new(time_to_live: Duration) -> UdpSourceVerifier {
+        UdpSourceVerifier {
+            validated: SpinMutex::new(LruCache::with_expiry_duration(time_to_live)),
+        }
+    }
+
+    /// Mark `ip` as validated, a TCP connection from it has passed the handshake
+    pub fn validate(&self, ip: IpAddr) {
+        self.validated.lock().insert(ip, ());
+    }
+
+    /// Check if `ip` could create UDP associations
+    pub fn is_validated(&self, ip: &IpAddr) -> bool {
+        self.validated.lock().peek(ip).is_some()
+    }
+}
@@ -4,10 +4,9 @@ use std::{
     io::{self, ErrorKind},
     net::SocketAddr,
     sync::Arc,
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 
-use futures::future::{self, Either};
 use log::{debug, error, info, trace, warn};
 use shadowsocks::{
     crypto::v1::CipherKind,
@@ -22,24 +21,77 @@ use shadowsocks::{
     ProxyListener,
     ServerConfig,
 };
-use tokio::{net::TcpStream as TokioTcpStream, time};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    time,
+};
 
-use crate::net::{utils::ignore_until_end, MonProxyStream};
+use crate::net::{
+    utils::{
+        ignore_until_end,
+        is_benign_close_error,
+        lifetime_deadline,
+        relay_tcp_tunnel,
+        shutdown_on_lifetime_exceeded,
+        LifetimeReader,
+        TcpTunnelCloseReason,
+    },
+    FlowStat,
+    MonProxyStream,
+};
 
-use super::context::ServiceContext;
+use super::{
+    context::ServiceContext,
+    ipfix::{FlowExporter, FlowRecord},
+    ramp::ConnectionRamp,
+    source_verify::UdpSourceVerifier,
+};
 
 pub struct TcpServer {
     context: Arc<ServiceContext>,
     accept_opts: AcceptOpts,
+    flow_exporter: Option<FlowExporter>,
+    connection_ramp: Option<Arc<ConnectionRamp>>,
+    source_verifier: Option<Arc<UdpSourceVerifier>>,
 }
 
 impl TcpServer {
     pub fn new(context: Arc<ServiceContext>, accept_opts: AcceptOpts) -> TcpServer {
-        TcpServer { context, accept_opts }
+        TcpServer {
+            context,
+            accept_opts,
+            flow_exporter: None,
+            connection_ramp: None,
+            source_verifier: None,
+        }
+    }
+
+    pub fn set_flow_exporter(&mut self, exporter: FlowExporter) {
+        self.flow_exporter = Some(exporter);
+    }
+
+    pub fn set_connection_ramp(&mut self, ramp: Arc<ConnectionRamp>) {
+        self.connection_ramp = Some(ramp);
+    }
+
+    pub fn set_source_verifier(&mut self, verifier: Arc<UdpSourceVerifier>) {
+        self.source_verifier = Some(verifier);
     }
 
     pub async fn run(self, svr_cfg: &ServerConfig) -> io::Result<()> {
-        let listener = ProxyListener::bind_with_opts(self.context.context(), svr_cfg, self.accept_opts).await?;
+        let listener = match ProxyListener::bind_with_opts(self.context.context(), svr_cfg, self.accept_opts).await {
+            Ok(l) => l,
+            Err(err) => {
+                #[cfg(target_os = "linux")]
+                let err = crate::sys::explain_bind_error(err, svr_cfg.addr().port());
+                error!(
+                    "shadowsocks tcp server failed to listen on {}, error: {}",
+                    svr_cfg.addr(),
+                    err
+                );
+                return Err(err);
+            }
+        };
 
         info!(
             "shadowsocks tcp server listening on {}, inbound address {}",
@@ -50,45 +102,148 @@ impl TcpServer {
         loop {
             let flow_stat = self.context.flow_stat();
 
-            let (local_stream, peer_addr) =
-                match listener.accept_map(|s| MonProxyStream::from_stream(s, flow_stat)).await {
-                    Ok(s) => s,
-                    Err(err) => {
-                        error!("tcp server accept failed with error: {}", err);
-                        time::sleep(Duration::from_secs(1)).await;
-                        continue;
-                    }
-                };
-
-            let client = TcpServerClient {
-                context: self.context.clone(),
-                method: svr_cfg.method(),
-                peer_addr,
-                stream: local_stream,
-                timeout: svr_cfg.timeout(),
+            // Connections are only counted separately if their flows are exported
+            let accepted = match self.flow_exporter {
+                Some(ref exporter) => {
+                    let conn_flow_stat = Arc::new(FlowStat::new());
+                    let mut server_addr = None;
+                    #[cfg(target_os = "linux")]
+                    let mut client_fd = -1;
+
+                    let accepted = listener
+                        .accept_map(|s| {
+                            server_addr = s.local_addr().ok();
+                            #[cfg(target_os = "linux")]
+                            {
+                                use std::os::unix::io::AsRawFd;
+                                client_fd = s.as_raw_fd();
+                            }
+                            MonProxyStream::from_stream(
+                                MonProxyStream::from_stream(s, flow_stat),
+                                conn_flow_stat.clone(),
+                            )
+                        })
+                        .await;
+
+                    accepted.map(|(stream, peer_addr)| {
+                        let flow = server_addr.map(|server_addr| ExportedFlow {
+                            exporter: exporter.clone(),
+                            flow_stat: conn_flow_stat,
+                            server_addr,
+                            #[cfg(target_os = "linux")]
+                            client_fd,
+                        });
+                        self.serve_client(svr_cfg, stream, peer_addr, flow)
+                    })
+                }
+                None => listener
+                    .accept_map(|s| MonProxyStream::from_stream(s, flow_stat))
+                    .await
+                    .map(|(stream, peer_addr)| self.serve_client(svr_cfg, stream, peer_addr, None)),
             };
 
-            tokio::spawn(async move {
-                if let Err(err) = client.serve().await {
-                    debug!("tcp server stream aborted with error: {}", err);
+            match accepted {
+                Ok(..) => {}
+                Err(err) if is_benign_close_error(&err) => {
+                    debug!("tcp server accept failed, client closed connection, error: {}", err);
                 }
-            });
+                Err(err) => {
+                    error!("tcp server accept failed with error: {}", err);
+                    time::sleep(Duration::from_secs(1)).await;
+                }
+            }
         }
     }
+
+    fn serve_client<S>(
+        &self,
+        svr_cfg: &ServerConfig,
+        stream: ProxyServerStream<S>,
+        peer_addr: SocketAddr,
+        flow: Option<ExportedFlow>,
+    ) where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let client = TcpServerClient {
+            context: self.context.clone(),
+            method: svr_cfg.method(),
+            peer_addr,
+            stream,
+            timeout: svr_cfg.timeout(),
+            flow,
+            source_verifier: self.source_verifier.clone(),
+        };
+
+        let delay = self.connection_ramp.as_ref().map(|r| r.enter(peer_addr.ip()));
+
+        tokio::spawn(async move {
+            if let Some(delay) = delay {
+                if delay > Duration::from_secs(0) {
+                    trace!("tcp client {} ramping up, delayed {:?}", peer_addr, delay);
+                    time::sleep(delay).await;
+                }
+            }
+
+            if let Err(err) = client.serve().await {
+                debug!("tcp server stream aborted with error: {}", err);
+            }
+        });
+    }
+}
+
+/// Flow of a connection which will be exported after it is finished
+struct ExportedFlow {
+    exporter: FlowExporter,
+    flow_stat: Arc<FlowStat>,
+    server_addr: SocketAddr,
+    #[cfg(target_os = "linux")]
+    client_fd: std::os::unix::io::RawFd,
 }
 
-struct TcpServerClient {
+struct TcpServerClient<S> {
     context: Arc<ServiceContext>,
     method: CipherKind,
     peer_addr: SocketAddr,
-    stream: ProxyServerStream<MonProxyStream<TokioTcpStream>>,
+    stream: ProxyServerStream<S>,
     timeout: Option<Duration>,
+    flow: Option<ExportedFlow>,
+    source_verifier: Option<Arc<UdpSourceVerifier>>,
 }
 
-impl TcpServerClient {
+impl<S> TcpServerClient<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
     async fn serve(mut self) -> io::Result<()> {
-        let target_addr = match Address::read_from(&mut self.stream).await {
+        let start_time = SystemTime::now();
+
+        // Server never sends anything before the first chunk from the client has been decrypted successfully,
+        // so a probe which connects without sending anything sees nothing but the connection being closed after
+        // `timeout`. Response (with the server's salt / IV) is only written after the target has been connected.
+        let target_addr = match self.timeout {
+            Some(d) => match time::timeout(d, Address::read_from(&mut self.stream)).await {
+                Ok(r) => r,
+                Err(..) => {
+                    debug!(
+                        "tcp client {} didn't send its first chunk in {:?}, closing",
+                        self.peer_addr, d
+                    );
+                    return Ok(());
+                }
+            },
+            None => Address::read_from(&mut self.stream).await,
+        };
+
+        let target_addr = match target_addr {
+            Ok(a) if self.context.unmap_ipv4() => a.unmap_ipv4(),
             Ok(a) => a,
+            Err(err) if is_benign_close_error(&err) => {
+                debug!(
+                    "tcp client {} closed connection before handshake, error: {}",
+                    self.peer_addr, err
+                );
+                return Ok(());
+            }
             Err(err) => {
                 // https://github.com/shadowsocks/shadowsocks-rust/issues/292
                 //
@@ -102,6 +257,11 @@ impl TcpServerClient {
             }
         };
 
+        // Client has sent a valid first chunk on an established TCP connection, so it owns its address
+        if let Some(ref verifier) = self.source_verifier {
+            verifier.validate(self.peer_addr.ip());
+        }
+
         trace!(
             "accepted tcp client connection {}, establishing tunnel to {}",
             self.peer_addr,
@@ -148,15 +308,20 @@ impl TcpServerClient {
             }
         };
 
-        let (mut lr, mut lw) = self.stream.into_split();
-        let (mut rr, mut rw) = remote_stream.split();
+        // Flow is exported only if the target's address is known
+        let remote_addr = remote_stream.peer_addr().ok();
+
+        let (lr, mut lw) = self.stream.into_split();
+        let (rr, mut rw) = remote_stream.split();
+
+        let deadline = lifetime_deadline(self.context.max_connection_lifetime());
+
+        let mut lr = LifetimeReader::new(lr, deadline);
+        let mut rr = LifetimeReader::new(rr, deadline);
 
         let l2r = copy_to_encrypted(self.method, &mut lr, &mut rw);
         let r2l = copy_from_encrypted(self.method, &mut rr, &mut lw);
 
-        tokio::pin!(l2r);
-        tokio::pin!(r2l);
-
         debug!(
             "established tcp tunnel {} <-> {} with {:?}",
             self.peer_addr,
@@ -164,29 +329,30 @@ impl TcpServerClient {
             self.context.connect_opts_ref()
         );
 
-        match future::select(l2r, r2l).await {
-            Either::Left((Ok(..), ..)) => {
-                trace!("tcp tunnel {} -> {} closed", self.peer_addr, target_addr);
-            }
-            Either::Left((Err(err), ..)) => {
-                trace!(
-                    "tcp tunnel {} -> {} closed with error: {}",
-                    self.peer_addr,
-                    target_addr,
-                    err
-                );
-            }
-            Either::Right((Ok(..), ..)) => {
-                trace!("tcp tunnel {} <- {} closed", self.peer_addr, target_addr);
-            }
-            Either::Right((Err(err), ..)) => {
-                trace!(
-                    "tcp tunnel {} <- {} closed with error: {}",
-                    self.peer_addr,
-                    target_addr,
-                    err
-                );
-            }
+        if let TcpTunnelCloseReason::LifetimeExceeded =
+            relay_tcp_tunnel(self.peer_addr, &target_addr, deadline, l2r, r2l).await
+        {
+            shutdown_on_lifetime_exceeded(&mut rw, &mut lw).await;
+        }
+
+        if let (Some(flow), Some(remote_addr)) = (self.flow, remote_addr) {
+            // Client's socket is still owned by `lr` and `lw`
+            #[cfg(target_os = "linux")]
+            let (responder_packets, initiator_packets) = crate::sys::tcp_segments(flow.client_fd).unwrap_or((0, 0));
+            #[cfg(not(target_os = "linux"))]
+            let (responder_packets, initiator_packets) = (0, 0);
+
+            flow.exporter.export(FlowRecord {
+                source: self.peer_addr,
+                server: flow.server_addr,
+                target: remote_addr,
+                initiator_octets: flow.flow_stat.rx(),
+                responder_octets: flow.flow_stat.tx(),
+                initiator_packets,
+                responder_packets,
+                start_time,
+                end_time: SystemTime::now(),
+            });
         }
 
         Ok(())
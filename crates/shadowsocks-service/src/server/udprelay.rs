@@ -22,13 +22,19 @@ use tokio::{
     time,
 };
 
-use crate::net::MonProxySocket;
+use crate::net::{
+    udp_eviction::{evict_if_full, UdpAssociationUsage},
+    udp_send_error::handle_send_error,
+    MonProxySocket,
+};
 
-use super::context::ServiceContext;
+use super::{context::ServiceContext, source_verify::UdpSourceVerifier};
 
 pub struct UdpServer {
     context: Arc<ServiceContext>,
     assoc_map: Arc<Mutex<LruCache<SocketAddr, UdpAssociation>>>,
+    capacity: Option<usize>,
+    source_verifier: Option<Arc<UdpSourceVerifier>>,
     cleanup_abortable: AbortHandle,
 }
 
@@ -41,10 +47,8 @@ impl Drop for UdpServer {
 impl UdpServer {
     pub fn new(context: Arc<ServiceContext>, time_to_live: Option<Duration>, capacity: Option<usize>) -> UdpServer {
         let time_to_live = time_to_live.unwrap_or(crate::DEFAULT_UDP_EXPIRY_DURATION);
-        let assoc_map = Arc::new(Mutex::new(match capacity {
-            Some(capacity) => LruCache::with_expiry_duration_and_capacity(time_to_live, capacity),
-            None => LruCache::with_expiry_duration(time_to_live),
-        }));
+        // Capacity is kept by `evict_if_full` with the configured eviction policy
+        let assoc_map = Arc::new(Mutex::new(LruCache::with_expiry_duration(time_to_live)));
 
         let cleanup_abortable = {
             let assoc_map = assoc_map.clone();
@@ -63,12 +67,30 @@ impl UdpServer {
         UdpServer {
             context,
             assoc_map,
+            capacity,
+            source_verifier: None,
             cleanup_abortable,
         }
     }
 
+    pub fn set_source_verifier(&mut self, verifier: Arc<UdpSourceVerifier>) {
+        self.source_verifier = Some(verifier);
+    }
+
     pub async fn run(mut self, svr_cfg: &ServerConfig) -> io::Result<()> {
-        let socket = ProxySocket::bind(self.context.context(), svr_cfg).await?;
+        let socket = match ProxySocket::bind(self.context.context(), svr_cfg).await {
+            Ok(s) => s,
+            Err(err) => {
+                #[cfg(target_os = "linux")]
+                let err = crate::sys::explain_bind_error(err, svr_cfg.addr().port());
+                error!(
+                    "shadowsocks udp server failed to listen on {}, error: {}",
+                    svr_cfg.addr(),
+                    err
+                );
+                return Err(err);
+            }
+        };
 
         info!(
             "shadowsocks udp server listening on {}",
@@ -112,9 +134,35 @@ impl UdpServer {
         target_addr: Address,
         data: &[u8],
     ) -> io::Result<()> {
-        match self.assoc_map.lock().await.entry(peer_addr) {
+        let mut assoc_map = self.assoc_map.lock().await;
+
+        // Checked before evicting, packets from unverified sources shouldn't affect the established associations
+        if let Some(ref verifier) = self.source_verifier {
+            if !assoc_map.contains_key(&peer_addr) && !verifier.is_validated(&peer_addr.ip()) {
+                debug!(
+                    "udp client {} isn't validated by tcp connections, dropped packet {} bytes to {}",
+                    peer_addr,
+                    data.len(),
+                    target_addr
+                );
+                return Ok(());
+            }
+        }
+
+        if let Some(capacity) = self.capacity {
+            evict_if_full(
+                &mut assoc_map,
+                &peer_addr,
+                capacity,
+                self.context.udp_eviction_policy(),
+                |assoc| &assoc.usage,
+            );
+        }
+
+        match assoc_map.entry(peer_addr) {
             Entry::Occupied(occ) => {
                 let assoc = occ.into_mut();
+                assoc.usage.touch();
                 assoc.try_send((target_addr, Bytes::copy_from_slice(data)))
             }
             Entry::Vacant(vac) => {
@@ -125,6 +173,7 @@ impl UdpServer {
                     self.assoc_map.clone(),
                 ));
                 trace!("created udp association for {}", peer_addr);
+                assoc.usage.touch();
                 assoc.try_send((target_addr, Bytes::copy_from_slice(data)))
             }
         }
@@ -134,6 +183,7 @@ impl UdpServer {
 struct UdpAssociation {
     assoc: Arc<UdpAssociationContext>,
     sender: mpsc::Sender<(Address, Bytes)>,
+    usage: UdpAssociationUsage,
 }
 
 impl Drop for UdpAssociation {
@@ -151,7 +201,11 @@ impl UdpAssociation {
         assoc_map: Arc<Mutex<LruCache<SocketAddr, UdpAssociation>>>,
     ) -> UdpAssociation {
         let (assoc, sender) = UdpAssociationContext::new(context, inbound, peer_addr, assoc_map);
-        UdpAssociation { assoc, sender }
+        UdpAssociation {
+            assoc,
+            sender,
+            usage: UdpAssociationUsage::new(),
+        }
     }
 
     fn try_send(&self, data: (Address, Bytes)) -> io::Result<()> {
@@ -269,10 +323,22 @@ impl UdpAssociationContext {
 
     async fn copy_l2r_dispatch(self: Arc<Self>, target_addr: &Address, data: &[u8]) -> io::Result<()> {
         match *target_addr {
-            Address::SocketAddress(sa) => match sa {
-                SocketAddr::V4(..) => self.copy_ipv4_l2r_dispatch(sa, data).await,
-                SocketAddr::V6(..) => self.copy_ipv6_l2r_dispatch(sa, data).await,
-            },
+            Address::SocketAddress(sa) => {
+                if self.context.unmap_ipv4() {
+                    if let Address::SocketAddress(unmapped) = target_addr.clone().unmap_ipv4() {
+                        if unmapped != sa {
+                            // Responses from the IPv4 address are sent back with the address that client requested
+                            self.target_cache.lock().await.insert(unmapped, target_addr.clone());
+                            return self.copy_ipv4_l2r_dispatch(unmapped, data).await;
+                        }
+                    }
+                }
+
+                match sa {
+                    SocketAddr::V4(..) => self.copy_ipv4_l2r_dispatch(sa, data).await,
+                    SocketAddr::V6(..) => self.copy_ipv6_l2r_dispatch(sa, data).await,
+                }
+            }
             Address::DomainNameAddress(ref dname, port) => {
                 let sa = lookup_then!(self.context.context_ref(), dname, port, |sa| {
                     match sa {
@@ -331,7 +397,22 @@ impl UdpAssociationContext {
             }
         };
 
-        let n = outbound.send_to(data, target_addr).await?;
+        let n = match outbound.send_to(data, target_addr).await {
+            Ok(n) => n,
+            Err(err) => {
+                handle_send_error(
+                    &self.assoc_map,
+                    self.context.udp_send_error_stat_ref(),
+                    self.context.udp_send_error_policy(),
+                    self.peer_addr,
+                    target_addr,
+                    data.len(),
+                    &err,
+                )
+                .await;
+                return Ok(());
+            }
+        };
         if n != data.len() {
             warn!(
                 "{} -> {} sent {} bytes != expected {} bytes",
@@ -386,7 +467,22 @@ impl UdpAssociationContext {
             }
         };
 
-        let n = outbound.send_to(data, target_addr).await?;
+        let n = match outbound.send_to(data, target_addr).await {
+            Ok(n) => n,
+            Err(err) => {
+                handle_send_error(
+                    &self.assoc_map,
+                    self.context.udp_send_error_stat_ref(),
+                    self.context.udp_send_error_policy(),
+                    self.peer_addr,
+                    target_addr,
+                    data.len(),
+                    &err,
+                )
+                .await;
+                return Ok(());
+            }
+        };
         if n != data.len() {
             warn!(
                 "{} -> {} sent {} bytes != expected {} bytes",
@@ -406,7 +502,9 @@ impl UdpAssociationContext {
             let (n, addr) = match outbound.recv_from(&mut buffer).await {
                 Ok(n) => {
                     // Keep association alive in map
-                    let _ = self.assoc_map.lock().await.get(&self.peer_addr);
+                    if let Some(assoc) = self.assoc_map.lock().await.get_mut(&self.peer_addr) {
+                        assoc.usage.touch();
+                    }
                     n
                 }
                 Err(err) => {
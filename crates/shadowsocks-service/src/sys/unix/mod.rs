@@ -1,3 +1,5 @@
+#[cfg(target_os = "linux")]
+use std::os::unix::io::RawFd;
 use std::{
     io::{self, Error, ErrorKind},
     mem,
@@ -47,3 +49,98 @@ pub fn set_nofile(_nofile: u64) -> io::Result<()> {
     // Android doesn't have this API
     Ok(())
 }
+
+/// Capability number of `CAP_NET_BIND_SERVICE`, linux/capability.h
+#[cfg(target_os = "linux")]
+const CAP_NET_BIND_SERVICE: u32 = 10;
+
+/// Check if the current process has `CAP_NET_BIND_SERVICE` in its effective capability set
+#[cfg(target_os = "linux")]
+pub fn has_net_bind_service_capability() -> io::Result<bool> {
+    let status = std::fs::read_to_string("/proc/self/status")?;
+
+    for line in status.lines() {
+        if let Some(caps) = line.strip_prefix("CapEff:") {
+            let caps = match u64::from_str_radix(caps.trim(), 16) {
+                Ok(c) => c,
+                Err(..) => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "invalid CapEff in /proc/self/status",
+                    ))
+                }
+            };
+            return Ok(caps & (1 << CAP_NET_BIND_SERVICE) != 0);
+        }
+    }
+
+    Err(Error::new(
+        ErrorKind::InvalidData,
+        "missing CapEff in /proc/self/status",
+    ))
+}
+
+/// The first port that could be bound without `CAP_NET_BIND_SERVICE`
+///
+/// It is 1024 unless it is changed by sysctl `net.ipv4.ip_unprivileged_port_start`
+#[cfg(target_os = "linux")]
+pub fn unprivileged_port_start() -> u16 {
+    const DEFAULT_UNPRIVILEGED_PORT_START: u16 = 1024;
+
+    match std::fs::read_to_string("/proc/sys/net/ipv4/ip_unprivileged_port_start") {
+        Ok(s) => s.trim().parse::<u16>().unwrap_or(DEFAULT_UNPRIVILEGED_PORT_START),
+        Err(..) => DEFAULT_UNPRIVILEGED_PORT_START,
+    }
+}
+
+/// Explain `err` of binding to `port` if it may be caused by missing `CAP_NET_BIND_SERVICE`
+#[cfg(target_os = "linux")]
+pub fn explain_bind_error(err: Error, port: u16) -> Error {
+    if err.kind() != ErrorKind::PermissionDenied || port >= unprivileged_port_start() {
+        return err;
+    }
+
+    if let Ok(true) = has_net_bind_service_capability() {
+        return err;
+    }
+
+    Error::new(
+        err.kind(),
+        format!(
+            "{}, binding to privileged port {} requires CAP_NET_BIND_SERVICE, \
+             grant it with `setcap cap_net_bind_service=+ep /path/to/binary` instead of running as root",
+            err, port
+        ),
+    )
+}
+
+/// Offset of `tcpi_segs_out` in `struct tcp_info`, linux/tcp.h, followed by `tcpi_segs_in`
+#[cfg(target_os = "linux")]
+const TCPI_SEGS_OUT_OFFSET: usize = 136;
+
+/// Get count of TCP segments sent and received by socket `fd`, from `TCP_INFO`
+///
+/// `libc::tcp_info` doesn't have these fields, so they are read at their offsets. Returns `None` if the kernel
+/// doesn't report them (older than 4.2).
+#[cfg(target_os = "linux")]
+pub fn tcp_segments(fd: RawFd) -> Option<(u64, u64)> {
+    // u32 buffer for the alignment of tcp_info
+    let mut info = [0u32; 64];
+    let mut len = mem::size_of_val(&info) as libc::socklen_t;
+
+    let ret = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            info.as_mut_ptr() as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret < 0 || (len as usize) < TCPI_SEGS_OUT_OFFSET + 8 {
+        return None;
+    }
+
+    let idx = TCPI_SEGS_OUT_OFFSET / 4;
+    Some((info[idx] as u64, info[idx + 1] as u64))
+}
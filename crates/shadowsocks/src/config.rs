@@ -64,6 +64,8 @@ pub struct ServerConfig {
     remarks: Option<String>,
     /// ID (SIP008) is a random generated UUID
     id: Option<String>,
+    /// Weight in the load balancer, relative to the other servers
+    weight: Option<u32>,
 }
 
 impl ServerConfig {
@@ -88,6 +90,7 @@ impl ServerConfig {
             plugin_addr: None,
             remarks: None,
             id: None,
+            weight: None,
         }
     }
 
@@ -194,6 +197,18 @@ impl ServerConfig {
         self.id = Some(id.into())
     }
 
+    /// Get server's weight in the load balancer
+    pub fn weight(&self) -> Option<u32> {
+        self.weight
+    }
+
+    /// Set server's weight in the load balancer
+    ///
+    /// A server with weight 3 will be chosen for about 3 times of connections as a server with weight 1
+    pub fn set_weight(&mut self, weight: u32) {
+        self.weight = Some(weight);
+    }
+
     /// Get URL for QRCode
     /// ```plain
     /// ss:// + base64(method:password@host:port)
@@ -480,11 +495,35 @@ pub enum ManagerAddr {
     /// Unix socket path
     #[cfg(unix)]
     UnixSocketAddr(PathBuf),
+    /// Abstract unix socket name, without the leading null byte
+    #[cfg(target_os = "linux")]
+    UnixAbstractAddr(String),
 }
 
 /// Error for parsing `ManagerAddr`
-#[derive(Debug)]
-pub struct ManagerAddrError;
+#[derive(Debug, Clone)]
+pub enum ManagerAddrError {
+    /// Not a valid address
+    InvalidAddr,
+    /// Abstract unix socket `@name` on platforms other than Linux
+    AbstractUnixSocketUnsupported,
+}
+
+impl fmt::Display for ManagerAddrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ManagerAddrError::InvalidAddr => write!(
+                f,
+                "should be either ip:port, domain:port, /path/to/unix.sock or @name (Linux only)"
+            ),
+            ManagerAddrError::AbstractUnixSocketUnsupported => {
+                write!(f, "abstract unix sockets are only supported on Linux")
+            }
+        }
+    }
+}
+
+impl error::Error for ManagerAddrError {}
 
 impl FromStr for ManagerAddr {
     type Err = ManagerAddrError;
@@ -502,18 +541,32 @@ impl FromStr for ManagerAddr {
 
                         match sport.parse::<u16>() {
                             Ok(port) => Ok(ManagerAddr::DomainName(sdomain.to_owned(), port)),
-                            Err(..) => Err(ManagerAddrError),
+                            Err(..) => Err(ManagerAddrError::InvalidAddr),
                         }
                     }
                 }
             }
+            #[cfg(target_os = "linux")]
+            None if s.starts_with('@') => {
+                // Abstract unix socket, @name
+                let name = &s[1..];
+                if name.is_empty() {
+                    return Err(ManagerAddrError::InvalidAddr);
+                }
+                Ok(ManagerAddr::UnixAbstractAddr(name.to_owned()))
+            }
+            #[cfg(not(target_os = "linux"))]
+            None if s.starts_with('@') => {
+                // Abstract unix socket is a Linux feature
+                Err(ManagerAddrError::AbstractUnixSocketUnsupported)
+            }
             #[cfg(unix)]
             None => {
                 // Must be a unix socket path
                 Ok(ManagerAddr::UnixSocketAddr(PathBuf::from(s)))
             }
             #[cfg(not(unix))]
-            None => Err(ManagerAddrError),
+            None => Err(ManagerAddrError::InvalidAddr),
         }
     }
 }
@@ -525,6 +578,8 @@ impl Display for ManagerAddr {
             ManagerAddr::DomainName(ref dname, port) => write!(f, "{}:{}", dname, port),
             #[cfg(unix)]
             ManagerAddr::UnixSocketAddr(ref path) => fmt::Display::fmt(&path.display(), f),
+            #[cfg(target_os = "linux")]
+            ManagerAddr::UnixAbstractAddr(ref name) => write!(f, "@{}", name),
         }
     }
 }
@@ -553,3 +608,16 @@ impl From<PathBuf> for ManagerAddr {
         ManagerAddr::UnixSocketAddr(p)
     }
 }
+
+#[cfg(all(test, not(target_os = "linux")))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_abstract_manager_addr_unsupported() {
+        match "@ss-manager".parse::<ManagerAddr>() {
+            Err(ManagerAddrError::AbstractUnixSocketUnsupported) => {}
+            r => panic!("{:?} should be unsupported", r),
+        }
+    }
+}
@@ -27,6 +27,12 @@ const BF_ERROR_RATE_FOR_SERVER: f64 = 1e-6;
 // Borrowed from shadowsocks-libev's default value
 const BF_ERROR_RATE_FOR_CLIENT: f64 = 1e-15;
 
+// Default minimum average AEAD chunk size for server
+//
+// Chunk flooding sends 1 byte chunks. Chunks of legitimate streams carry everything read from the client at once,
+// even interactive ones (SSH sends keystrokes in packets of dozens of bytes).
+const DEFAULT_MIN_AVG_CHUNK_SIZE_FOR_SERVER: usize = 2;
+
 // A bloom filter borrowed from shadowsocks-libev's `ppbloom`
 //
 // It contains 2 bloom filters and each one holds 1/2 entries.
@@ -98,6 +104,9 @@ pub struct Context {
 
     // trust-dns resolver, which supports REAL asynchronous resolving, and also customizable
     dns_resolver: Arc<DnsResolver>,
+
+    // Connections sending AEAD chunks smaller than this in average will be dropped, `0` disables the check
+    min_avg_chunk_size: usize,
 }
 
 /// `Context` for sharing between services
@@ -107,9 +116,14 @@ impl Context {
     /// Create a new `Context` for `Client` or `Server`
     pub fn new(config_type: ServerType) -> Context {
         let nonce_ppbloom = SpinMutex::new(PingPongBloom::new(config_type));
+        let min_avg_chunk_size = match config_type {
+            ServerType::Local => 0,
+            ServerType::Server => DEFAULT_MIN_AVG_CHUNK_SIZE_FOR_SERVER,
+        };
         Context {
             nonce_ppbloom,
             dns_resolver: Arc::new(DnsResolver::system_resolver()),
+            min_avg_chunk_size,
         }
     }
 
@@ -148,4 +162,18 @@ impl Context {
     pub async fn dns_resolve<'a>(&self, addr: &'a str, port: u16) -> io::Result<impl Iterator<Item = SocketAddr> + 'a> {
         self.dns_resolver.resolve(addr, port).await
     }
+
+    /// Set the minimum average size of AEAD chunks received in one connection
+    ///
+    /// Connections that are flooding with tiny chunks will be dropped. Set to `0` to disable this check.
+    ///
+    /// It is 2 bytes by default for server, and disabled for local, which receives chunks from trusted servers.
+    pub fn set_min_avg_chunk_size(&mut self, size: usize) {
+        self.min_avg_chunk_size = size;
+    }
+
+    /// Get the minimum average size of AEAD chunks received in one connection
+    pub fn min_avg_chunk_size(&self) -> usize {
+        self.min_avg_chunk_size
+    }
 }
@@ -6,7 +6,10 @@ pub use self::{
     config::{ManagerAddr, ServerAddr, ServerConfig},
     manager::{ManagerClient, ManagerListener},
     relay::{
-        tcprelay::{proxy_listener::ProxyListener, proxy_stream::ProxyClientStream},
+        tcprelay::{
+            proxy_listener::ProxyListener,
+            proxy_stream::{connect_proxied, ProxiedStream, ProxyClientStream},
+        },
         udprelay::proxy_socket::ProxySocket,
     },
 };
@@ -3,7 +3,18 @@
 #[cfg(unix)]
 use std::io::ErrorKind;
 use std::{fmt, io, net::SocketAddr};
+#[cfg(target_os = "linux")]
+use std::{
+    mem,
+    os::unix::{
+        ffi::OsStrExt,
+        io::{AsRawFd, FromRawFd},
+        net::UnixDatagram as StdUnixDatagram,
+    },
+};
 
+#[cfg(target_os = "linux")]
+use tokio::io::unix::AsyncFd;
 use tokio::net::UdpSocket;
 #[cfg(unix)]
 use tokio::net::{unix::SocketAddr as UnixSocketAddr, UnixDatagram};
@@ -20,6 +31,9 @@ pub enum ManagerSocketAddr {
     SocketAddr(SocketAddr),
     #[cfg(unix)]
     UnixSocketAddr(UnixSocketAddr),
+    /// `sun_path` of a peer of an abstract unix socket, abstract names start with a null byte
+    #[cfg(target_os = "linux")]
+    UnixRawSocketAddr(Vec<u8>),
 }
 
 impl ManagerSocketAddr {
@@ -29,6 +43,8 @@ impl ManagerSocketAddr {
             ManagerSocketAddr::SocketAddr(..) => false,
             #[cfg(unix)]
             ManagerSocketAddr::UnixSocketAddr(ref s) => s.is_unnamed(),
+            #[cfg(target_os = "linux")]
+            ManagerSocketAddr::UnixRawSocketAddr(ref path) => path.is_empty(),
         }
     }
 }
@@ -39,6 +55,12 @@ impl fmt::Display for ManagerSocketAddr {
             ManagerSocketAddr::SocketAddr(ref saddr) => fmt::Display::fmt(saddr, f),
             #[cfg(unix)]
             ManagerSocketAddr::UnixSocketAddr(ref saddr) => fmt::Debug::fmt(saddr, f),
+            #[cfg(target_os = "linux")]
+            ManagerSocketAddr::UnixRawSocketAddr(ref path) => match path.split_first() {
+                Some((0, name)) => write!(f, "@{}", String::from_utf8_lossy(name)),
+                Some(..) => f.write_str(&String::from_utf8_lossy(path)),
+                None => f.write_str("(unnamed)"),
+            },
         }
     }
 }
@@ -50,6 +72,9 @@ pub enum ManagerDatagram {
     UdpDatagram(UdpSocket),
     #[cfg(unix)]
     UnixDatagram(UnixDatagram),
+    /// Abstract unix socket, `tokio` couldn't represent addresses of its peers
+    #[cfg(target_os = "linux")]
+    UnixAbstractDatagram(AsyncFd<StdUnixDatagram>),
 }
 
 impl ManagerDatagram {
@@ -74,6 +99,13 @@ impl ManagerDatagram {
 
                 Ok(ManagerDatagram::UnixDatagram(UnixDatagram::bind(path)?))
             }
+            #[cfg(target_os = "linux")]
+            ManagerAddr::UnixAbstractAddr(ref name) => {
+                // Abstract sockets don't have files, they are removed automatically after being closed
+                Ok(ManagerDatagram::UnixAbstractDatagram(unix_abstract_datagram(
+                    name, false,
+                )?))
+            }
         }
     }
 
@@ -98,6 +130,14 @@ impl ManagerDatagram {
             // For unix socket, it doesn't need to bind to any valid address
             // Because manager won't response to you
             ManagerAddr::UnixSocketAddr(..) => Ok(ManagerDatagram::UnixDatagram(UnixDatagram::unbound()?)),
+
+            #[cfg(target_os = "linux")]
+            ManagerAddr::UnixAbstractAddr(ref name) => {
+                // Bound to an autogenerated abstract name, so manager could response to it
+                Ok(ManagerDatagram::UnixAbstractDatagram(unix_abstract_datagram(
+                    name, true,
+                )?))
+            }
         }
     }
 
@@ -112,6 +152,8 @@ impl ManagerDatagram {
             ManagerDatagram::UdpDatagram(ref mut udp) => udp.recv(buf).await,
             #[cfg(unix)]
             ManagerDatagram::UnixDatagram(ref mut unix) => unix.recv(buf).await,
+            #[cfg(target_os = "linux")]
+            ManagerDatagram::UnixAbstractDatagram(ref fd) => abstract_io(fd, true, |s| s.recv(buf)).await,
         }
     }
 
@@ -127,6 +169,10 @@ impl ManagerDatagram {
                 let (s, addr) = unix.recv_from(buf).await?;
                 Ok((s, ManagerSocketAddr::UnixSocketAddr(addr)))
             }
+            #[cfg(target_os = "linux")]
+            ManagerDatagram::UnixAbstractDatagram(ref fd) => {
+                abstract_io(fd, true, |s| abstract_recv_from(s, buf)).await
+            }
         }
     }
 
@@ -136,6 +182,8 @@ impl ManagerDatagram {
             ManagerDatagram::UdpDatagram(ref mut udp) => udp.send(buf).await,
             #[cfg(unix)]
             ManagerDatagram::UnixDatagram(ref mut unix) => unix.send(buf).await,
+            #[cfg(target_os = "linux")]
+            ManagerDatagram::UnixAbstractDatagram(ref fd) => abstract_io(fd, false, |s| s.send(buf)).await,
         }
     }
 
@@ -149,6 +197,11 @@ impl ManagerDatagram {
                     let err = io::Error::new(ErrorKind::InvalidInput, "udp datagram requires IP address target");
                     Err(err)
                 }
+                #[cfg(target_os = "linux")]
+                ManagerSocketAddr::UnixRawSocketAddr(..) => {
+                    let err = io::Error::new(ErrorKind::InvalidInput, "udp datagram requires IP address target");
+                    Err(err)
+                }
             },
             #[cfg(unix)]
             ManagerDatagram::UnixDatagram(ref mut unix) => match *target {
@@ -159,11 +212,38 @@ impl ManagerDatagram {
                         Err(err)
                     }
                 },
+                #[cfg(target_os = "linux")]
+                ManagerSocketAddr::UnixRawSocketAddr(..) => {
+                    let err = io::Error::new(ErrorKind::InvalidInput, "unix datagram requires path address target");
+                    Err(err)
+                }
                 ManagerSocketAddr::SocketAddr(..) => {
                     let err = io::Error::new(ErrorKind::InvalidInput, "unix datagram requires path address target");
                     Err(err)
                 }
             },
+            #[cfg(target_os = "linux")]
+            ManagerDatagram::UnixAbstractDatagram(ref fd) => {
+                let path = match *target {
+                    ManagerSocketAddr::UnixRawSocketAddr(ref path) if !path.is_empty() => path.clone(),
+                    ManagerSocketAddr::UnixSocketAddr(ref saddr) => match saddr.as_pathname() {
+                        Some(paddr) => paddr.as_os_str().as_bytes().to_owned(),
+                        None => {
+                            let err = io::Error::new(ErrorKind::InvalidInput, "target address must not be unnamed");
+                            return Err(err);
+                        }
+                    },
+                    ManagerSocketAddr::UnixRawSocketAddr(..) => {
+                        let err = io::Error::new(ErrorKind::InvalidInput, "target address must not be unnamed");
+                        return Err(err);
+                    }
+                    ManagerSocketAddr::SocketAddr(..) => {
+                        let err = io::Error::new(ErrorKind::InvalidInput, "unix datagram requires path address target");
+                        return Err(err);
+                    }
+                };
+                abstract_io(fd, false, |s| abstract_send_to(s, buf, &path)).await
+            }
         }
     }
 
@@ -181,10 +261,36 @@ impl ManagerDatagram {
                     let err = io::Error::new(ErrorKind::InvalidInput, "udp datagram requires IP address target");
                     Err(err)
                 }
+                #[cfg(target_os = "linux")]
+                ManagerAddr::UnixAbstractAddr(..) => {
+                    let err = io::Error::new(ErrorKind::InvalidInput, "udp datagram requires IP address target");
+                    Err(err)
+                }
             },
             #[cfg(unix)]
             ManagerDatagram::UnixDatagram(ref mut unix) => match *target {
                 ManagerAddr::UnixSocketAddr(ref paddr) => unix.send_to(buf, paddr).await,
+                #[cfg(target_os = "linux")]
+                ManagerAddr::UnixAbstractAddr(..) => {
+                    let err = io::Error::new(ErrorKind::InvalidInput, "unix datagram requires path address target");
+                    Err(err)
+                }
+                ManagerAddr::SocketAddr(..) | ManagerAddr::DomainName(..) => {
+                    let err = io::Error::new(ErrorKind::InvalidInput, "unix datagram requires path address target");
+                    Err(err)
+                }
+            },
+            #[cfg(target_os = "linux")]
+            ManagerDatagram::UnixAbstractDatagram(ref fd) => match *target {
+                ManagerAddr::UnixAbstractAddr(ref name) => {
+                    let mut path = vec![0u8];
+                    path.extend_from_slice(name.as_bytes());
+                    abstract_io(fd, false, |s| abstract_send_to(s, buf, &path)).await
+                }
+                ManagerAddr::UnixSocketAddr(ref paddr) => {
+                    let path = paddr.as_os_str().as_bytes();
+                    abstract_io(fd, false, |s| abstract_send_to(s, buf, path)).await
+                }
                 ManagerAddr::SocketAddr(..) | ManagerAddr::DomainName(..) => {
                     let err = io::Error::new(ErrorKind::InvalidInput, "unix datagram requires path address target");
                     Err(err)
@@ -199,6 +305,214 @@ impl ManagerDatagram {
             ManagerDatagram::UdpDatagram(ref socket) => socket.local_addr().map(ManagerSocketAddr::SocketAddr),
             #[cfg(unix)]
             ManagerDatagram::UnixDatagram(ref dgram) => dgram.local_addr().map(ManagerSocketAddr::UnixSocketAddr),
+            #[cfg(target_os = "linux")]
+            ManagerDatagram::UnixAbstractDatagram(ref fd) => abstract_local_addr(fd.get_ref()),
+        }
+    }
+}
+
+/// Build the `sockaddr_un` of `path`, abstract names start with a null byte
+///
+/// `std` rejects paths with null bytes, so the address has to be constructed manually.
+#[cfg(target_os = "linux")]
+fn unix_sockaddr(path: &[u8]) -> io::Result<(libc::sockaddr_un, libc::socklen_t)> {
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+
+    // Abstract names are not null terminated, paths are terminated by the zeroed tail
+    if path.len() >= addr.sun_path.len() {
+        let err = io::Error::new(ErrorKind::InvalidInput, "unix socket address is too long");
+        return Err(err);
+    }
+    for (dst, src) in addr.sun_path.iter_mut().zip(path) {
+        *dst = *src as libc::c_char;
+    }
+    let addr_len = (mem::size_of::<libc::sa_family_t>() + path.len()) as libc::socklen_t;
+
+    Ok((addr, addr_len))
+}
+
+/// Convert `sockaddr_un` returned by the kernel to `ManagerSocketAddr`
+#[cfg(target_os = "linux")]
+fn unix_raw_socket_addr(addr: &libc::sockaddr_un, addr_len: libc::socklen_t) -> ManagerSocketAddr {
+    let path_len = (addr_len as usize)
+        .saturating_sub(mem::size_of::<libc::sa_family_t>())
+        .min(addr.sun_path.len());
+    let mut path = addr.sun_path[..path_len].iter().map(|c| *c as u8).collect::<Vec<u8>>();
+
+    // Paths may be returned with the terminating null bytes
+    if path.first() != Some(&0) {
+        if let Some(pos) = path.iter().position(|b| *b == 0) {
+            path.truncate(pos);
+        }
+    }
+
+    ManagerSocketAddr::UnixRawSocketAddr(path)
+}
+
+/// Run `f` on the abstract unix socket once it is ready
+#[cfg(target_os = "linux")]
+async fn abstract_io<R, F>(fd: &AsyncFd<StdUnixDatagram>, read: bool, mut f: F) -> io::Result<R>
+where
+    F: FnMut(&StdUnixDatagram) -> io::Result<R>,
+{
+    loop {
+        let mut guard = if read {
+            fd.readable().await?
+        } else {
+            fd.writable().await?
+        };
+        match f(fd.get_ref()) {
+            Err(ref err) if err.kind() == ErrorKind::WouldBlock => guard.clear_ready(),
+            r => return r,
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn abstract_recv_from(socket: &StdUnixDatagram, buf: &mut [u8]) -> io::Result<(usize, ManagerSocketAddr)> {
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    let mut addr_len = mem::size_of::<libc::sockaddr_un>() as libc::socklen_t;
+
+    let n = unsafe {
+        libc::recvfrom(
+            socket.as_raw_fd(),
+            buf.as_mut_ptr() as *mut libc::c_void,
+            buf.len(),
+            0,
+            &mut addr as *mut libc::sockaddr_un as *mut libc::sockaddr,
+            &mut addr_len,
+        )
+    };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok((n as usize, unix_raw_socket_addr(&addr, addr_len)))
+}
+
+#[cfg(target_os = "linux")]
+fn abstract_send_to(socket: &StdUnixDatagram, buf: &[u8], path: &[u8]) -> io::Result<usize> {
+    let (addr, addr_len) = unix_sockaddr(path)?;
+
+    let n = unsafe {
+        libc::sendto(
+            socket.as_raw_fd(),
+            buf.as_ptr() as *const libc::c_void,
+            buf.len(),
+            0,
+            &addr as *const libc::sockaddr_un as *const libc::sockaddr,
+            addr_len,
+        )
+    };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(n as usize)
+}
+
+#[cfg(target_os = "linux")]
+fn abstract_local_addr(socket: &StdUnixDatagram) -> io::Result<ManagerSocketAddr> {
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    let mut addr_len = mem::size_of::<libc::sockaddr_un>() as libc::socklen_t;
+
+    let ret = unsafe {
+        libc::getsockname(
+            socket.as_raw_fd(),
+            &mut addr as *mut libc::sockaddr_un as *mut libc::sockaddr,
+            &mut addr_len,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(unix_raw_socket_addr(&addr, addr_len))
+}
+
+/// Create an abstract unix socket binding or connecting to the abstract name `name`
+///
+/// Connecting sockets are bound to an autogenerated abstract name first, so they could be replied to.
+#[cfg(target_os = "linux")]
+fn unix_abstract_datagram(name: &str, connect: bool) -> io::Result<AsyncFd<StdUnixDatagram>> {
+    let mut path = Vec::with_capacity(name.len() + 1);
+    path.push(0u8);
+    path.extend_from_slice(name.as_bytes());
+    let (addr, addr_len) = unix_sockaddr(&path)?;
+
+    let socket = unsafe {
+        let fd = libc::socket(
+            libc::AF_UNIX,
+            libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            0,
+        );
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
         }
+        // Take the ownership, fd will be closed if it fails in the following steps
+        let socket = StdUnixDatagram::from_raw_fd(fd);
+
+        let addr_ptr = &addr as *const libc::sockaddr_un as *const libc::sockaddr;
+        let ret = if connect {
+            // Binding with only the address family autobinds to an unique abstract name
+            let family_len = mem::size_of::<libc::sa_family_t>() as libc::socklen_t;
+            if libc::bind(fd, addr_ptr, family_len) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            libc::connect(fd, addr_ptr, addr_len)
+        } else {
+            libc::bind(fd, addr_ptr, addr_len)
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        socket
+    };
+
+    AsyncFd::new(socket)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use crate::config::ServerType;
+
+    use super::*;
+
+    #[test]
+    fn parse_abstract_manager_addr() {
+        let addr = "@ss-manager".parse::<ManagerAddr>().unwrap();
+        match addr {
+            ManagerAddr::UnixAbstractAddr(ref name) => assert_eq!(name, "ss-manager"),
+            _ => panic!("{:?} is not an abstract address", addr),
+        }
+        assert_eq!(addr.to_string(), "@ss-manager");
+
+        assert!("@".parse::<ManagerAddr>().is_err());
+    }
+
+    #[tokio::test]
+    async fn abstract_manager_reply() {
+        let context = Context::new_shared(ServerType::Server);
+        let addr = ManagerAddr::UnixAbstractAddr(format!("ss-manager-test-{}", std::process::id()));
+
+        let mut manager = ManagerDatagram::bind(&context, &addr).await.unwrap();
+        assert_eq!(manager.local_addr().unwrap().to_string(), addr.to_string());
+
+        let mut client = ManagerDatagram::connect(&context, &addr, &ConnectOpts::default())
+            .await
+            .unwrap();
+        client.send(b"ping").await.unwrap();
+
+        let mut buf = [0u8; 16];
+        let (n, peer_addr) = manager.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"ping");
+        assert!(!peer_addr.is_unnamed());
+        assert!(peer_addr.to_string().starts_with('@'));
+
+        manager.send_to(b"pong", &peer_addr).await.unwrap();
+        let n = client.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"pong");
     }
 }
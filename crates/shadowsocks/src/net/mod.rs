@@ -9,6 +9,8 @@ pub use self::{
 };
 
 mod option;
+pub mod port_exhaustion;
+pub mod report;
 pub mod tcp;
 pub mod udp;
 
@@ -15,6 +15,12 @@ pub struct TcpSocketOpts {
 
     /// `TCP_NODELAY`
     pub nodelay: bool,
+
+    /// `SO_REUSEADDR` of outbound sockets, ignored by inbound sockets and on Windows
+    ///
+    /// It only takes effect on sockets bound to `bind_local_addr`, which could then bind to local ports still in
+    /// `TIME_WAIT` state. It doesn't add ephemeral ports, unbound sockets pick their ports when connecting.
+    pub reuse_addr: bool,
 }
 
 impl Default for TcpSocketOpts {
@@ -23,6 +29,7 @@ impl Default for TcpSocketOpts {
             send_buffer_size: None,
             recv_buffer_size: None,
             nodelay: false,
+            reuse_addr: false,
         }
     }
 }
@@ -49,6 +56,19 @@ pub struct ConnectOpts {
     #[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios"))]
     pub bind_interface: Option<OsString>,
 
+    /// DSCP of outbound packets, going to set by `setsockopt` with `IP_TOS` or `IPV6_TCLASS` option
+    ///
+    /// ```plain
+    /// ToS / Traffic Class byte
+    /// +---+---+---+---+---+---+---+---+
+    /// |         DSCP          |  ECN  |
+    /// +---+---+---+---+---+---+---+---+
+    /// ```
+    ///
+    /// DSCP occupies the higher 6 bits, the lower 2 bits (ECN) are left to the kernel.
+    #[cfg(unix)]
+    pub dscp: Option<u8>,
+
     /// TCP options
     pub tcp: TcpSocketOpts,
 }
@@ -63,6 +83,8 @@ impl Default for ConnectOpts {
             bind_local_addr: None,
             #[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios"))]
             bind_interface: None,
+            #[cfg(unix)]
+            dscp: None,
             tcp: TcpSocketOpts::default(),
         }
     }
@@ -73,12 +95,18 @@ impl Default for ConnectOpts {
 pub struct AcceptOpts {
     /// TCP options
     pub tcp: TcpSocketOpts,
+
+    /// Backlog of TCP listeners, the maximum length of the queue of pending connections
+    ///
+    /// It is capped by the system, like `net.core.somaxconn` on Linux. Default is 1024.
+    pub backlog: Option<i32>,
 }
 
 impl Default for AcceptOpts {
     fn default() -> AcceptOpts {
         AcceptOpts {
             tcp: TcpSocketOpts::default(),
+            backlog: None,
         }
     }
 }
@@ -0,0 +1,125 @@
+//! Exhaustion of ephemeral ports
+//!
+//! Under very high connection churn, the system may run out of ephemeral ports for outbound connections, and
+//! `connect()` (or `bind()`) fails with `EADDRNOTAVAIL`. Retrying immediately only hammers the kernel, so outbound
+//! connects are delayed for a short period after it happened, until ports in `TIME_WAIT` are released.
+//!
+//! Ports are only exhausted for the connections sharing them, so the backoff is scoped the same way:
+//!
+//! - Sockets that are not bound before connecting could reuse a local port for different destinations, they
+//!   back off for connects to the same destination address.
+//! - Sockets bound to an address (`bind_local_addr`) take a port of that address exclusively, they back off for
+//!   connects from the same bind address.
+
+use std::{
+    collections::HashMap,
+    io::{self, ErrorKind},
+    net::{IpAddr, SocketAddr},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use lazy_static::lazy_static;
+use log::warn;
+use spin::Mutex as SpinMutex;
+use tokio::time;
+
+/// Outbound connects are delayed for this period after ephemeral ports were exhausted
+pub const PORT_EXHAUSTION_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Interval between warnings, the error may happen on every connect during exhaustion
+const PORT_EXHAUSTION_WARN_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Connections sharing the same ephemeral ports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ExhaustionScope {
+    BindAddr(IpAddr),
+    Destination(SocketAddr),
+}
+
+impl ExhaustionScope {
+    fn new(bind_addr: Option<IpAddr>, addr: &SocketAddr) -> ExhaustionScope {
+        match bind_addr {
+            Some(ip) => ExhaustionScope::BindAddr(ip),
+            None => ExhaustionScope::Destination(*addr),
+        }
+    }
+}
+
+struct ExhaustionState {
+    last_exhausted: HashMap<ExhaustionScope, Instant>,
+    last_warned: Option<Instant>,
+}
+
+static PORT_EXHAUSTED_COUNT: AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+    static ref PORT_EXHAUSTION_STATE: SpinMutex<ExhaustionState> = SpinMutex::new(ExhaustionState {
+        last_exhausted: HashMap::new(),
+        last_warned: None,
+    });
+}
+
+/// Check if `err` is caused by exhaustion of ephemeral ports (`EADDRNOTAVAIL`)
+pub fn is_port_exhausted(err: &io::Error) -> bool {
+    err.kind() == ErrorKind::AddrNotAvailable
+}
+
+/// Total count of outbound connects failed because ephemeral ports were exhausted
+pub fn port_exhausted_count() -> u64 {
+    PORT_EXHAUSTED_COUNT.load(Ordering::Relaxed)
+}
+
+/// Record that connecting to `addr` from a socket bound to `bind_addr` failed because ephemeral ports were exhausted
+pub fn report_port_exhausted(bind_addr: Option<IpAddr>, addr: &SocketAddr) {
+    let count = PORT_EXHAUSTED_COUNT.fetch_add(1, Ordering::Relaxed) + 1;
+
+    let now = Instant::now();
+    let should_warn = {
+        let mut state = PORT_EXHAUSTION_STATE.lock();
+
+        // Forget scopes that are not backing off anymore
+        state
+            .last_exhausted
+            .retain(|_, t| now.saturating_duration_since(*t) < PORT_EXHAUSTION_BACKOFF);
+        state.last_exhausted.insert(ExhaustionScope::new(bind_addr, addr), now);
+
+        match state.last_warned {
+            Some(t) if now - t < PORT_EXHAUSTION_WARN_INTERVAL => false,
+            _ => {
+                state.last_warned = Some(now);
+                true
+            }
+        }
+    };
+
+    if should_warn {
+        let from = match bind_addr {
+            Some(ip) => format!(" from {}", ip),
+            None => String::new(),
+        };
+        warn!(
+            "connect {}{} failed, ephemeral ports exhausted (EADDRNOTAVAIL, {} times in total), \
+             its outbound connects are delayed for {:?}. consider widening net.ipv4.ip_local_port_range \
+             or enabling net.ipv4.tcp_tw_reuse",
+            addr, from, count, PORT_EXHAUSTION_BACKOFF
+        );
+    }
+}
+
+/// Wait until the backoff period has passed, if ephemeral ports for connecting to `addr` from `bind_addr` were
+/// exhausted recently
+pub async fn wait_backoff(bind_addr: Option<IpAddr>, addr: &SocketAddr) {
+    let last_exhausted = PORT_EXHAUSTION_STATE
+        .lock()
+        .last_exhausted
+        .get(&ExhaustionScope::new(bind_addr, addr))
+        .copied();
+
+    if let Some(t) = last_exhausted {
+        let elapsed = Instant::now() - t;
+        if elapsed < PORT_EXHAUSTION_BACKOFF {
+            time::sleep(PORT_EXHAUSTION_BACKOFF - elapsed).await;
+        }
+    }
+}
@@ -0,0 +1,242 @@
+//! Report of socket options that actually took effect
+//!
+//! Some socket options are silently adjusted or ignored by the kernel, for example Linux clamps `SO_SNDBUF` to
+//! `net.core.wmem_max`. Options are read back from real sockets right after they have been set up, and compared
+//! with the requested values:
+//!
+//! - Outbound options are reported on the first outbound TCP connection of the process.
+//! - Inbound options are reported on the first connection accepted by each listener.
+//! - Listen backlog is reported when each listener is created.
+//!
+//! Options that didn't take effect are logged as warnings.
+
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, FromRawFd};
+#[cfg(windows)]
+use std::os::windows::io::{AsRawSocket, FromRawSocket};
+use std::{
+    fmt,
+    io,
+    mem::ManuallyDrop,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use log::{info, warn};
+use socket2::Socket;
+
+use super::{option::TcpSocketOpts, AcceptOpts, ConnectOpts};
+
+static OUTBOUND_REPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Requested and effective value of a socket option
+#[derive(Debug, Clone)]
+pub struct SocketOptionReport {
+    /// Name of the socket option, like `SO_SNDBUF`
+    pub name: &'static str,
+    /// Value in configuration
+    pub requested: String,
+    /// Value read back from the socket, or the error of getting it
+    pub effective: String,
+}
+
+impl SocketOptionReport {
+    fn new<R, E>(name: &'static str, requested: R, effective: io::Result<E>) -> SocketOptionReport
+    where
+        R: fmt::Display,
+        E: fmt::Display,
+    {
+        SocketOptionReport {
+            name,
+            requested: requested.to_string(),
+            effective: match effective {
+                Ok(e) => e.to_string(),
+                Err(err) => format!("error: {}", err),
+            },
+        }
+    }
+
+    /// Check if the effective value is the same as requested
+    pub fn is_effective(&self) -> bool {
+        self.requested == self.effective
+    }
+}
+
+impl fmt::Display for SocketOptionReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} (effective {})", self.name, self.requested, self.effective)
+    }
+}
+
+/// Read back options in `opts` from `stream`, an outbound TCP connection which has been set up with them
+#[cfg(unix)]
+pub fn report_connect_opts<S: AsRawFd>(stream: &S, is_ipv6: bool, opts: &ConnectOpts) -> Vec<SocketOptionReport> {
+    let socket = ManuallyDrop::new(unsafe { Socket::from_raw_fd(stream.as_raw_fd()) });
+    connect_opts_report(&socket, is_ipv6, opts)
+}
+
+/// Read back options in `opts` from `stream`, an outbound TCP connection which has been set up with them
+#[cfg(windows)]
+pub fn report_connect_opts<S: AsRawSocket>(stream: &S, is_ipv6: bool, opts: &ConnectOpts) -> Vec<SocketOptionReport> {
+    let socket = ManuallyDrop::new(unsafe { Socket::from_raw_socket(stream.as_raw_socket()) });
+    connect_opts_report(&socket, is_ipv6, opts)
+}
+
+/// Read back options in `opts` from `stream`, an inbound TCP connection accepted by a listener set up with them
+#[cfg(unix)]
+pub fn report_accept_opts<S: AsRawFd>(stream: &S, opts: &AcceptOpts) -> Vec<SocketOptionReport> {
+    let socket = ManuallyDrop::new(unsafe { Socket::from_raw_fd(stream.as_raw_fd()) });
+    accept_opts_report(&socket, opts)
+}
+
+/// Read back options in `opts` from `stream`, an inbound TCP connection accepted by a listener set up with them
+#[cfg(windows)]
+pub fn report_accept_opts<S: AsRawSocket>(stream: &S, opts: &AcceptOpts) -> Vec<SocketOptionReport> {
+    let socket = ManuallyDrop::new(unsafe { Socket::from_raw_socket(stream.as_raw_socket()) });
+    accept_opts_report(&socket, opts)
+}
+
+fn connect_opts_report(socket: &Socket, is_ipv6: bool, opts: &ConnectOpts) -> Vec<SocketOptionReport> {
+    let mut reports = Vec::new();
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if let Some(mark) = opts.fwmark {
+        let effective = getsockopt_int(socket, libc::SOL_SOCKET, libc::SO_MARK).map(|m| m as u32);
+        reports.push(SocketOptionReport::new("SO_MARK", mark, effective));
+    }
+
+    // DSCP occupies the higher 6 bits of IP_TOS / IPV6_TCLASS
+    #[cfg(unix)]
+    if let Some(dscp) = opts.dscp {
+        let effective = if is_ipv6 {
+            getsockopt_int(socket, libc::IPPROTO_IPV6, libc::IPV6_TCLASS)
+        } else {
+            getsockopt_int(socket, libc::IPPROTO_IP, libc::IP_TOS)
+        };
+        reports.push(SocketOptionReport::new("DSCP", dscp, effective.map(|tos| tos >> 2)));
+    }
+    #[cfg(not(unix))]
+    let _ = is_ipv6;
+
+    report_tcp_opts(socket, &opts.tcp, &mut reports);
+
+    #[cfg(unix)]
+    if opts.tcp.reuse_addr {
+        reports.push(SocketOptionReport::new("SO_REUSEADDR", true, socket.reuse_address()));
+    }
+
+    reports
+}
+
+fn accept_opts_report(socket: &Socket, opts: &AcceptOpts) -> Vec<SocketOptionReport> {
+    let mut reports = Vec::new();
+
+    report_tcp_opts(socket, &opts.tcp, &mut reports);
+
+    reports
+}
+
+/// Report `backlog` of a listener, and the value actually used by the system
+pub fn report_listen_backlog(backlog: i32) -> SocketOptionReport {
+    SocketOptionReport::new("listen backlog", backlog, effective_listen_backlog(backlog))
+}
+
+/// Backlog is silently truncated to `net.core.somaxconn` by `listen()`, it couldn't be read back
+///
+/// Linux compares it as unsigned, so negative values are truncated too.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn effective_listen_backlog(backlog: i32) -> io::Result<i32> {
+    let somaxconn = std::fs::read_to_string("/proc/sys/net/core/somaxconn")?;
+    let somaxconn = somaxconn
+        .trim()
+        .parse::<i32>()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    if backlog < 0 || backlog > somaxconn {
+        Ok(somaxconn)
+    } else {
+        Ok(backlog)
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn effective_listen_backlog(backlog: i32) -> io::Result<i32> {
+    Ok(backlog)
+}
+
+fn report_tcp_opts(socket: &Socket, opts: &TcpSocketOpts, reports: &mut Vec<SocketOptionReport>) {
+    if let Some(size) = opts.send_buffer_size {
+        let effective = socket.send_buffer_size().map(normalize_buffer_size);
+        reports.push(SocketOptionReport::new("SO_SNDBUF", size, effective));
+    }
+
+    if let Some(size) = opts.recv_buffer_size {
+        let effective = socket.recv_buffer_size().map(normalize_buffer_size);
+        reports.push(SocketOptionReport::new("SO_RCVBUF", size, effective));
+    }
+
+    if opts.nodelay {
+        reports.push(SocketOptionReport::new("TCP_NODELAY", true, socket.nodelay()));
+    }
+}
+
+/// Linux doubles the buffer size set by `setsockopt` for bookkeeping overhead, and reports the doubled value
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn normalize_buffer_size(size: usize) -> usize {
+    size / 2
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn normalize_buffer_size(size: usize) -> usize {
+    size
+}
+
+/// Log `reports` of `category` sockets, options that didn't take effect are warned
+pub fn log_socket_opts_report(category: &str, reports: &[SocketOptionReport]) {
+    if reports.is_empty() {
+        return;
+    }
+
+    let summary = reports.iter().map(ToString::to_string).collect::<Vec<_>>();
+    info!("{} socket options: {}", category, summary.join(", "));
+
+    for report in reports.iter().filter(|r| !r.is_effective()) {
+        warn!(
+            "{} socket option {} is set to {}, but {} took effect",
+            category, report.name, report.requested, report.effective
+        );
+    }
+}
+
+/// Report options of the first outbound TCP connection of the process
+#[cfg(unix)]
+pub(crate) fn report_outbound_once<S: AsRawFd>(stream: &S, is_ipv6: bool, opts: &ConnectOpts) {
+    if !OUTBOUND_REPORTED.swap(true, Ordering::AcqRel) {
+        log_socket_opts_report("outbound", &report_connect_opts(stream, is_ipv6, opts));
+    }
+}
+
+/// Report options of the first outbound TCP connection of the process
+#[cfg(windows)]
+pub(crate) fn report_outbound_once<S: AsRawSocket>(stream: &S, is_ipv6: bool, opts: &ConnectOpts) {
+    if !OUTBOUND_REPORTED.swap(true, Ordering::AcqRel) {
+        log_socket_opts_report("outbound", &report_connect_opts(stream, is_ipv6, opts));
+    }
+}
+
+#[cfg(unix)]
+fn getsockopt_int(socket: &Socket, level: libc::c_int, name: libc::c_int) -> io::Result<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of_val(&value) as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &mut value as *mut _ as *mut _,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(value)
+}
@@ -9,6 +9,7 @@ use std::{
     net::SocketAddr,
     ops::{Deref, DerefMut},
     pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
     task::{self, Poll},
 };
 
@@ -27,7 +28,7 @@ use crate::{
     ServerAddr,
 };
 
-use super::{AcceptOpts, ConnectOpts};
+use super::{port_exhaustion, report, AcceptOpts, ConnectOpts};
 
 /// TcpStream for outbound connections
 #[pin_project]
@@ -36,7 +37,7 @@ pub struct TcpStream(#[pin] TokioTcpStream);
 impl TcpStream {
     /// Connects to address
     pub async fn connect_with_opts(addr: &SocketAddr, opts: &ConnectOpts) -> io::Result<TcpStream> {
-        tcp_stream_connect(addr, opts).await.map(TcpStream)
+        tcp_stream_connect_backoff(addr, opts).await.map(TcpStream)
     }
 
     /// Connects shadowsocks server
@@ -46,10 +47,10 @@ impl TcpStream {
         opts: &ConnectOpts,
     ) -> io::Result<TcpStream> {
         let stream = match *addr {
-            ServerAddr::SocketAddr(ref addr) => tcp_stream_connect(addr, opts).await?,
+            ServerAddr::SocketAddr(ref addr) => tcp_stream_connect_backoff(addr, opts).await?,
             ServerAddr::DomainName(ref domain, port) => {
                 lookup_then!(&context, &domain, port, |addr| {
-                    tcp_stream_connect(&addr, opts).await
+                    tcp_stream_connect_backoff(&addr, opts).await
                 })?
                 .1
             }
@@ -65,10 +66,10 @@ impl TcpStream {
         opts: &ConnectOpts,
     ) -> io::Result<TcpStream> {
         let stream = match *addr {
-            Address::SocketAddress(ref addr) => tcp_stream_connect(addr, opts).await?,
+            Address::SocketAddress(ref addr) => tcp_stream_connect_backoff(addr, opts).await?,
             Address::DomainNameAddress(ref domain, port) => {
                 lookup_then!(&context, &domain, port, |addr| {
-                    tcp_stream_connect(&addr, opts).await
+                    tcp_stream_connect_backoff(&addr, opts).await
                 })?
                 .1
             }
@@ -78,6 +79,27 @@ impl TcpStream {
     }
 }
 
+/// Connects to `addr`, with backpressure if ephemeral ports were exhausted recently
+async fn tcp_stream_connect_backoff(addr: &SocketAddr, opts: &ConnectOpts) -> io::Result<TokioTcpStream> {
+    // Socket only binds to `bind_local_addr` with the same family
+    let bind_addr = opts.bind_local_addr.filter(|ip| ip.is_ipv4() == addr.is_ipv4());
+
+    port_exhaustion::wait_backoff(bind_addr, addr).await;
+
+    match tcp_stream_connect(addr, opts).await {
+        Ok(stream) => {
+            report::report_outbound_once(&stream, addr.is_ipv6(), opts);
+            Ok(stream)
+        }
+        Err(err) => {
+            if port_exhaustion::is_port_exhausted(&err) {
+                port_exhaustion::report_port_exhausted(bind_addr, addr);
+            }
+            Err(err)
+        }
+    }
+}
+
 impl Deref for TcpStream {
     type Target = TokioTcpStream;
 
@@ -128,6 +150,7 @@ impl Into<TokioTcpStream> for TcpStream {
 pub struct TcpListener {
     inner: TokioTcpListener,
     accept_opts: AcceptOpts,
+    opts_reported: AtomicBool,
 }
 
 impl TcpListener {
@@ -139,9 +162,13 @@ impl TcpListener {
             false
         };
 
-        if !set_dual_stack {
+        if !set_dual_stack && accept_opts.backlog.is_none() {
             let inner = TokioTcpListener::bind(addr).await?;
-            Ok(TcpListener { inner, accept_opts })
+            Ok(TcpListener {
+                inner,
+                accept_opts,
+                opts_reported: AtomicBool::new(false),
+            })
         } else {
             let socket = match *addr {
                 SocketAddr::V4(..) => TcpSocket::new_v4()?,
@@ -176,25 +203,40 @@ impl TcpListener {
                 }
             }
 
-            set_only_v6(&socket, false);
-            match socket.bind(*addr) {
-                Ok(..) => {}
-                Err(ref err) if err.kind() == ErrorKind::AddrInUse => {
-                    // This is probably 0.0.0.0 with the same port has already been occupied
-                    debug!(
-                        "0.0.0.0:{} may have already been occupied, retry with IPV6_V6ONLY",
-                        addr.port()
-                    );
-
-                    set_only_v6(&socket, true);
-                    socket.bind(*addr)?;
+            if set_dual_stack {
+                set_only_v6(&socket, false);
+                match socket.bind(*addr) {
+                    Ok(..) => {}
+                    Err(ref err) if err.kind() == ErrorKind::AddrInUse => {
+                        // This is probably 0.0.0.0 with the same port has already been occupied
+                        debug!(
+                            "0.0.0.0:{} may have already been occupied, retry with IPV6_V6ONLY",
+                            addr.port()
+                        );
+
+                        set_only_v6(&socket, true);
+                        socket.bind(*addr)?;
+                    }
+                    Err(err) => return Err(err),
                 }
-                Err(err) => return Err(err),
+            } else {
+                socket.bind(*addr)?;
             }
 
             // mio's default backlog is 1024
-            let inner = socket.listen(1024)?;
-            Ok(TcpListener { inner, accept_opts })
+            //
+            // listen() takes an int, mio casts the value back to it
+            let inner = socket.listen(accept_opts.backlog.map(|b| b as u32).unwrap_or(1024))?;
+
+            if let Some(backlog) = accept_opts.backlog {
+                report::log_socket_opts_report("listener", &[report::report_listen_backlog(backlog)]);
+            }
+
+            Ok(TcpListener {
+                inner,
+                accept_opts,
+                opts_reported: AtomicBool::new(false),
+            })
         }
     }
 
@@ -203,6 +245,7 @@ impl TcpListener {
         TcpListener {
             inner: listener,
             accept_opts,
+            opts_reported: AtomicBool::new(false),
         }
     }
 
@@ -210,6 +253,10 @@ impl TcpListener {
     pub fn poll_accept(&self, cx: &mut task::Context<'_>) -> Poll<io::Result<(TokioTcpStream, SocketAddr)>> {
         let (stream, peer_addr) = ready!(self.inner.poll_accept(cx))?;
         setsockopt_with_opt(&stream, &self.accept_opts)?;
+        if !self.opts_reported.swap(true, Ordering::AcqRel) {
+            let reports = report::report_accept_opts(&stream, &self.accept_opts);
+            report::log_socket_opts_report("inbound", &reports);
+        }
         Poll::Ready(Ok((stream, peer_addr)))
     }
 
@@ -15,17 +15,18 @@
 use std::{
     io,
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener},
-    process::ExitStatus,
+    process::{ExitStatus, Stdio},
     time::{Duration, Instant},
 };
 
-use log::{debug, error};
+use log::{debug, error, Level};
 use tokio::{net::TcpStream, process::Child, time};
 
 use crate::config::ServerAddr;
 
 mod obfs_proxy;
 mod ss_plugin;
+mod stderr;
 
 /// Config for plugin
 #[derive(Debug, Clone)]
@@ -68,6 +69,27 @@ impl Plugin {
     /// `PluginMode::Client`: Plugin listens to `local_addr` and send data to `remote_addr`, client should send data to `local_addr`
     /// `PluginMode::Server`: Plugin listens to `remote_addr` and send data to `local_addr`, server should listen to `local_addr`
     pub fn start(c: &PluginConfig, remote_addr: &ServerAddr, mode: PluginMode) -> io::Result<Plugin> {
+        Plugin::start_impl(c, remote_addr, mode, None)
+    }
+
+    /// Start a plugin subprocess, lines written to its stderr are logged with `level`
+    ///
+    /// Unlike `start`, which lets plugin write to stderr of the current process directly.
+    pub fn start_with_log_level(
+        c: &PluginConfig,
+        remote_addr: &ServerAddr,
+        mode: PluginMode,
+        level: Level,
+    ) -> io::Result<Plugin> {
+        Plugin::start_impl(c, remote_addr, mode, Some(level))
+    }
+
+    fn start_impl(
+        c: &PluginConfig,
+        remote_addr: &ServerAddr,
+        mode: PluginMode,
+        stderr_level: Option<Level>,
+    ) -> io::Result<Plugin> {
         let loop_ip = match remote_addr {
             ServerAddr::SocketAddr(sa) => match sa.ip() {
                 IpAddr::V4(..) => Ipv4Addr::LOCALHOST.into(),
@@ -78,7 +100,7 @@ impl Plugin {
 
         let local_addr = get_local_port(loop_ip)?;
 
-        match start_plugin(c, remote_addr, &local_addr, mode) {
+        match start_plugin(c, remote_addr, &local_addr, mode, stderr_level.is_some()) {
             Err(err) => {
                 error!(
                     "failed to start plugin \"{}\" for server {}, err: {}",
@@ -86,7 +108,16 @@ impl Plugin {
                 );
                 Err(err)
             }
-            Ok(process) => {
+            Ok(mut process) => {
+                if let (Some(level), Some(stderr)) = (stderr_level, process.stderr.take()) {
+                    tokio::spawn(stderr::log_plugin_stderr(
+                        stderr,
+                        c.plugin.clone(),
+                        remote_addr.to_string(),
+                        level,
+                    ));
+                }
+
                 match mode {
                     PluginMode::Client => {
                         debug!(
@@ -220,12 +251,21 @@ impl Drop for Plugin {
     }
 }
 
-fn start_plugin(plugin: &PluginConfig, remote: &ServerAddr, local: &SocketAddr, mode: PluginMode) -> io::Result<Child> {
+fn start_plugin(
+    plugin: &PluginConfig,
+    remote: &ServerAddr,
+    local: &SocketAddr,
+    mode: PluginMode,
+    capture_stderr: bool,
+) -> io::Result<Child> {
     let mut cmd = if plugin.plugin == "obfsproxy" {
         obfs_proxy::plugin_cmd(plugin, remote, local, mode)
     } else {
         ss_plugin::plugin_cmd(plugin, remote, local, mode)
     };
+    if capture_stderr {
+        cmd.stderr(Stdio::piped());
+    }
     cmd.spawn()
 }
 
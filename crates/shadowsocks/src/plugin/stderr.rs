@@ -0,0 +1,115 @@
+//! Logging of plugin processes' stderr
+//!
+//! Each line written to stderr by a plugin is logged with the plugin's name and its server, so diagnostics of
+//! plugins show up in the same log as everything else. Long lines are split, and lines exceeding the rate limit
+//! are dropped, so a chatty plugin can't flood the log.
+
+use std::time::{Duration, Instant};
+
+use log::{log, Level};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::ChildStderr,
+};
+
+/// Lines longer than this are split
+const PLUGIN_STDERR_MAX_LINE: usize = 1024;
+
+/// At most `PLUGIN_STDERR_MAX_LINES` lines are logged in every `PLUGIN_STDERR_INTERVAL`
+const PLUGIN_STDERR_MAX_LINES: usize = 50;
+const PLUGIN_STDERR_INTERVAL: Duration = Duration::from_secs(1);
+
+struct StderrLogger {
+    plugin: String,
+    server: String,
+    level: Level,
+    window_start: Instant,
+    window_lines: usize,
+    suppressed: usize,
+}
+
+impl StderrLogger {
+    fn log_line(&mut self, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end();
+        if line.is_empty() {
+            return;
+        }
+
+        let now = Instant::now();
+        if now - self.window_start >= PLUGIN_STDERR_INTERVAL {
+            self.report_suppressed();
+            self.window_start = now;
+            self.window_lines = 0;
+        }
+
+        if self.window_lines >= PLUGIN_STDERR_MAX_LINES {
+            self.suppressed += 1;
+            return;
+        }
+        self.window_lines += 1;
+
+        log!(
+            self.level,
+            "plugin \"{}\" (server {}): {}",
+            self.plugin,
+            self.server,
+            line
+        );
+    }
+
+    fn report_suppressed(&mut self) {
+        if self.suppressed > 0 {
+            log!(
+                self.level,
+                "plugin \"{}\" (server {}): {} lines of stderr suppressed",
+                self.plugin,
+                self.server,
+                self.suppressed
+            );
+            self.suppressed = 0;
+        }
+    }
+}
+
+/// Log lines from `stderr` of plugin until it is closed
+pub async fn log_plugin_stderr(stderr: ChildStderr, plugin: String, server: String, level: Level) {
+    let mut logger = StderrLogger {
+        plugin,
+        server,
+        level,
+        window_start: Instant::now(),
+        window_lines: 0,
+        suppressed: 0,
+    };
+
+    let mut reader = BufReader::new(stderr);
+    let mut line = Vec::with_capacity(PLUGIN_STDERR_MAX_LINE);
+
+    loop {
+        let buf = match reader.fill_buf().await {
+            Ok(buf) => buf,
+            Err(..) => break,
+        };
+        if buf.is_empty() {
+            break;
+        }
+
+        let (consumed, complete) = match buf.iter().position(|&b| b == b'\n') {
+            Some(pos) => (pos + 1, true),
+            None => (buf.len(), false),
+        };
+
+        let n = consumed.min(PLUGIN_STDERR_MAX_LINE - line.len());
+        line.extend_from_slice(&buf[..n]);
+        reader.consume(n);
+
+        if (complete && n == consumed) || line.len() >= PLUGIN_STDERR_MAX_LINE {
+            logger.log_line(&line);
+            line.clear();
+        }
+    }
+
+    logger.log_line(&line);
+    logger.report_suppressed();
+}
@@ -21,6 +21,7 @@ pub use self::consts::{
     SOCKS5_AUTH_METHOD_NONE,
     SOCKS5_AUTH_METHOD_NOT_ACCEPTABLE,
     SOCKS5_AUTH_METHOD_PASSWORD,
+    SOCKS5_PASSWORD_AUTH_SUCCEEDED,
 };
 
 #[rustfmt::skip]
@@ -32,6 +33,9 @@ mod consts {
     pub const SOCKS5_AUTH_METHOD_PASSWORD:             u8 = 0x02;
     pub const SOCKS5_AUTH_METHOD_NOT_ACCEPTABLE:       u8 = 0xff;
 
+    pub const SOCKS5_PASSWORD_AUTH_VERSION:            u8 = 0x01;
+    pub const SOCKS5_PASSWORD_AUTH_SUCCEEDED:          u8 = 0x00;
+
     pub const SOCKS5_CMD_TCP_CONNECT:                  u8 = 0x01;
     pub const SOCKS5_CMD_TCP_BIND:                     u8 = 0x02;
     pub const SOCKS5_CMD_UDP_ASSOCIATE:                u8 = 0x03;
@@ -166,6 +170,8 @@ pub enum Error {
     AddressDomainInvalidEncoding,
     #[error("unsupported socks version {0:#x}")]
     UnsupportedSocksVersion(u8),
+    #[error("unsupported username/password authentication version {0:#x}")]
+    UnsupportedPasswordAuthVersion(u8),
     #[error("unsupported command {0:#x}")]
     UnsupportedCommand(u8),
     #[error("{0}")]
@@ -192,6 +198,7 @@ impl Error {
             Error::AddressTypeNotSupported(..) => Reply::AddressTypeNotSupported,
             Error::AddressDomainInvalidEncoding => Reply::GeneralFailure,
             Error::UnsupportedSocksVersion(..) => Reply::GeneralFailure,
+            Error::UnsupportedPasswordAuthVersion(..) => Reply::GeneralFailure,
             Error::UnsupportedCommand(..) => Reply::CommandNotSupported,
             Error::Reply(r) => r,
         }
@@ -328,6 +335,20 @@ impl Address {
             Address::DomainNameAddress(ref domain, ..) => domain.to_owned(),
         }
     }
+
+    /// Convert IPv4-mapped IPv6 address (`::ffff:a.b.c.d`) to plain IPv4 address
+    ///
+    /// IPv4-compatible IPv6 addresses (`::a.b.c.d`) are deprecated by RFC 4291 and are kept as IPv6, just like
+    /// the other IPv6 addresses and domain names.
+    pub fn unmap_ipv4(self) -> Address {
+        if let Address::SocketAddress(SocketAddr::V6(v6)) = self {
+            if let [0, 0, 0, 0, 0, 0xffff, hi, lo] = v6.ip().segments() {
+                let ip = Ipv4Addr::new((hi >> 8) as u8, hi as u8, (lo >> 8) as u8, lo as u8);
+                return Address::SocketAddress(SocketAddr::V4(SocketAddrV4::new(ip, v6.port())));
+            }
+        }
+        self
+    }
 }
 
 impl Debug for Address {
@@ -727,6 +748,146 @@ impl HandshakeResponse {
     }
 }
 
+/// SOCKS5 username/password authentication request packet (RFC1929)
+///
+/// ```plain
+/// +----+------+----------+------+----------+
+/// |VER | ULEN |  UNAME   | PLEN |  PASSWD  |
+/// +----+------+----------+------+----------+
+/// | 1  |  1   | 1 to 255 |  1   | 1 to 255 |
+/// +----+------+----------+------+----------+
+/// ```
+#[derive(Clone, Debug)]
+pub struct PasswdAuthRequest {
+    pub uname: Vec<u8>,
+    pub passwd: Vec<u8>,
+}
+
+impl PasswdAuthRequest {
+    /// Creates a username/password authentication request
+    pub fn new<U, P>(uname: U, passwd: P) -> PasswdAuthRequest
+    where
+        U: Into<Vec<u8>>,
+        P: Into<Vec<u8>>,
+    {
+        let uname = uname.into();
+        let passwd = passwd.into();
+        assert!(
+            uname.len() <= u8::MAX as usize && passwd.len() <= u8::MAX as usize,
+            "username and password must be at most 255 bytes"
+        );
+
+        PasswdAuthRequest { uname, passwd }
+    }
+
+    /// Read from a reader
+    pub async fn read_from<R>(r: &mut R) -> Result<PasswdAuthRequest, Error>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut buf = [0u8; 2];
+        let _ = r.read_exact(&mut buf).await?;
+
+        let ver = buf[0];
+        let ulen = buf[1];
+
+        if ver != consts::SOCKS5_PASSWORD_AUTH_VERSION {
+            return Err(Error::UnsupportedPasswordAuthVersion(ver));
+        }
+
+        let mut uname = vec![0u8; ulen as usize];
+        let _ = r.read_exact(&mut uname).await?;
+
+        let plen = r.read_u8().await?;
+        let mut passwd = vec![0u8; plen as usize];
+        let _ = r.read_exact(&mut passwd).await?;
+
+        Ok(PasswdAuthRequest { uname, passwd })
+    }
+
+    /// Write to a writer
+    pub async fn write_to<W>(&self, w: &mut W) -> io::Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let mut buf = BytesMut::with_capacity(self.serialized_len());
+        self.write_to_buf(&mut buf);
+        w.write_all(&buf).await
+    }
+
+    /// Write to buffer
+    pub fn write_to_buf<B: BufMut>(&self, buf: &mut B) {
+        buf.put_slice(&[consts::SOCKS5_PASSWORD_AUTH_VERSION, self.uname.len() as u8]);
+        buf.put_slice(&self.uname);
+        buf.put_u8(self.passwd.len() as u8);
+        buf.put_slice(&self.passwd);
+    }
+
+    /// Get length of bytes
+    pub fn serialized_len(&self) -> usize {
+        3 + self.uname.len() + self.passwd.len()
+    }
+}
+
+/// SOCKS5 username/password authentication response packet (RFC1929)
+///
+/// ```plain
+/// +----+--------+
+/// |VER | STATUS |
+/// +----+--------+
+/// | 1  |   1    |
+/// +----+--------+
+/// ```
+#[derive(Clone, Debug, Copy)]
+pub struct PasswdAuthResponse {
+    pub status: u8,
+}
+
+impl PasswdAuthResponse {
+    /// Creates a username/password authentication response
+    pub fn new(status: u8) -> PasswdAuthResponse {
+        PasswdAuthResponse { status }
+    }
+
+    /// Read from a reader
+    pub async fn read_from<R>(r: &mut R) -> Result<PasswdAuthResponse, Error>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut buf = [0u8; 2];
+        let _ = r.read_exact(&mut buf).await?;
+
+        let ver = buf[0];
+        let status = buf[1];
+
+        if ver != consts::SOCKS5_PASSWORD_AUTH_VERSION {
+            Err(Error::UnsupportedPasswordAuthVersion(ver))
+        } else {
+            Ok(PasswdAuthResponse { status })
+        }
+    }
+
+    /// Write to a writer
+    pub async fn write_to<W>(self, w: &mut W) -> io::Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let mut buf = BytesMut::with_capacity(self.serialized_len());
+        self.write_to_buf(&mut buf);
+        w.write_all(&buf).await
+    }
+
+    /// Write to buffer
+    pub fn write_to_buf<B: BufMut>(self, buf: &mut B) {
+        buf.put_slice(&[consts::SOCKS5_PASSWORD_AUTH_VERSION, self.status]);
+    }
+
+    /// Length in bytes
+    pub fn serialized_len(self) -> usize {
+        2
+    }
+}
+
 /// UDP ASSOCIATE request header
 ///
 /// ```plain
@@ -1,12 +1,11 @@
-#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios"))]
-use std::os::unix::io::AsRawFd;
+#[cfg(any(target_os = "android"))]
+use std::path::Path;
 use std::{
     io::{self, Error, ErrorKind},
     mem,
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    os::unix::io::{AsRawFd, RawFd},
 };
-#[cfg(any(target_os = "android"))]
-use std::{os::unix::io::RawFd, path::Path};
 #[cfg(any(target_os = "macos", target_os = "ios"))]
 use std::{os::unix::prelude::OsStrExt, ptr};
 
@@ -167,6 +166,16 @@ pub async fn tcp_stream_connect(saddr: &SocketAddr, config: &ConnectOpts) -> io:
         }
     }
 
+    // Set IP_TOS / IPV6_TCLASS for DSCP marking
+    if let Some(dscp) = config.dscp {
+        set_dscp(socket.as_raw_fd(), saddr.is_ipv6(), dscp)?;
+    }
+
+    // Set `SO_REUSEADDR`, must be set before binding
+    if config.tcp.reuse_addr {
+        socket.set_reuseaddr(true)?;
+    }
+
     // Binds to IP address
     if let Some(ip) = config.bind_local_addr {
         match (ip, saddr.ip()) {
@@ -303,9 +312,43 @@ pub async fn create_outbound_udp_socket(af: AddrFamily, config: &ConnectOpts) ->
         }
     }
 
+    // Set IP_TOS / IPV6_TCLASS for DSCP marking
+    if let Some(dscp) = config.dscp {
+        set_dscp(socket.as_raw_fd(), bind_addr.is_ipv6(), dscp)?;
+    }
+
     Ok(socket)
 }
 
+/// Set DSCP of packets sent from socket `fd`, the lower 2 bits (ECN) of ToS / Traffic Class are kept as 0
+fn set_dscp(fd: RawFd, is_ipv6: bool, dscp: u8) -> io::Result<()> {
+    if dscp > 0x3F {
+        return Err(Error::new(ErrorKind::InvalidInput, "DSCP must be in range 0 - 63"));
+    }
+
+    let tos = (dscp << 2) as libc::c_int;
+    let (level, name) = if is_ipv6 {
+        (libc::IPPROTO_IPV6, libc::IPV6_TCLASS)
+    } else {
+        (libc::IPPROTO_IP, libc::IP_TOS)
+    };
+
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            &tos as *const _ as *const _,
+            mem::size_of_val(&tos) as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(())
+}
+
 /// Create a `UdpSocket` binded to `addr`
 #[inline(always)]
 pub async fn create_inbound_udp_socket(addr: &SocketAddr) -> io::Result<UdpSocket> {
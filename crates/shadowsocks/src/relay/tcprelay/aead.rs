@@ -43,7 +43,7 @@ use std::{
 use byte_string::ByteStr;
 use bytes::{BufMut, Bytes, BytesMut};
 use futures::ready;
-use log::trace;
+use log::{trace, warn};
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 
 use crate::{
@@ -54,6 +54,9 @@ use crate::{
 /// AEAD packet payload must be smaller than 0x3FFF
 pub const MAX_PACKET_SIZE: usize = 0x3FFF;
 
+/// Number of chunks to be received before checking their average size
+const MIN_AVG_CHUNK_SIZE_CHECK_COUNT: u64 = 4096;
+
 enum DecryptReadState {
     WaitSalt { key: Bytes },
     ReadLength,
@@ -67,6 +70,8 @@ pub struct DecryptedReader {
     cipher: Option<Cipher>,
     buffer: BytesMut,
     method: CipherKind,
+    chunk_count: u64,
+    chunk_bytes: u64,
 }
 
 impl DecryptedReader {
@@ -79,6 +84,8 @@ impl DecryptedReader {
                 cipher: None,
                 buffer: BytesMut::with_capacity(method.salt_len()),
                 method,
+                chunk_count: 0,
+                chunk_bytes: 0,
             }
         } else {
             DecryptedReader {
@@ -86,6 +93,8 @@ impl DecryptedReader {
                 cipher: Some(Cipher::new(method, key, &[])),
                 buffer: BytesMut::with_capacity(2 + method.tag_len()),
                 method,
+                chunk_count: 0,
+                chunk_bytes: 0,
             }
         }
     }
@@ -123,6 +132,7 @@ impl DecryptedReader {
                 },
                 DecryptReadState::ReadData { length } => {
                     ready!(self.poll_read_data(cx, stream, length))?;
+                    self.check_chunk_size(context, length)?;
 
                     self.state = DecryptReadState::BufferedData { pos: 0 };
                 }
@@ -225,6 +235,30 @@ impl DecryptedReader {
         Ok(()).into()
     }
 
+    fn check_chunk_size(&mut self, context: &Context, length: usize) -> io::Result<()> {
+        let min_avg_chunk_size = context.min_avg_chunk_size() as u64;
+        if min_avg_chunk_size == 0 {
+            return Ok(());
+        }
+
+        self.chunk_count += 1;
+        self.chunk_bytes += length as u64;
+
+        if self.chunk_count >= MIN_AVG_CHUNK_SIZE_CHECK_COUNT
+            && self.chunk_bytes < min_avg_chunk_size * self.chunk_count
+        {
+            warn!(
+                "AEAD chunk flooding detected, received {} chunks with {} bytes, minimum average chunk size {} bytes",
+                self.chunk_count, self.chunk_bytes, min_avg_chunk_size
+            );
+
+            let err = io::Error::new(ErrorKind::Other, "too many small AEAD chunks");
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
     fn poll_read_exact<S>(&mut self, cx: &mut task::Context<'_>, stream: &mut S, size: usize) -> Poll<io::Result<usize>>
     where
         S: AsyncRead + Unpin + ?Sized,
@@ -370,3 +404,84 @@ impl EncryptedWriter {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use futures::future;
+
+    use super::*;
+    use crate::config::ServerType;
+
+    /// Send `count` chunks of `chunk_size` bytes, and then read them back with `min_avg_chunk_size`
+    async fn relay_chunks(min_avg_chunk_size: usize, chunk_size: usize, count: usize) -> io::Result<usize> {
+        let method = CipherKind::AES_128_GCM;
+        let key = [0u8; 16];
+        let salt = [1u8; 16];
+
+        let mut encrypted = Vec::new();
+        let mut writer = EncryptedWriter::new(method, &key, &salt);
+        let chunk = vec![0u8; chunk_size];
+        for _ in 0..count {
+            future::poll_fn(|cx| writer.poll_write_encrypted(cx, &mut encrypted, &chunk)).await?;
+        }
+
+        let mut context = Context::new(ServerType::Server);
+        context.set_min_avg_chunk_size(min_avg_chunk_size);
+
+        let mut reader = DecryptedReader::new(method, &key);
+        let mut stream = encrypted.as_slice();
+        let mut buffer = [0u8; 1024];
+        let mut total = 0;
+        loop {
+            let mut read_buf = ReadBuf::new(&mut buffer);
+            future::poll_fn(|cx| reader.poll_read_decrypted(cx, &context, &mut stream, &mut read_buf)).await?;
+
+            let n = read_buf.filled().len();
+            if n == 0 {
+                break;
+            }
+            total += n;
+        }
+
+        Ok(total)
+    }
+
+    #[tokio::test]
+    async fn min_avg_chunk_size_default() {
+        let default = Context::new(ServerType::Server).min_avg_chunk_size();
+        assert_eq!(default, 2);
+        assert_eq!(Context::new(ServerType::Local).min_avg_chunk_size(), 0);
+
+        // Flooding is dropped by servers with the default
+        let err = relay_chunks(default, 1, 5000).await.unwrap_err();
+        assert_eq!(err.to_string(), "too many small AEAD chunks");
+
+        // Small chunks of interactive streams are kept
+        let n = relay_chunks(default, 4, 5000).await.unwrap();
+        assert_eq!(n, 4 * 5000);
+    }
+
+    #[tokio::test]
+    async fn min_avg_chunk_size_disabled() {
+        let n = relay_chunks(0, 1, 5000).await.unwrap();
+        assert_eq!(n, 5000);
+    }
+
+    #[tokio::test]
+    async fn min_avg_chunk_size_flooding() {
+        let err = relay_chunks(4, 1, 5000).await.unwrap_err();
+        assert_eq!(err.to_string(), "too many small AEAD chunks");
+
+        // Not checked before enough chunks have been received
+        let n = relay_chunks(4, 1, MIN_AVG_CHUNK_SIZE_CHECK_COUNT as usize - 1)
+            .await
+            .unwrap();
+        assert_eq!(n, MIN_AVG_CHUNK_SIZE_CHECK_COUNT as usize - 1);
+    }
+
+    #[tokio::test]
+    async fn min_avg_chunk_size_large_chunks() {
+        let n = relay_chunks(4, 16, 5000).await.unwrap();
+        assert_eq!(n, 16 * 5000);
+    }
+}
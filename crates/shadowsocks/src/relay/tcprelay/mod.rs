@@ -2,7 +2,7 @@
 
 pub use self::{
     proxy_listener::ProxyListener,
-    proxy_stream::{ProxyClientStream, ProxyServerStream},
+    proxy_stream::{connect_proxied, ProxiedStream, ProxyClientStream, ProxyServerStream},
 };
 
 mod aead;
@@ -17,8 +17,8 @@ use tokio::{
 };
 
 use crate::{
-    config::ServerConfig,
-    context::SharedContext,
+    config::{ServerConfig, ServerType},
+    context::{Context, SharedContext},
     net::{ConnectOpts, TcpStream as OutboundTcpStream},
     relay::{
         socks5::Address,
@@ -295,3 +295,17 @@ where
         self.writer.poll_shutdown(cx)
     }
 }
+
+/// A connected stream to the target through shadowsocks' proxy server, returned by `connect_proxied`
+pub type ProxiedStream = ProxyClientStream<TcpStream>;
+
+/// Connect to `target` via shadowsocks' server configured by `svr_cfg`
+///
+/// The returned stream is ready to send / receive plain data to / from the target, data will be encrypted
+/// and decrypted transparently. All streams created by this function share one client context.
+pub async fn connect_proxied(svr_cfg: &ServerConfig, target: &Address) -> io::Result<ProxiedStream> {
+    lazy_static! {
+        static ref DEFAULT_CONTEXT: SharedContext = Context::new_shared(ServerType::Local);
+    }
+    ProxyClientStream::connect(DEFAULT_CONTEXT.clone(), svr_cfg, target.clone()).await
+}
@@ -1,7 +1,13 @@
 //! Stream interface for communicating with shadowsocks proxy servers
 
 pub use self::{
-    client::{ProxyClientStream, ProxyClientStreamReadHalf, ProxyClientStreamWriteHalf},
+    client::{
+        connect_proxied,
+        ProxiedStream,
+        ProxyClientStream,
+        ProxyClientStreamReadHalf,
+        ProxyClientStreamWriteHalf,
+    },
     server::{ProxyServerStream, ProxyServerStreamReadHalf, ProxyServerStreamWriteHalf},
 };
 
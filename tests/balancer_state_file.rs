@@ -0,0 +1,117 @@
+#![cfg(feature = "local")]
+
+use std::{env, fs, process};
+
+use tokio::time::{self, Duration};
+
+use shadowsocks_service::{
+    config::{Config, ConfigType},
+    local::loadbalancing::{server_stat::Score, BalancerState, ServerScore, ServerType},
+    run_local,
+};
+
+#[tokio::test]
+async fn balancer_state_file_saved() {
+    let _ = env_logger::try_init();
+
+    let path = env::temp_dir().join(format!("ss-balancer-state-saved-{}", process::id()));
+    let _ = fs::remove_file(&path);
+
+    // Neither of the servers is running, probes fail
+    let config = Config::load_from_str(
+        &format!(
+            r#"{{
+                "local_port": 8320,
+                "local_address": "127.0.0.1",
+                "servers": [
+                    {{ "server": "127.0.0.1", "server_port": 8220, "password": "password", "method": "aes-256-gcm" }},
+                    {{ "server": "127.0.0.1", "server_port": 8221, "password": "password", "method": "aes-256-gcm" }}
+                ],
+                "balancer_state_file": "{}"
+            }}"#,
+            path.display()
+        ),
+        ConfigType::Local,
+    )
+    .unwrap();
+    tokio::spawn(run_local(config));
+
+    // Saved after the first check
+    let mut state = None;
+    for _ in 0..50 {
+        time::sleep(Duration::from_millis(200)).await;
+        if let Ok(s) = BalancerState::load_from_file(&path) {
+            if s.scores(ServerType::Tcp, "127.0.0.1:8221").is_some() {
+                state = Some(s);
+                break;
+            }
+        }
+    }
+    let state = state.expect("balancer state is saved");
+
+    for addr in &["127.0.0.1:8220", "127.0.0.1:8221"] {
+        let scores = state.scores(ServerType::Tcp, addr).unwrap();
+        assert!(scores.contains(&Score::Errored), "{} {:?}", addr, scores);
+    }
+    // Not probed in tcp_only mode
+    assert!(state.scores(ServerType::Udp, "127.0.0.1:8220").is_none());
+
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn balancer_state_file_roundtrip() {
+    let path = env::temp_dir().join(format!("ss-balancer-state-{}", process::id()));
+
+    // Missing file is an empty state
+    let _ = fs::remove_file(&path);
+    let state = BalancerState::load_from_file(&path).unwrap();
+    assert!(state.scores(ServerType::Tcp, "127.0.0.1:8220").is_none());
+
+    let tcp_scores = vec![Score::Latency(120), Score::Errored, Score::Latency(98)];
+    let udp_scores = vec![Score::Errored];
+
+    let mut state = BalancerState::new();
+    state.set_scores(ServerType::Tcp, "127.0.0.1:8220".to_owned(), tcp_scores.clone());
+    state.set_scores(ServerType::Udp, "example.com:8220".to_owned(), udp_scores.clone());
+    state.save_to_file(&path).unwrap();
+
+    let mut content = fs::read_to_string(&path).unwrap();
+    content.push_str("# comment\nquic 127.0.0.1:8221 100\ntcp 127.0.0.1:8222 fast\n");
+    fs::write(&path, content).unwrap();
+
+    // Malformed lines are ignored
+    let state = BalancerState::load_from_file(&path).unwrap();
+    assert_eq!(state.scores(ServerType::Tcp, "127.0.0.1:8220"), Some(&tcp_scores[..]));
+    assert_eq!(state.scores(ServerType::Udp, "example.com:8220"), Some(&udp_scores[..]));
+    assert!(state.scores(ServerType::Udp, "127.0.0.1:8220").is_none());
+    assert!(state.scores(ServerType::Tcp, "127.0.0.1:8222").is_none());
+
+    let _ = fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn balancer_state_seed_scores() {
+    let scores = [
+        Score::Latency(120),
+        Score::Latency(140),
+        Score::Errored,
+        Score::Latency(98),
+    ];
+
+    let probed = ServerScore::new();
+    for score in scores.iter() {
+        probed.push_score(*score).await;
+    }
+
+    // Seeded server is scored as if it had been probed
+    let seeded = ServerScore::new();
+    assert_eq!(seeded.seed_scores(&scores).await, probed.score());
+    assert_eq!(seeded.recent_scores().await, scores.to_vec());
+    assert!(seeded.is_healthy());
+
+    // Latest seeded probe failed
+    let seeded = ServerScore::new();
+    seeded.seed_scores(&[Score::Latency(120), Score::Errored]).await;
+    assert!(!seeded.is_healthy());
+}
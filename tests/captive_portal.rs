@@ -0,0 +1,149 @@
+#![cfg(feature = "local")]
+
+use std::{sync::Arc, time::Duration};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    time,
+};
+
+use shadowsocks_service::{
+    config::{CaptivePortalConfig, Config, ConfigType},
+    local::{
+        captive_portal::{captive_portal_task, check_captive_portal},
+        context::ServiceContext,
+    },
+};
+
+fn local_config(captive_portal_check: &str) -> Result<Config, shadowsocks_service::config::Error> {
+    Config::load_from_str(
+        &format!(
+            r#"{{
+                "local_address": "127.0.0.1",
+                "local_port": 8310,
+                "server": "127.0.0.1",
+                "server_port": 8210,
+                "password": "password",
+                "method": "aes-256-gcm",
+                "captive_portal_check": {}
+            }}"#,
+            captive_portal_check
+        ),
+        ConfigType::Local,
+    )
+}
+
+#[test]
+fn captive_portal_config() {
+    let config =
+        local_config(r#"{ "url": "http://connectivitycheck.gstatic.com/generate_204", "interval": 60 }"#).unwrap();
+    let check = config.captive_portal_check.as_ref().unwrap();
+    assert_eq!(check.interval, Duration::from_secs(60));
+    assert_eq!(check.threshold, 2);
+    assert_eq!(
+        check.split_url(),
+        Some(("connectivitycheck.gstatic.com", 80, "/generate_204"))
+    );
+
+    assert!(local_config(r#"{ "url": "http://example.com/", "interval": 0 }"#).is_err());
+    assert!(local_config(r#"{ "url": "http://example.com/", "interval": 60, "threshold": 0 }"#).is_err());
+    assert!(local_config(r#"{ "url": "https://example.com/", "interval": 60 }"#).is_err());
+
+    let split = |url: &str| {
+        CaptivePortalConfig {
+            url: url.to_owned(),
+            interval: Duration::from_secs(60),
+            threshold: 2,
+        }
+        .split_url()
+        .map(|(h, p, path)| (h.to_owned(), p, path.to_owned()))
+    };
+    assert_eq!(
+        split("http://127.0.0.1:8080"),
+        Some(("127.0.0.1".to_owned(), 8080, "/".to_owned()))
+    );
+    assert_eq!(
+        split("http://[::1]:8080/204"),
+        Some(("::1".to_owned(), 8080, "/204".to_owned()))
+    );
+}
+
+async fn portal_server(responses: Vec<&'static str>) -> CaptivePortalConfig {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        for response in responses {
+            let (mut stream, ..) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 1024];
+            let _ = stream.read(&mut buf).await;
+            let _ = stream.write_all(response.as_bytes()).await;
+        }
+    });
+
+    CaptivePortalConfig {
+        url: format!("http://{}/generate_204", addr),
+        interval: Duration::from_secs(1),
+        threshold: 2,
+    }
+}
+
+#[tokio::test]
+async fn captive_portal_detect() {
+    let config = portal_server(vec![
+        "HTTP/1.1 302 Found\r\nLocation: http://portal.example.com/login\r\nContent-Length: 0\r\n\r\n",
+        "HTTP/1.1 204 No Content\r\n\r\n",
+    ])
+    .await;
+
+    let context = ServiceContext::new();
+    assert!(check_captive_portal(&context, &config).await.unwrap());
+    assert!(!check_captive_portal(&context, &config).await.unwrap());
+}
+
+#[tokio::test]
+async fn captive_portal_detect_200() {
+    let config = portal_server(vec![
+        // Portal's login page
+        "HTTP/1.1 200 OK\r\nContent-Length: 13\r\n\r\n<html></html>",
+        "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nd\r\n<html></html>\r\n0\r\n\r\n",
+        "HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n<html></html>",
+        // Empty responses are not portals
+        "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n",
+        "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+        "HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n",
+        // Errors of the check server are not portals
+        "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 5\r\n\r\nbusy!",
+    ])
+    .await;
+
+    let context = ServiceContext::new();
+    for _ in 0..3 {
+        assert!(check_captive_portal(&context, &config).await.unwrap());
+    }
+    for _ in 0..4 {
+        assert!(!check_captive_portal(&context, &config).await.unwrap());
+    }
+}
+
+#[tokio::test]
+async fn captive_portal_threshold() {
+    const REDIRECT: &str =
+        "HTTP/1.1 302 Found\r\nLocation: http://portal.example.com/login\r\nContent-Length: 0\r\n\r\n";
+    let config = portal_server(vec![REDIRECT, REDIRECT, "HTTP/1.1 204 No Content\r\n\r\n"]).await;
+
+    let context = Arc::new(ServiceContext::new());
+    tokio::spawn(captive_portal_task(context.clone(), config));
+
+    // Detected once
+    time::sleep(Duration::from_millis(500)).await;
+    assert!(!context.captive_portal_detected());
+
+    // Detected twice
+    time::sleep(Duration::from_secs(1)).await;
+    assert!(context.captive_portal_detected());
+
+    // Cleared on the first check without portal
+    time::sleep(Duration::from_secs(1)).await;
+    assert!(!context.captive_portal_detected());
+}
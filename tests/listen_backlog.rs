@@ -0,0 +1,67 @@
+use std::net::SocketAddr;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream as TokioTcpStream,
+};
+
+use shadowsocks_service::shadowsocks::net::{
+    report::{report_accept_opts, report_listen_backlog},
+    AcceptOpts,
+    TcpListener,
+};
+
+#[tokio::test]
+async fn listen_backlog_bind() {
+    let mut accept_opts = AcceptOpts::default();
+    accept_opts.backlog = Some(16);
+
+    let addr = "127.0.0.1:0".parse::<SocketAddr>().unwrap();
+    let listener = TcpListener::bind_with_opts(&addr, accept_opts).await.unwrap();
+    let local_addr = listener.local_addr().unwrap();
+
+    let mut client = TokioTcpStream::connect(local_addr).await.unwrap();
+    let (mut stream, ..) = listener.accept().await.unwrap();
+
+    client.write_all(b"hello").await.unwrap();
+    let mut buf = [0u8; 5];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+}
+
+#[tokio::test]
+async fn listen_backlog_report() {
+    let mut accept_opts = AcceptOpts::default();
+    // Far beyond any system's cap
+    accept_opts.backlog = Some(1 << 30);
+    accept_opts.tcp.send_buffer_size = Some(65536);
+
+    let addr = "127.0.0.1:0".parse::<SocketAddr>().unwrap();
+    let listener = TcpListener::bind_with_opts(&addr, accept_opts.clone()).await.unwrap();
+    let local_addr = listener.local_addr().unwrap();
+
+    let _client = TokioTcpStream::connect(local_addr).await.unwrap();
+    let (stream, ..) = listener.accept().await.unwrap();
+
+    // Options are read back from the accepted connection, backlog is reported when the listener is created
+    let reports = report_accept_opts(&stream, &accept_opts);
+    assert_eq!(reports.len(), 1);
+
+    assert_eq!(reports[0].name, "SO_SNDBUF");
+    assert_eq!(reports[0].requested, "65536");
+    // Doubled value reported by Linux is normalized
+    #[cfg(target_os = "linux")]
+    assert!(reports[0].is_effective(), "{}", reports[0]);
+
+    let report = report_listen_backlog(1 << 30);
+    assert_eq!(report.name, "listen backlog");
+    assert_eq!(report.requested, (1i32 << 30).to_string());
+    #[cfg(target_os = "linux")]
+    assert!(!report.is_effective());
+
+    // Negative backlog is capped by the system as well
+    #[cfg(target_os = "linux")]
+    assert_eq!(report_listen_backlog(-1).effective, report.effective);
+
+    assert!(report_accept_opts(&stream, &AcceptOpts::default()).is_empty());
+}
@@ -0,0 +1,86 @@
+#![cfg(feature = "local")]
+
+use tokio::{
+    net::TcpStream,
+    time::{self, Duration},
+};
+
+use shadowsocks_service::{
+    config::{Config, ConfigType, ProtocolType},
+    run_local,
+    shadowsocks::ServerAddr,
+};
+
+// TEST-NET-1, never assigned to local interfaces
+const UNAVAILABLE_ADDR: &str = "192.0.2.1";
+
+fn local_config(local_address: &str) -> Result<Config, shadowsocks_service::config::Error> {
+    Config::load_from_str(
+        &format!(
+            r#"{{
+                "local_address": {},
+                "local_port": 8300,
+                "server": "127.0.0.1",
+                "server_port": 8200,
+                "password": "password",
+                "method": "aes-256-gcm"
+            }}"#,
+            local_address
+        ),
+        ConfigType::Local,
+    )
+}
+
+#[test]
+fn local_addr_fallback_config() {
+    let config = local_config(r#""127.0.0.1""#).unwrap();
+    assert_eq!(config.local_addr, Some("127.0.0.1:8300".parse().unwrap()));
+    assert!(config.local_addr_fallback.is_empty());
+
+    let config = local_config(&format!(r#"["{}", "::1", "127.0.0.1"]"#, UNAVAILABLE_ADDR)).unwrap();
+    assert_eq!(
+        config.local_addr,
+        Some(format!("{}:8300", UNAVAILABLE_ADDR).parse().unwrap())
+    );
+    assert_eq!(
+        config.local_addr_fallback,
+        vec![
+            "[::1]:8300".parse::<ServerAddr>().unwrap(),
+            "127.0.0.1:8300".parse::<ServerAddr>().unwrap(),
+        ]
+    );
+
+    assert!(local_config("[]").is_err());
+
+    // Fallbacks share the port of the local address
+    let mut config = local_config(r#""127.0.0.1""#).unwrap();
+    config.local_addr_fallback = vec!["127.0.0.1:8301".parse().unwrap()];
+    assert!(config.check_integrity().is_err());
+
+    // Servers have only one outbound address
+    let config = Config::load_from_str(
+        r#"{
+            "local_address": ["127.0.0.1", "::1"],
+            "server": "127.0.0.1",
+            "server_port": 8200,
+            "password": "password",
+            "method": "aes-256-gcm"
+        }"#,
+        ConfigType::Server,
+    );
+    assert!(config.is_err());
+}
+
+#[tokio::test]
+async fn local_addr_fallback_bind() {
+    let _ = env_logger::try_init();
+
+    let mut config = local_config(&format!(r#"["{}", "127.0.0.1"]"#, UNAVAILABLE_ADDR)).unwrap();
+    config.local_protocol = ProtocolType::Socks;
+    tokio::spawn(run_local(config));
+
+    time::sleep(Duration::from_secs(1)).await;
+
+    // Listening on the fallback address
+    TcpStream::connect("127.0.0.1:8300").await.unwrap();
+}
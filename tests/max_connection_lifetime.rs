@@ -0,0 +1,123 @@
+#![cfg(all(feature = "local", feature = "server"))]
+
+use std::net::SocketAddr;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    time::{self, Duration},
+};
+
+use shadowsocks_service::{
+    config::{Config, ConfigType, ProtocolType},
+    local::socks::client::socks5::Socks5TcpClient,
+    run_local,
+    run_server,
+    shadowsocks::relay::socks5::Address,
+};
+
+#[test]
+fn max_connection_lifetime_invalid() {
+    let config = Config::load_from_str(
+        r#"{
+            "server": "127.0.0.1",
+            "server_port": 8352,
+            "password": "password",
+            "method": "aes-256-gcm",
+            "max_connection_lifetime": 0
+        }"#,
+        ConfigType::Server,
+    );
+    assert!(config.is_err());
+}
+
+/// Relay an idle connection through servers, returns whether it was closed in `wait`
+async fn relay_idle_connection(server_port: u16, local_port: u16, server_lifetime: bool, wait: Duration) -> bool {
+    // Echo target which never closes the connection by itself
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut stream, ..) = listener.accept().await.unwrap();
+        let mut buf = [0u8; 1024];
+        loop {
+            match stream.read(&mut buf).await {
+                Ok(0) | Err(..) => break,
+                Ok(n) => {
+                    if stream.write_all(&buf[..n]).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+
+    let lifetime = r#", "max_connection_lifetime": 1"#;
+
+    let server_config = Config::load_from_str(
+        &format!(
+            r#"{{
+                "server": "127.0.0.1",
+                "server_port": {},
+                "password": "password",
+                "method": "aes-256-gcm"{}
+            }}"#,
+            server_port,
+            if server_lifetime { lifetime } else { "" }
+        ),
+        ConfigType::Server,
+    )
+    .unwrap();
+
+    let mut local_config = Config::load_from_str(
+        &format!(
+            r#"{{
+                "local_port": {},
+                "local_address": "127.0.0.1",
+                "server": "127.0.0.1",
+                "server_port": {},
+                "password": "password",
+                "method": "aes-256-gcm"{}
+            }}"#,
+            local_port,
+            server_port,
+            if server_lifetime { "" } else { lifetime }
+        ),
+        ConfigType::Local,
+    )
+    .unwrap();
+    local_config.local_protocol = ProtocolType::Socks;
+
+    tokio::spawn(run_server(server_config));
+    tokio::spawn(run_local(local_config));
+
+    time::sleep(Duration::from_secs(1)).await;
+
+    let local_addr: SocketAddr = format!("127.0.0.1:{}", local_port).parse().unwrap();
+    let mut c = Socks5TcpClient::connect(Address::SocketAddress(target_addr), local_addr)
+        .await
+        .unwrap();
+
+    c.write_all(b"hello").await.unwrap();
+    c.flush().await.unwrap();
+
+    let mut buf = [0u8; 5];
+    c.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+
+    // Closed gracefully, not reset
+    matches!(time::timeout(wait, c.read(&mut buf)).await, Ok(Ok(0)))
+}
+
+#[tokio::test]
+async fn max_connection_lifetime_local() {
+    let _ = env_logger::try_init();
+
+    assert!(relay_idle_connection(8352, 8350, false, Duration::from_secs(5)).await);
+}
+
+#[tokio::test]
+async fn max_connection_lifetime_server() {
+    let _ = env_logger::try_init();
+
+    assert!(relay_idle_connection(8353, 8351, true, Duration::from_secs(5)).await);
+}
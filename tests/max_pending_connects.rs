@@ -0,0 +1,144 @@
+#![cfg(feature = "local")]
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use futures::future;
+use tokio::{
+    net::{TcpListener, TcpSocket},
+    time::{self, Duration},
+};
+
+use shadowsocks_service::{
+    config::{Config, ConfigType},
+    local::{context::ServiceContext, net::AutoProxyClientStream},
+};
+
+#[test]
+fn max_pending_connects_invalid() {
+    let config = Config::load_from_str(
+        r#"{
+            "local_port": 8280,
+            "local_address": "127.0.0.1",
+            "server": "127.0.0.1",
+            "server_port": 8180,
+            "password": "password",
+            "method": "aes-256-gcm",
+            "max_pending_connects": 0
+        }"#,
+        ConfigType::Local,
+    );
+    assert!(config.is_err());
+}
+
+async fn target_listener() -> (TcpListener, std::net::SocketAddr) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    (listener, addr)
+}
+
+#[tokio::test]
+async fn max_pending_connects_queued() {
+    let (_listener, target_addr) = target_listener().await;
+
+    let mut context = ServiceContext::new();
+    context.set_max_pending_connects(2);
+    let context = Arc::new(context);
+
+    // Occupy all the permits, as if there were 2 connects pending
+    let permits = context.connect_permits().unwrap();
+    let p1 = permits.clone().acquire_owned().await.unwrap();
+    let p2 = permits.clone().acquire_owned().await.unwrap();
+
+    let mut connect = tokio::spawn(AutoProxyClientStream::connect_bypassed(context.clone(), target_addr));
+    assert!(time::timeout(Duration::from_millis(200), &mut connect).await.is_err());
+
+    // One pending connect finished
+    drop(p1);
+    let stream = time::timeout(Duration::from_secs(5), connect).await.unwrap().unwrap();
+    assert!(stream.is_ok());
+
+    // Permit is released after connected, established connections are not counted
+    assert_eq!(permits.available_permits(), 1);
+    drop(stream);
+    drop(p2);
+    assert_eq!(permits.available_permits(), 2);
+}
+
+/// Count outbound TCP sockets to `port` in `SYN_SENT` state, which are connects still in progress
+#[cfg(target_os = "linux")]
+fn syn_sent_count(port: u16) -> usize {
+    const TCP_SYN_SENT: &str = "02";
+
+    let remote_port = format!(":{:04X}", port);
+    let mut count = 0;
+    for table in &["/proc/net/tcp", "/proc/net/tcp6"] {
+        let content = std::fs::read_to_string(table).unwrap_or_default();
+        for line in content.lines().skip(1) {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            if fields.len() > 3 && fields[2].ends_with(&remote_port) && fields[3] == TCP_SYN_SENT {
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn max_pending_connects_bounded() {
+    const MAX_PENDING_CONNECTS: usize = 4;
+    const CONNECTIONS: usize = 16;
+
+    // Target with the smallest accept queue, which doesn't accept in the beginning.
+    // SYNs are dropped when its accept queue is full, so connects stay pending until it starts accepting.
+    let socket = TcpSocket::new_v4().unwrap();
+    socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let listener = socket.listen(0).unwrap();
+    let target_addr = listener.local_addr().unwrap();
+
+    let mut context = ServiceContext::new();
+    context.set_max_pending_connects(MAX_PENDING_CONNECTS);
+    let context = Arc::new(context);
+
+    // Sample the number of connects in progress, observed by the system
+    let max_pending = Arc::new(AtomicUsize::new(0));
+    let sampler = {
+        let max_pending = max_pending.clone();
+        tokio::spawn(async move {
+            loop {
+                max_pending.fetch_max(syn_sent_count(target_addr.port()), Ordering::Relaxed);
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+    };
+
+    let target = tokio::spawn(async move {
+        time::sleep(Duration::from_millis(500)).await;
+
+        let mut accepted = Vec::new();
+        while let Ok((stream, ..)) = listener.accept().await {
+            accepted.push(stream);
+        }
+    });
+
+    let connects = (0..CONNECTIONS).map(|_| AutoProxyClientStream::connect_bypassed(context.clone(), target_addr));
+    let streams = time::timeout(Duration::from_secs(30), future::join_all(connects))
+        .await
+        .unwrap();
+    sampler.abort();
+    target.abort();
+
+    // All connections are established eventually, with the others queued
+    assert!(streams.iter().all(Result::is_ok));
+
+    let max_pending = max_pending.load(Ordering::Relaxed);
+    assert!(max_pending > 0, "no pending connect has been observed");
+    assert!(
+        max_pending <= MAX_PENDING_CONNECTS,
+        "{} connects were pending at the same time",
+        max_pending
+    );
+}
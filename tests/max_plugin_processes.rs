@@ -0,0 +1,94 @@
+#![cfg(unix)]
+
+use shadowsocks_service::{
+    config::{Config, ConfigType},
+    plugin::PluginRegistry,
+    shadowsocks::{
+        plugin::{PluginConfig, PluginMode},
+        ServerAddr,
+    },
+};
+
+fn sleep_plugin(secs: &str) -> PluginConfig {
+    PluginConfig {
+        plugin: "sleep".to_owned(),
+        plugin_opts: None,
+        plugin_args: vec![secs.to_owned()],
+    }
+}
+
+#[test]
+fn max_plugin_processes_invalid() {
+    let config = Config::load_from_str(
+        r#"{
+            "server": "127.0.0.1",
+            "server_port": 8230,
+            "password": "password",
+            "method": "aes-256-gcm",
+            "max_plugin_processes": 0
+        }"#,
+        ConfigType::Server,
+    );
+    assert!(config.is_err());
+}
+
+#[tokio::test]
+async fn max_plugin_processes_limited() {
+    let registry = PluginRegistry::new(Some(2));
+    let server_addr = "127.0.0.1:8230".parse::<ServerAddr>().unwrap();
+
+    let p1 = registry
+        .start(&sleep_plugin("30"), &server_addr, PluginMode::Server)
+        .unwrap();
+    let p2 = registry
+        .start(&sleep_plugin("30"), &server_addr, PluginMode::Server)
+        .unwrap();
+    assert_eq!(registry.running_processes(), 2);
+
+    let err = match registry.start(&sleep_plugin("30"), &server_addr, PluginMode::Server) {
+        Ok(..) => panic!("started plugin exceeding max_plugin_processes"),
+        Err(err) => err,
+    };
+    assert!(err.to_string().contains("max_plugin_processes 2"));
+    assert_eq!(registry.running_processes(), 2);
+
+    // Released after the process is terminated
+    drop(p1);
+    assert_eq!(registry.running_processes(), 1);
+    let _p3 = registry
+        .start(&sleep_plugin("30"), &server_addr, PluginMode::Server)
+        .unwrap();
+    drop(p2);
+}
+
+#[tokio::test]
+async fn max_plugin_processes_shared() {
+    let registry = PluginRegistry::new(Some(1));
+    let server_addr = "127.0.0.1:8231".parse::<ServerAddr>().unwrap();
+
+    let (addr, plugin) = registry
+        .start_shared(&sleep_plugin("30"), &server_addr, PluginMode::Client)
+        .unwrap();
+    let plugin = plugin.unwrap();
+    assert_eq!(addr, plugin.local_addr());
+
+    // Same address and plugin configuration
+    let (shared_addr, shared) = registry
+        .start_shared(&sleep_plugin("30"), &server_addr, PluginMode::Client)
+        .unwrap();
+    assert_eq!(shared_addr, addr);
+    assert!(shared.is_none());
+    assert_eq!(registry.running_processes(), 1);
+
+    // Different plugin configuration needs another process
+    assert!(registry
+        .start_shared(&sleep_plugin("31"), &server_addr, PluginMode::Client)
+        .is_err());
+
+    // Not shared after the process is terminated
+    drop(plugin);
+    let (.., plugin) = registry
+        .start_shared(&sleep_plugin("30"), &server_addr, PluginMode::Client)
+        .unwrap();
+    assert!(plugin.is_some());
+}
@@ -0,0 +1,97 @@
+#![cfg(unix)]
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use shadowsocks_service::{
+    config::{Config, ConfigType},
+    plugin::PluginRegistry,
+    shadowsocks::{
+        plugin::{PluginConfig, PluginMode},
+        ServerAddr,
+    },
+};
+
+struct CaptureLogger {
+    lines: Arc<Mutex<Vec<(Level, String)>>>,
+}
+
+impl Log for CaptureLogger {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        if record.target().starts_with("shadowsocks::plugin") {
+            let mut lines = self.lines.lock().unwrap();
+            lines.push((record.level(), record.args().to_string()));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+#[test]
+fn plugin_log_level_invalid() {
+    let config = Config::load_from_str(
+        r#"{
+            "server": "127.0.0.1",
+            "server_port": 8232,
+            "password": "password",
+            "method": "aes-256-gcm",
+            "plugin_log_level": "loud"
+        }"#,
+        ConfigType::Server,
+    );
+    assert!(config.is_err());
+}
+
+#[tokio::test]
+async fn plugin_log_level_stderr() {
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let logger = Box::leak(Box::new(CaptureLogger { lines: lines.clone() }));
+    log::set_logger(logger).unwrap();
+    log::set_max_level(LevelFilter::Trace);
+
+    let mut registry = PluginRegistry::new(None);
+    registry.set_log_level(Level::Warn);
+
+    // Writes more lines than the rate limit at once
+    let c = PluginConfig {
+        plugin: "sh".to_owned(),
+        plugin_opts: None,
+        plugin_args: vec![
+            "-c".to_owned(),
+            "i=0; while [ $i -lt 100 ]; do echo \"line $i\" >&2; i=$((i + 1)); done".to_owned(),
+        ],
+    };
+    let remote_addr = "127.0.0.1:8232".parse::<ServerAddr>().unwrap();
+    let plugin = registry.start(&c, &remote_addr, PluginMode::Client).unwrap();
+    plugin.join().await.unwrap();
+
+    let mut suppressed = false;
+    for _ in 0..30 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        suppressed = lines
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(_, l)| l.ends_with("50 lines of stderr suppressed"));
+        if suppressed {
+            break;
+        }
+    }
+    assert!(suppressed);
+
+    let lines = lines.lock().unwrap();
+    let plugin_lines = lines
+        .iter()
+        .filter(|(_, l)| l.starts_with("plugin \"sh\" (server 127.0.0.1:8232): line "))
+        .collect::<Vec<_>>();
+    assert_eq!(plugin_lines.len(), 50);
+    assert!(plugin_lines.iter().all(|(level, _)| *level == Level::Warn));
+    assert!(plugin_lines[0].1.ends_with("line 0"));
+}
@@ -0,0 +1,101 @@
+use std::{
+    io::{self, ErrorKind},
+    net::IpAddr,
+    time::Instant,
+};
+
+use tokio::net::TcpListener;
+
+#[cfg(unix)]
+use shadowsocks_service::shadowsocks::net::report::report_connect_opts;
+use shadowsocks_service::shadowsocks::net::{
+    port_exhaustion::{self, PORT_EXHAUSTION_BACKOFF},
+    ConnectOpts,
+    TcpStream,
+};
+
+#[cfg(unix)]
+#[tokio::test]
+async fn outbound_reuse_addr() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let mut opts = ConnectOpts::default();
+    opts.tcp.reuse_addr = true;
+
+    let stream = TcpStream::connect_with_opts(&addr, &opts).await.unwrap();
+    let reports = report_connect_opts(&*stream, false, &opts);
+    let report = reports.iter().find(|r| r.name == "SO_REUSEADDR").unwrap();
+    assert!(report.is_effective(), "{:?}", report);
+
+    // Not reported if not requested
+    let opts = ConnectOpts::default();
+    let stream = TcpStream::connect_with_opts(&addr, &opts).await.unwrap();
+    let reports = report_connect_opts(&*stream, false, &opts);
+    assert!(reports.iter().all(|r| r.name != "SO_REUSEADDR"));
+}
+
+#[tokio::test]
+async fn port_exhaustion_backoff() {
+    assert!(port_exhaustion::is_port_exhausted(&io::Error::from(
+        ErrorKind::AddrNotAvailable
+    )));
+    assert!(!port_exhaustion::is_port_exhausted(&io::Error::from(
+        ErrorKind::ConnectionRefused
+    )));
+
+    let exhausted_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let exhausted_addr = exhausted_listener.local_addr().unwrap();
+    let other_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let other_addr = other_listener.local_addr().unwrap();
+
+    let count = port_exhaustion::port_exhausted_count();
+    port_exhaustion::report_port_exhausted(None, &exhausted_addr);
+    assert_eq!(port_exhaustion::port_exhausted_count(), count + 1);
+
+    let opts = ConnectOpts::default();
+
+    // Connects to the exhausted destination right after exhaustion are delayed
+    let start = Instant::now();
+    port_exhaustion::report_port_exhausted(None, &exhausted_addr);
+    TcpStream::connect_with_opts(&exhausted_addr, &opts).await.unwrap();
+    assert!(start.elapsed() >= PORT_EXHAUSTION_BACKOFF);
+
+    // But not after the backoff period
+    let start = Instant::now();
+    TcpStream::connect_with_opts(&exhausted_addr, &opts).await.unwrap();
+    assert!(start.elapsed() < PORT_EXHAUSTION_BACKOFF);
+
+    // Nor connects to the other destinations
+    let start = Instant::now();
+    port_exhaustion::report_port_exhausted(None, &exhausted_addr);
+    TcpStream::connect_with_opts(&other_addr, &opts).await.unwrap();
+    assert!(start.elapsed() < PORT_EXHAUSTION_BACKOFF);
+}
+
+#[tokio::test]
+async fn port_exhaustion_backoff_bind_addr() {
+    let bind_ip = IpAddr::from([127, 0, 0, 1]);
+
+    let exhausted_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let exhausted_addr = exhausted_listener.local_addr().unwrap();
+    let other_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let other_addr = other_listener.local_addr().unwrap();
+
+    let mut opts = ConnectOpts::default();
+    opts.bind_local_addr = Some(bind_ip);
+
+    // Ports of the bind address are shared by all destinations
+    let start = Instant::now();
+    port_exhaustion::report_port_exhausted(Some(bind_ip), &exhausted_addr);
+    TcpStream::connect_with_opts(&other_addr, &opts).await.unwrap();
+    assert!(start.elapsed() >= PORT_EXHAUSTION_BACKOFF);
+
+    // Unbound sockets are not affected
+    let start = Instant::now();
+    port_exhaustion::report_port_exhausted(Some(bind_ip), &exhausted_addr);
+    TcpStream::connect_with_opts(&exhausted_addr, &ConnectOpts::default())
+        .await
+        .unwrap();
+    assert!(start.elapsed() < PORT_EXHAUSTION_BACKOFF);
+}
@@ -0,0 +1,47 @@
+#![cfg(feature = "server")]
+
+use tokio::{
+    io::AsyncReadExt,
+    net::TcpStream,
+    time::{self, Duration, Instant},
+};
+
+use shadowsocks_service::{
+    config::{Config, ConfigType},
+    run_server,
+    shadowsocks::{config::ServerConfig, crypto::v1::CipherKind},
+};
+
+#[tokio::test]
+async fn tcp_server_silent_for_idle_client() {
+    let _ = env_logger::try_init();
+
+    const SERVER_ADDR: &str = "127.0.0.1:8130";
+    const TIMEOUT: Duration = Duration::from_secs(3);
+
+    let mut svr_cfg = ServerConfig::new(
+        SERVER_ADDR.parse().unwrap(),
+        "test-password".to_owned(),
+        CipherKind::AES_256_GCM,
+    );
+    svr_cfg.set_timeout(TIMEOUT);
+
+    let mut config = Config::new(ConfigType::Server);
+    config.server = vec![svr_cfg];
+    tokio::spawn(run_server(config));
+
+    time::sleep(Duration::from_secs(1)).await;
+
+    let mut c = TcpStream::connect(SERVER_ADDR).await.unwrap();
+    let start = Instant::now();
+
+    // Server must not send any bytes until it is closing the connection
+    let mut buf = [0u8; 1024];
+    let n = time::timeout(TIMEOUT * 2, c.read(&mut buf))
+        .await
+        .expect("server should close idle connection after timeout")
+        .unwrap();
+
+    assert_eq!(n, 0, "server sent {} bytes to an idle client", n);
+    assert!(start.elapsed() >= TIMEOUT - Duration::from_millis(100));
+}
@@ -0,0 +1,84 @@
+#![cfg(feature = "local")]
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use shadowsocks_service::{
+    config::{Config, ConfigType},
+    local::loadbalancing::{ServerAffinity, ServerIdent, ServerType},
+    shadowsocks::{crypto::v1::CipherKind, ServerConfig},
+};
+
+fn server(port: u16) -> Arc<ServerIdent> {
+    let svr_cfg = ServerConfig::new(
+        SocketAddr::from(([127, 0, 0, 1], port)),
+        "password".to_owned(),
+        CipherKind::AES_256_GCM,
+    );
+    Arc::new(ServerIdent::new(svr_cfg))
+}
+
+#[test]
+fn server_affinity_invalid() {
+    let config = Config::load_from_str(
+        r#"{
+            "local_port": 8330,
+            "local_address": "127.0.0.1",
+            "server": "127.0.0.1",
+            "server_port": 8250,
+            "password": "password",
+            "method": "aes-256-gcm",
+            "server_affinity": { "ttl": 0 }
+        }"#,
+        ConfigType::Local,
+    );
+    assert!(config.is_err());
+}
+
+#[tokio::test]
+async fn server_affinity_sticky() {
+    let affinity = ServerAffinity::new(Duration::from_secs(600));
+    let s1 = server(8251);
+    let s2 = server(8252);
+
+    let client1 = "192.0.2.1".parse::<IpAddr>().unwrap();
+    let client2 = "192.0.2.2".parse::<IpAddr>().unwrap();
+
+    // Assigned by the balancer
+    let chosen = affinity.server_for(ServerType::Tcp, client1, || s1.clone());
+    assert!(Arc::ptr_eq(&chosen, &s1));
+    let chosen = affinity.server_for(ServerType::Tcp, client2, || s2.clone());
+    assert!(Arc::ptr_eq(&chosen, &s2));
+
+    // Kept even if the balancer prefers another server now
+    let chosen = affinity.server_for(ServerType::Tcp, client1, || s2.clone());
+    assert!(Arc::ptr_eq(&chosen, &s1));
+
+    // UDP has its own sticky servers
+    let chosen = affinity.server_for(ServerType::Udp, client1, || s2.clone());
+    assert!(Arc::ptr_eq(&chosen, &s2));
+
+    // Reassigned after the sticky server became unhealthy
+    s1.tcp_score().report_failure().await;
+    let chosen = affinity.server_for(ServerType::Tcp, client1, || s2.clone());
+    assert!(Arc::ptr_eq(&chosen, &s2));
+    let chosen = affinity.server_for(ServerType::Tcp, client1, || s1.clone());
+    assert!(Arc::ptr_eq(&chosen, &s2));
+}
+
+#[tokio::test]
+async fn server_affinity_expired() {
+    let affinity = ServerAffinity::new(Duration::from_millis(100));
+    let s1 = server(8251);
+    let s2 = server(8252);
+    let client = "192.0.2.1".parse::<IpAddr>().unwrap();
+
+    affinity.server_for(ServerType::Tcp, client, || s1.clone());
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let chosen = affinity.server_for(ServerType::Tcp, client, || s2.clone());
+    assert!(Arc::ptr_eq(&chosen, &s2));
+}
@@ -7,6 +7,7 @@ use std::{
 
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
     time::{self, Duration},
 };
 
@@ -137,3 +138,59 @@ async fn socks5_relay_aead() {
     let http_status = b"HTTP/1.0 200 OK\r\n";
     buf.starts_with(http_status);
 }
+
+#[tokio::test]
+async fn socks5_truncated_domain_name() {
+    let _ = env_logger::try_init();
+
+    const SERVER_ADDR: &str = "127.0.0.1:8120";
+    const LOCAL_ADDR: &str = "127.0.0.1:8220";
+
+    const PASSWORD: &str = "test-password";
+    const METHOD: CipherKind = CipherKind::AES_256_GCM;
+
+    let svr = Socks5TestServer::new(SERVER_ADDR, LOCAL_ADDR, PASSWORD, METHOD, false);
+    svr.run().await;
+
+    // Domain name claims 200 bytes, but only a few of them will be sent
+    let truncated_req = b"\x05\x01\x00\x03\xc8www.example.com";
+
+    // Client closes the connection before sending the whole domain name
+    {
+        let mut c = TcpStream::connect(svr.client_addr()).await.unwrap();
+
+        c.write_all(b"\x05\x01\x00").await.unwrap();
+        let mut handshake_resp = [0u8; 2];
+        c.read_exact(&mut handshake_resp).await.unwrap();
+        assert_eq!(handshake_resp, [0x05, 0x00]);
+
+        c.write_all(truncated_req).await.unwrap();
+        c.shutdown().await.unwrap();
+
+        let mut reply = [0u8; 2];
+        time::timeout(Duration::from_secs(5), c.read_exact(&mut reply))
+            .await
+            .expect("truncated request header should be replied immediately")
+            .unwrap();
+        assert_eq!(reply, [0x05, 0x01]);
+    }
+
+    // Client keeps the connection open, waiting for the handshake timeout
+    {
+        let mut c = TcpStream::connect(svr.client_addr()).await.unwrap();
+
+        c.write_all(b"\x05\x01\x00").await.unwrap();
+        let mut handshake_resp = [0u8; 2];
+        c.read_exact(&mut handshake_resp).await.unwrap();
+        assert_eq!(handshake_resp, [0x05, 0x00]);
+
+        c.write_all(truncated_req).await.unwrap();
+
+        let mut reply = [0u8; 2];
+        time::timeout(Duration::from_secs(30), c.read_exact(&mut reply))
+            .await
+            .expect("truncated request header should be replied after handshake timeout")
+            .unwrap();
+        assert_eq!(reply, [0x05, 0x01]);
+    }
+}
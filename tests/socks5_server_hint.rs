@@ -0,0 +1,121 @@
+#![cfg(feature = "local")]
+
+use std::net::SocketAddr;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::{self, Duration},
+};
+
+use shadowsocks_service::{
+    config::{Config, ConfigType, ProtocolType},
+    run_local,
+    shadowsocks::{
+        config::{ServerAddr, ServerConfig},
+        crypto::v1::CipherKind,
+        relay::socks5,
+    },
+};
+
+/// Version of the username/password subnegotiation (RFC1929)
+const SOCKS5_PASSWORD_AUTH_VERSION: u8 = 0x01;
+
+async fn start_local(local_port: u16, server_hint: bool) -> SocketAddr {
+    let local_addr = SocketAddr::from(([127, 0, 0, 1], local_port));
+
+    let mut svr_cfg = ServerConfig::new(
+        SocketAddr::from(([127, 0, 0, 1], 8370)),
+        "password".to_owned(),
+        CipherKind::AES_256_GCM,
+    );
+    svr_cfg.set_remarks("tokyo-1");
+
+    let mut config = Config::new(ConfigType::Local);
+    config.local_addr = Some(ServerAddr::from(local_addr));
+    config.local_protocol = ProtocolType::Socks;
+    config.server = vec![svr_cfg];
+    config.socks5_server_hint = server_hint;
+
+    tokio::spawn(run_local(config));
+    time::sleep(Duration::from_secs(1)).await;
+
+    local_addr
+}
+
+/// Send the method selection message, returns the method chosen by the server
+async fn negotiate(stream: &mut TcpStream, methods: &[u8]) -> u8 {
+    let mut req = vec![0x05, methods.len() as u8];
+    req.extend_from_slice(methods);
+    stream.write_all(&req).await.unwrap();
+
+    let mut resp = [0u8; 2];
+    stream.read_exact(&mut resp).await.unwrap();
+    assert_eq!(resp[0], 0x05);
+    resp[1]
+}
+
+async fn authenticate(stream: &mut TcpStream, uname: &str, passwd: &str) -> u8 {
+    let mut req = vec![SOCKS5_PASSWORD_AUTH_VERSION, uname.len() as u8];
+    req.extend_from_slice(uname.as_bytes());
+    req.push(passwd.len() as u8);
+    req.extend_from_slice(passwd.as_bytes());
+    stream.write_all(&req).await.unwrap();
+
+    let mut resp = [0u8; 2];
+    stream.read_exact(&mut resp).await.unwrap();
+    assert_eq!(resp[0], SOCKS5_PASSWORD_AUTH_VERSION);
+    resp[1]
+}
+
+#[tokio::test]
+async fn socks5_server_hint_none_only() {
+    let _ = env_logger::try_init();
+
+    let local_addr = start_local(8360, true).await;
+
+    let mut stream = TcpStream::connect(local_addr).await.unwrap();
+    let method = negotiate(&mut stream, &[socks5::SOCKS5_AUTH_METHOD_NONE]).await;
+    assert_eq!(method, socks5::SOCKS5_AUTH_METHOD_NONE);
+}
+
+#[tokio::test]
+async fn socks5_server_hint_password_only() {
+    let _ = env_logger::try_init();
+
+    let local_addr = start_local(8361, true).await;
+
+    let mut stream = TcpStream::connect(local_addr).await.unwrap();
+    let method = negotiate(&mut stream, &[socks5::SOCKS5_AUTH_METHOD_PASSWORD]).await;
+    assert_eq!(method, socks5::SOCKS5_AUTH_METHOD_PASSWORD);
+
+    // Password is not verified, it only carries the hint
+    let status = authenticate(&mut stream, "alice;server:tokyo-1", "any").await;
+    assert_eq!(status, socks5::SOCKS5_PASSWORD_AUTH_SUCCEEDED);
+}
+
+#[tokio::test]
+async fn socks5_server_hint_prefer_none() {
+    let _ = env_logger::try_init();
+
+    let local_addr = start_local(8362, true).await;
+
+    let mut stream = TcpStream::connect(local_addr).await.unwrap();
+    let method = negotiate(
+        &mut stream,
+        &[socks5::SOCKS5_AUTH_METHOD_PASSWORD, socks5::SOCKS5_AUTH_METHOD_NONE],
+    )
+    .await;
+    assert_eq!(method, socks5::SOCKS5_AUTH_METHOD_NONE);
+}
+
+#[tokio::test]
+async fn socks5_server_hint_disabled() {
+    let _ = env_logger::try_init();
+
+    let local_addr = start_local(8363, false).await;
+
+    let mut stream = TcpStream::connect(local_addr).await.unwrap();
+    let method = negotiate(&mut stream, &[socks5::SOCKS5_AUTH_METHOD_PASSWORD]).await;
+    assert_eq!(method, socks5::SOCKS5_AUTH_METHOD_NOT_ACCEPTABLE);
+}
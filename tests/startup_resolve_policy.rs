@@ -0,0 +1,34 @@
+#![cfg(feature = "local")]
+
+use shadowsocks_service::config::{Config, ConfigType, StartupResolvePolicy};
+
+#[test]
+fn startup_resolve_policy_config() {
+    let config = Config::load_from_str(
+        r#"{
+            "local_address": "127.0.0.1",
+            "local_port": 8340,
+            "server": "127.0.0.1",
+            "server_port": 8260,
+            "password": "password",
+            "method": "aes-256-gcm"
+        }"#,
+        ConfigType::Local,
+    )
+    .unwrap();
+    assert_eq!(config.startup_resolve_policy, StartupResolvePolicy::Defer);
+
+    let config = Config::load_from_str(
+        r#"{
+            "local_address": "127.0.0.1",
+            "local_port": 8340,
+            "server": "127.0.0.1",
+            "server_port": 8260,
+            "password": "password",
+            "method": "aes-256-gcm",
+            "startup_resolve_policy": "retry"
+        }"#,
+        ConfigType::Local,
+    );
+    assert!(config.is_err());
+}
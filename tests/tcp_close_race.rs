@@ -0,0 +1,156 @@
+#![cfg(all(feature = "local", feature = "server"))]
+
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{Mutex, Once},
+};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::{self, Duration},
+};
+
+use shadowsocks_service::{
+    config::{Config, ConfigType, ProtocolType},
+    local::socks::client::socks5::Socks5TcpClient,
+    net::utils::is_benign_close_error,
+    run_local,
+    run_server,
+};
+
+/// Keeps all error-level records, the others are discarded after being formatted
+struct ErrorCollector {
+    errors: Mutex<Vec<String>>,
+}
+
+impl Log for ErrorCollector {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let message = format!("{}: {}", record.target(), record.args());
+        if record.level() == Level::Error {
+            self.errors.lock().unwrap().push(message);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static COLLECTOR: ErrorCollector = ErrorCollector {
+    errors: Mutex::new(Vec::new()),
+};
+
+fn init_collector() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        log::set_logger(&COLLECTOR).unwrap();
+        log::set_max_level(LevelFilter::Trace);
+    });
+}
+
+#[test]
+fn tcp_close_race_error_classification() {
+    for &kind in &[
+        io::ErrorKind::ConnectionReset,
+        io::ErrorKind::ConnectionAborted,
+        io::ErrorKind::BrokenPipe,
+        io::ErrorKind::NotConnected,
+    ] {
+        assert!(is_benign_close_error(&io::Error::from(kind)));
+    }
+
+    for &kind in &[
+        io::ErrorKind::ConnectionRefused,
+        io::ErrorKind::TimedOut,
+        io::ErrorKind::PermissionDenied,
+        io::ErrorKind::InvalidData,
+        io::ErrorKind::UnexpectedEof,
+        io::ErrorKind::Other,
+    ] {
+        assert!(!is_benign_close_error(&io::Error::from(kind)));
+    }
+}
+
+#[tokio::test]
+async fn tcp_close_race_no_error_log() {
+    init_collector();
+
+    const CONNECTIONS: usize = 32;
+
+    // Target resets every connection as soon as it has received something
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, ..) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut buf = [0u8; 5];
+                let _ = stream.read(&mut buf).await;
+                stream.set_linger(Some(Duration::from_secs(0))).unwrap();
+            });
+        }
+    });
+
+    let server_config = Config::load_from_str(
+        r#"{
+            "server": "127.0.0.1",
+            "server_port": 8170,
+            "password": "password",
+            "method": "aes-256-gcm"
+        }"#,
+        ConfigType::Server,
+    )
+    .unwrap();
+
+    let mut local_config = Config::load_from_str(
+        r#"{
+            "local_port": 8270,
+            "local_address": "127.0.0.1",
+            "server": "127.0.0.1",
+            "server_port": 8170,
+            "password": "password",
+            "method": "aes-256-gcm"
+        }"#,
+        ConfigType::Local,
+    )
+    .unwrap();
+    local_config.local_protocol = ProtocolType::Socks;
+
+    tokio::spawn(run_server(server_config));
+    tokio::spawn(run_local(local_config));
+
+    time::sleep(Duration::from_secs(1)).await;
+
+    let server_addr: SocketAddr = "127.0.0.1:8170".parse().unwrap();
+    let local_addr: SocketAddr = "127.0.0.1:8270".parse().unwrap();
+
+    let mut tasks = Vec::with_capacity(CONNECTIONS * 2);
+    for _ in 0..CONNECTIONS {
+        // Client closes right after sending, while the target is resetting the connection
+        tasks.push(tokio::spawn(async move {
+            let mut c = Socks5TcpClient::connect(target_addr, local_addr).await.unwrap();
+            let _ = c.write_all(b"hello").await;
+            let _ = c.shutdown().await;
+        }));
+
+        // Client resets the connection before sending its first chunk to the server
+        tasks.push(tokio::spawn(async move {
+            let s = TcpStream::connect(server_addr).await.unwrap();
+            s.set_linger(Some(Duration::from_secs(0))).unwrap();
+        }));
+    }
+
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    time::sleep(Duration::from_secs(1)).await;
+
+    let errors = COLLECTOR.errors.lock().unwrap();
+    assert!(errors.is_empty(), "unexpected error logs: {:?}", *errors);
+}
@@ -0,0 +1,86 @@
+use std::{collections::HashMap, thread, time::Duration};
+
+use shadowsocks_service::{
+    config::{Config, ConfigType, UdpEvictionPolicy},
+    net::udp_eviction::{eviction_policy, select_victim, UdpAssociationUsage},
+};
+
+// Usages of 3 associations:
+//
+// - "old" is created first and is the most frequently used
+// - "hot" is created second and is the most recently used
+// - "new" is created last and is the least frequently used
+fn usages() -> HashMap<&'static str, UdpAssociationUsage> {
+    let tick = || thread::sleep(Duration::from_millis(5));
+
+    let mut old = UdpAssociationUsage::new();
+    tick();
+    let mut hot = UdpAssociationUsage::new();
+    tick();
+    let mut new = UdpAssociationUsage::new();
+    tick();
+
+    for _ in 0..5 {
+        old.touch();
+    }
+    tick();
+    new.touch();
+    tick();
+    hot.touch();
+    hot.touch();
+
+    let mut usages = HashMap::new();
+    usages.insert("old", old);
+    usages.insert("hot", hot);
+    usages.insert("new", new);
+    usages
+}
+
+fn victim(policy: UdpEvictionPolicy, usages: &HashMap<&'static str, UdpAssociationUsage>) -> &'static str {
+    *select_victim(eviction_policy(policy), usages.iter()).unwrap()
+}
+
+#[test]
+fn udp_eviction_lru() {
+    let usages = usages();
+    assert_eq!(victim(UdpEvictionPolicy::Lru, &usages), "old");
+}
+
+#[test]
+fn udp_eviction_lfu() {
+    let usages = usages();
+    assert_eq!(victim(UdpEvictionPolicy::Lfu, &usages), "new");
+}
+
+#[test]
+fn udp_eviction_oldest() {
+    let usages = usages();
+    assert_eq!(victim(UdpEvictionPolicy::Oldest, &usages), "old");
+
+    // Being used doesn't protect an association from being evicted as the oldest one
+    let mut usages = usages;
+    usages.get_mut("old").unwrap().touch();
+    assert_eq!(victim(UdpEvictionPolicy::Oldest, &usages), "old");
+    assert_eq!(victim(UdpEvictionPolicy::Lru, &usages), "new");
+}
+
+#[test]
+fn udp_eviction_empty() {
+    let usages = HashMap::<&'static str, UdpAssociationUsage>::new();
+    assert!(select_victim(eviction_policy(UdpEvictionPolicy::Lru), usages.iter()).is_none());
+}
+
+#[test]
+fn udp_eviction_policy_invalid() {
+    let config = Config::load_from_str(
+        r#"{
+            "server": "127.0.0.1",
+            "server_port": 8160,
+            "password": "password",
+            "method": "aes-256-gcm",
+            "udp_eviction_policy": "random"
+        }"#,
+        ConfigType::Server,
+    );
+    assert!(config.is_err());
+}
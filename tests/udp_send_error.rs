@@ -0,0 +1,193 @@
+#![cfg(feature = "server")]
+
+use std::{io, net::UdpSocket};
+
+use shadowsocks_service::{
+    config::{Config, ConfigType, UdpSendErrorPolicy},
+    net::udp_send_error::{UdpSendErrorAction, UdpSendErrorKind, UdpSendErrorStat},
+};
+
+// Larger than the maximum payload of an IPv4 UDP datagram (65507 bytes)
+const OVERSIZED_PACKET_LEN: usize = 65536;
+
+fn send_oversized_packet() -> io::Error {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let target = socket.local_addr().unwrap();
+
+    let buf = vec![0u8; OVERSIZED_PACKET_LEN];
+    socket.send_to(&buf, target).unwrap_err()
+}
+
+#[test]
+fn udp_send_error_message_too_large() {
+    let err = send_oversized_packet();
+    let kind = UdpSendErrorKind::from_io_error(&err);
+    assert_eq!(kind, UdpSendErrorKind::MessageTooLarge);
+
+    // Oversized packets never tear down associations
+    assert_eq!(
+        kind.action(UdpSendErrorPolicy::DropPacket),
+        UdpSendErrorAction::DropPacket
+    );
+    assert_eq!(
+        kind.action(UdpSendErrorPolicy::DropAssociation),
+        UdpSendErrorAction::DropPacket
+    );
+}
+
+#[test]
+fn udp_send_error_stat() {
+    let stat = UdpSendErrorStat::new();
+
+    let kind = UdpSendErrorKind::from_io_error(&send_oversized_packet());
+    assert_eq!(stat.incr(kind), 1);
+    assert_eq!(stat.incr(kind), 2);
+
+    let kind = UdpSendErrorKind::from_io_error(&io::Error::new(io::ErrorKind::Other, "other"));
+    assert_eq!(kind, UdpSendErrorKind::Other);
+    assert_eq!(stat.incr(kind), 1);
+
+    assert_eq!(stat.count(UdpSendErrorKind::MessageTooLarge), 2);
+    assert_eq!(stat.count(UdpSendErrorKind::Unreachable), 0);
+    assert_eq!(stat.count(UdpSendErrorKind::Other), 1);
+}
+
+#[test]
+fn udp_send_error_policy_config() {
+    let config = Config::load_from_str(
+        r#"{
+            "server": "127.0.0.1",
+            "server_port": 8140,
+            "password": "password",
+            "method": "aes-256-gcm"
+        }"#,
+        ConfigType::Server,
+    )
+    .unwrap();
+    assert_eq!(config.udp_send_error_policy, UdpSendErrorPolicy::DropPacket);
+
+    let config = Config::load_from_str(
+        r#"{
+            "server": "127.0.0.1",
+            "server_port": 8140,
+            "password": "password",
+            "method": "aes-256-gcm",
+            "udp_send_error_policy": "drop-everything"
+        }"#,
+        ConfigType::Server,
+    );
+    assert!(config.is_err());
+}
+
+/// Relay of local, with all targets bypassed, for checking the association kept or dropped after a send error
+#[cfg(all(feature = "local", target_os = "linux"))]
+mod relay {
+    use std::{fs, net::SocketAddr};
+
+    use tokio::{
+        net::UdpSocket,
+        time::{self, Duration},
+    };
+
+    use shadowsocks_service::{
+        acl::AccessControl,
+        config::{Config, ConfigType, Mode, ProtocolType, UdpSendErrorPolicy},
+        local::socks::client::socks5::Socks5UdpClient,
+        run_local,
+        shadowsocks::{
+            config::{ServerAddr, ServerConfig},
+            crypto::v1::CipherKind,
+            relay::socks5::Address,
+        },
+    };
+
+    async fn start_local(local_port: u16, policy: UdpSendErrorPolicy) -> SocketAddr {
+        let local_addr = SocketAddr::from(([127, 0, 0, 1], local_port));
+
+        let acl_path = std::env::temp_dir().join(format!("udp-send-error-{}.acl", local_port));
+        fs::write(&acl_path, "[bypass_all]\n").unwrap();
+
+        let mut config = Config::new(ConfigType::Local);
+        config.local_addr = Some(ServerAddr::from(local_addr));
+        config.local_protocol = ProtocolType::Socks;
+        config.mode = Mode::TcpAndUdp;
+        config.server = vec![ServerConfig::new(
+            SocketAddr::from(([127, 0, 0, 1], 8382)),
+            "password".to_owned(),
+            CipherKind::AES_256_GCM,
+        )];
+        config.acl = Some(AccessControl::load_from_file(&acl_path).unwrap());
+        config.udp_send_error_policy = policy;
+
+        tokio::spawn(run_local(config));
+        time::sleep(Duration::from_secs(1)).await;
+
+        local_addr
+    }
+
+    /// Start a UDP server replying the source address of each packet
+    async fn start_source_echo_server() -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 65536];
+            loop {
+                let (_, src) = socket.recv_from(&mut buf).await.unwrap();
+                let _ = socket.send_to(src.to_string().as_bytes(), src).await;
+            }
+        });
+
+        addr
+    }
+
+    /// Source address of the association's outbound socket, seen by the echo server
+    async fn outbound_addr(client: &Socks5UdpClient, echo_addr: SocketAddr) -> String {
+        client.send_to(0, b"ping", echo_addr).await.unwrap();
+
+        let mut buf = vec![0u8; 65536];
+        let (n, ..) = time::timeout(Duration::from_secs(5), client.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        String::from_utf8(buf[..n].to_vec()).unwrap()
+    }
+
+    /// Returns the source addresses before and after a packet failed to be sent
+    async fn outbound_addrs_around_error(local_port: u16, policy: UdpSendErrorPolicy) -> (String, String) {
+        let local_addr = start_local(local_port, policy).await;
+        let echo_addr = start_source_echo_server().await;
+
+        let mut client = Socks5UdpClient::bind("127.0.0.1:0".parse::<SocketAddr>().unwrap())
+            .await
+            .unwrap();
+        client.associate(&local_addr).await.unwrap();
+
+        let before = outbound_addr(&client, echo_addr).await;
+
+        // Broadcasting without SO_BROADCAST fails with EACCES
+        let broadcast = Address::SocketAddress(SocketAddr::from(([255, 255, 255, 255], 9)));
+        client.send_to(0, b"broadcast", broadcast).await.unwrap();
+        time::sleep(Duration::from_millis(200)).await;
+
+        let after = outbound_addr(&client, echo_addr).await;
+        (before, after)
+    }
+
+    #[tokio::test]
+    async fn udp_send_error_keep_association() {
+        let _ = env_logger::try_init();
+
+        let (before, after) = outbound_addrs_around_error(8380, UdpSendErrorPolicy::DropPacket).await;
+        assert_eq!(before, after);
+    }
+
+    #[tokio::test]
+    async fn udp_send_error_drop_association() {
+        let _ = env_logger::try_init();
+
+        // Association is created again with a new outbound socket
+        let (before, after) = outbound_addrs_around_error(8381, UdpSendErrorPolicy::DropAssociation).await;
+        assert_ne!(before, after);
+    }
+}
@@ -0,0 +1,135 @@
+#![cfg(feature = "server")]
+
+use std::net::SocketAddr;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, UdpSocket},
+    time::{self, Duration},
+};
+
+use shadowsocks_service::{
+    config::{Config, ConfigType},
+    run_server,
+    shadowsocks::{
+        config::ServerType,
+        context::Context,
+        crypto::v1::CipherKind,
+        relay::socks5::Address,
+        ProxyClientStream,
+        ProxySocket,
+        ServerConfig,
+    },
+};
+
+const SERVER_ADDR: &str = "127.0.0.1:8190";
+const PASSWORD: &str = "password";
+const METHOD: CipherKind = CipherKind::AES_256_GCM;
+
+#[test]
+fn udp_source_verify_invalid() {
+    // Sources are validated by TCP connections
+    let config = Config::load_from_str(
+        r#"{
+            "server": "127.0.0.1",
+            "server_port": 8190,
+            "password": "password",
+            "method": "aes-256-gcm",
+            "mode": "udp_only",
+            "udp_source_verify": true
+        }"#,
+        ConfigType::Server,
+    );
+    assert!(config.is_err());
+
+    // TCP connections come from the plugin
+    let config = Config::load_from_str(
+        r#"{
+            "server": "127.0.0.1",
+            "server_port": 8190,
+            "password": "password",
+            "method": "aes-256-gcm",
+            "mode": "tcp_and_udp",
+            "plugin": "obfs-server",
+            "plugin_opts": "obfs=http",
+            "udp_source_verify": true
+        }"#,
+        ConfigType::Server,
+    )
+    .unwrap();
+    assert!(config.check_integrity().is_err());
+}
+
+async fn udp_echo(socket: &ProxySocket, target_addr: &Address) -> bool {
+    socket.send(target_addr, b"hello").await.unwrap();
+
+    let mut buf = [0u8; 65536];
+    match time::timeout(Duration::from_secs(1), socket.recv(&mut buf)).await {
+        Ok(Ok((n, ..))) => &buf[..n] == b"hello",
+        _ => false,
+    }
+}
+
+#[tokio::test]
+async fn udp_source_verify_relay() {
+    let _ = env_logger::try_init();
+
+    let udp_target = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let udp_target_addr = Address::from(udp_target.local_addr().unwrap());
+    tokio::spawn(async move {
+        let mut buf = [0u8; 65536];
+        loop {
+            let (n, peer_addr) = udp_target.recv_from(&mut buf).await.unwrap();
+            let _ = udp_target.send_to(&buf[..n], peer_addr).await;
+        }
+    });
+
+    let tcp_target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let tcp_target_addr = tcp_target.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, ..) = tcp_target.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut buf = [0u8; 5];
+                if stream.read_exact(&mut buf).await.is_ok() {
+                    let _ = stream.write_all(&buf).await;
+                }
+            });
+        }
+    });
+
+    let config = Config::load_from_str(
+        r#"{
+            "server": "127.0.0.1",
+            "server_port": 8190,
+            "password": "password",
+            "method": "aes-256-gcm",
+            "mode": "tcp_and_udp",
+            "udp_source_verify": true
+        }"#,
+        ConfigType::Server,
+    )
+    .unwrap();
+    tokio::spawn(run_server(config));
+
+    time::sleep(Duration::from_secs(1)).await;
+
+    let svr_cfg = ServerConfig::new(SERVER_ADDR.parse::<SocketAddr>().unwrap(), PASSWORD.to_owned(), METHOD);
+    let context = Context::new_shared(ServerType::Local);
+
+    // Source hasn't made any TCP connections
+    let socket = ProxySocket::connect(context.clone(), &svr_cfg).await.unwrap();
+    assert!(!udp_echo(&socket, &udp_target_addr).await);
+
+    // Source is validated after its TCP connection passed the handshake
+    let mut stream = ProxyClientStream::connect(context.clone(), &svr_cfg, tcp_target_addr)
+        .await
+        .unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    stream.flush().await.unwrap();
+    let mut buf = [0u8; 5];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+
+    assert!(udp_echo(&socket, &udp_target_addr).await);
+}
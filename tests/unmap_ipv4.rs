@@ -0,0 +1,136 @@
+#![cfg(all(feature = "local", feature = "server"))]
+
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    time::{self, Duration},
+};
+
+use shadowsocks_service::{
+    config::{Config, ConfigType, ProtocolType},
+    local::socks::client::socks5::Socks5TcpClient,
+    run_local,
+    run_server,
+    shadowsocks::relay::socks5::Address,
+};
+
+fn mapped_addr(port: u16) -> Address {
+    let ip = Ipv4Addr::new(127, 0, 0, 1).to_ipv6_mapped();
+    Address::SocketAddress(SocketAddr::V6(SocketAddrV6::new(ip, port, 0, 0)))
+}
+
+#[test]
+fn unmap_ipv4_address() {
+    // IPv4-mapped
+    assert_eq!(
+        mapped_addr(80).unmap_ipv4(),
+        Address::SocketAddress("127.0.0.1:80".parse().unwrap())
+    );
+
+    // IPv4-compatible, deprecated and kept as IPv6
+    let compat = Ipv4Addr::new(1, 2, 3, 4).to_ipv6_compatible();
+    let compat = Address::SocketAddress(SocketAddr::V6(SocketAddrV6::new(compat, 80, 0, 0)));
+    assert_eq!(compat.clone().unmap_ipv4(), compat);
+
+    // Native addresses
+    let native_v4 = Address::SocketAddress("1.2.3.4:80".parse().unwrap());
+    assert_eq!(native_v4.clone().unmap_ipv4(), native_v4);
+
+    let native_v6 = Address::SocketAddress(SocketAddr::new(Ipv6Addr::LOCALHOST.into(), 80));
+    assert_eq!(native_v6.clone().unmap_ipv4(), native_v6);
+
+    let domain = Address::DomainNameAddress("www.example.com".to_owned(), 80);
+    assert_eq!(domain.clone().unmap_ipv4(), domain);
+}
+
+#[test]
+fn unmap_ipv4_default() {
+    let config = Config::load_from_str(
+        r#"{
+            "server": "127.0.0.1",
+            "server_port": 8150,
+            "password": "password",
+            "method": "aes-256-gcm"
+        }"#,
+        ConfigType::Server,
+    )
+    .unwrap();
+    assert!(config.unmap_ipv4);
+}
+
+async fn relay_to_mapped_target(server_port: u16, local_port: u16, ipv6_first: bool) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let (mut stream, ..) = listener.accept().await.unwrap();
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        stream.write_all(&buf).await.unwrap();
+    });
+
+    let server_config = Config::load_from_str(
+        &format!(
+            r#"{{
+                "server": "127.0.0.1",
+                "server_port": {},
+                "password": "password",
+                "method": "aes-256-gcm",
+                "ipv6_first": {}
+            }}"#,
+            server_port, ipv6_first
+        ),
+        ConfigType::Server,
+    )
+    .unwrap();
+
+    let mut local_config = Config::load_from_str(
+        &format!(
+            r#"{{
+                "local_port": {},
+                "local_address": "127.0.0.1",
+                "server": "127.0.0.1",
+                "server_port": {},
+                "password": "password",
+                "method": "aes-256-gcm",
+                "ipv6_first": {}
+            }}"#,
+            local_port, server_port, ipv6_first
+        ),
+        ConfigType::Local,
+    )
+    .unwrap();
+    local_config.local_protocol = ProtocolType::Socks;
+
+    tokio::spawn(run_server(server_config));
+    tokio::spawn(run_local(local_config));
+
+    time::sleep(Duration::from_secs(1)).await;
+
+    let local_addr: SocketAddr = format!("127.0.0.1:{}", local_port).parse().unwrap();
+    let mut c = Socks5TcpClient::connect(mapped_addr(target_port), local_addr)
+        .await
+        .unwrap();
+
+    c.write_all(b"hello").await.unwrap();
+    c.flush().await.unwrap();
+
+    let mut buf = [0u8; 5];
+    c.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+}
+
+#[tokio::test]
+async fn unmap_ipv4_relay_ipv4_first() {
+    let _ = env_logger::try_init();
+
+    relay_to_mapped_target(8150, 8250, false).await;
+}
+
+#[tokio::test]
+async fn unmap_ipv4_relay_ipv6_first() {
+    let _ = env_logger::try_init();
+
+    relay_to_mapped_target(8151, 8251, true).await;
+}